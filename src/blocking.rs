@@ -0,0 +1,197 @@
+//! a synchronous facade over [SnapcastConnection](crate::SnapcastConnection), enabled by the `blocking` feature
+//!
+//! this is a convenience layer for quick scripts and codebases without an async runtime. every call blocks the
+//! current thread on a private current-thread tokio runtime, so the connection only makes progress while one of its
+//! methods is running. it is not meant for high-throughput use, use the async
+//! [SnapcastConnection](crate::SnapcastConnection) there instead.
+//!
+//! the methods must not be called from within an async runtime, tokio panics when a runtime is blocked on from
+//! inside another one.
+//!
+//! # example
+//! ```no_run
+//! use snapcast_control::blocking::SnapcastConnection;
+//!
+//! let mut client = SnapcastConnection::connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+//!   .expect("could not connect");
+//! client.server_get_status().expect("could not send request");
+//!
+//! while let Some(message) = client.recv() {
+//!   println!("{:?}", message);
+//! }
+//! ```
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+  protocol::{client, stream},
+  state::WrappedState,
+  ClientError, SnapcastConnectionBuilder, ValidMessage,
+};
+
+/// A blocking connection to a Snapcast server, see the [module](self) docs
+pub struct SnapcastConnection {
+  /// The state of the Snapcast server, automatically kept up to date by the client
+  pub state: WrappedState,
+
+  runtime: Runtime,
+  inner: crate::SnapcastConnection,
+}
+
+impl SnapcastConnection {
+  /// open a new connection to a Snapcast server with the default options
+  ///
+  /// # args
+  /// `address`: [std::net::SocketAddr] - the address of the Snapcast server
+  ///
+  /// # returns
+  /// a new [SnapcastConnection], or a [ClientError] if the connection could not be established
+  pub fn connect(address: std::net::SocketAddr) -> Result<Self, ClientError> {
+    Self::connect_with(crate::SnapcastConnection::builder(), address)
+  }
+
+  /// open a new connection to a Snapcast server with the options of a [SnapcastConnectionBuilder]
+  ///
+  /// # args
+  /// `builder`: [SnapcastConnectionBuilder] - the configured options
+  /// `address`: [std::net::SocketAddr] - the address of the Snapcast server
+  ///
+  /// # returns
+  /// a new [SnapcastConnection], or a [ClientError] if the runtime could not be created or the connection could not
+  /// be established
+  pub fn connect_with(builder: SnapcastConnectionBuilder, address: std::net::SocketAddr) -> Result<Self, ClientError> {
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    let inner = runtime.block_on(builder.connect(address))?;
+
+    Ok(Self {
+      state: inner.state.clone(),
+      runtime,
+      inner,
+    })
+  }
+
+  /// receive the next message from the server, blocking until one arrives
+  ///
+  /// see [SnapcastConnection::recv](crate::SnapcastConnection::recv)
+  pub fn recv(&mut self) -> Option<Result<ValidMessage, ClientError>> {
+    self.runtime.block_on(self.inner.recv())
+  }
+
+  /// request the current status of the server
+  ///
+  /// see [SnapcastConnection::server_get_status](crate::SnapcastConnection::server_get_status)
+  pub fn server_get_status(&mut self) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.server_get_status())
+  }
+
+  /// set the volume and mute status of a client
+  ///
+  /// see [SnapcastConnection::client_set_volume](crate::SnapcastConnection::client_set_volume)
+  pub fn client_set_volume(&mut self, id: String, volume: client::ClientVolume) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.client_set_volume(id, volume))
+  }
+
+  /// set the latency of a client
+  ///
+  /// see [SnapcastConnection::client_set_latency](crate::SnapcastConnection::client_set_latency)
+  pub fn client_set_latency(&mut self, id: String, latency: usize) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.client_set_latency(id, latency))
+  }
+
+  /// set the name of a client
+  ///
+  /// see [SnapcastConnection::client_set_name](crate::SnapcastConnection::client_set_name)
+  pub fn client_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.client_set_name(id, name))
+  }
+
+  /// mute or unmute a group
+  ///
+  /// see [SnapcastConnection::group_set_mute](crate::SnapcastConnection::group_set_mute)
+  pub fn group_set_mute(&mut self, id: String, mute: bool) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.group_set_mute(id, mute))
+  }
+
+  /// set the stream of a group
+  ///
+  /// see [SnapcastConnection::group_set_stream](crate::SnapcastConnection::group_set_stream)
+  pub fn group_set_stream(&mut self, id: String, stream_id: String) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.group_set_stream(id, stream_id))
+  }
+
+  /// set the clients of a group
+  ///
+  /// see [SnapcastConnection::group_set_clients](crate::SnapcastConnection::group_set_clients)
+  pub fn group_set_clients(&mut self, id: String, clients: Vec<String>) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.group_set_clients(id, clients))
+  }
+
+  /// set the name of a group
+  ///
+  /// see [SnapcastConnection::group_set_name](crate::SnapcastConnection::group_set_name)
+  pub fn group_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.group_set_name(id, name))
+  }
+
+  /// mute or unmute every group
+  ///
+  /// see [SnapcastConnection::set_all_groups_muted](crate::SnapcastConnection::set_all_groups_muted)
+  pub fn set_all_groups_muted(&mut self, mute: bool) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.set_all_groups_muted(mute))
+  }
+
+  /// set the property of a stream
+  ///
+  /// see [SnapcastConnection::stream_set_property](crate::SnapcastConnection::stream_set_property)
+  pub fn stream_set_property(
+    &mut self,
+    id: String,
+    properties: stream::SetPropertyProperties,
+  ) -> Result<(), ClientError> {
+    self.runtime.block_on(self.inner.stream_set_property(id, properties))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{protocol::Request, SnapcastResult};
+  use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+  };
+
+  #[test]
+  fn blocking_round_trip() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = std::thread::spawn(move || {
+      let (socket, _) = listener.accept().unwrap();
+      let mut writer = socket.try_clone().unwrap();
+      let mut line = String::new();
+      BufReader::new(socket).read_line(&mut line).unwrap();
+
+      let request: Request = serde_json::from_str(&line).unwrap();
+      let response = format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"volume":{{"muted":true,"percent":30}}}}}}"#,
+        request.id
+      );
+      writer.write_all(format!("{}\n", response).as_bytes()).unwrap();
+    });
+
+    let builder = crate::SnapcastConnection::builder().auto_refresh_on_connect(false);
+    let mut client = SnapcastConnection::connect_with(builder, address).unwrap();
+    client
+      .client_set_volume("client".to_string(), client::ClientVolume::muted(30))
+      .unwrap();
+
+    let message = client.recv().unwrap().unwrap();
+    let ValidMessage::Result { result, .. } = message else {
+      panic!("expected a result, got {:?}", message);
+    };
+    assert!(matches!(*result, SnapcastResult::ClientSetVolume(..)));
+
+    server.join().unwrap();
+  }
+}
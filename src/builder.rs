@@ -0,0 +1,527 @@
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+  clock::ClockHook,
+  metrics::MetricsHook,
+  server::{GetRpcVersionResult, Server},
+  state::WrappedState,
+  tape::WireTap,
+  transport::{RetryPolicy, StatusCallback, StatusHook, TcpTarget, TransportOptions},
+  ClientError, Clock, ConnectionStatus, Direction, Metrics, RetryDecision, SnapcastConnection,
+};
+
+/// generates the id of each request, random v4 [Uuid]s by default
+#[derive(Clone)]
+pub(crate) struct IdGenerator(Arc<dyn Fn() -> Uuid + Send + Sync>);
+
+impl IdGenerator {
+  pub(crate) fn generate(&self) -> Uuid {
+    (self.0)()
+  }
+}
+
+impl Default for IdGenerator {
+  fn default() -> Self {
+    Self(Arc::new(Uuid::new_v4))
+  }
+}
+
+impl fmt::Debug for IdGenerator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IdGenerator")
+  }
+}
+
+/// Builder for a [SnapcastConnection]
+///
+/// created with [SnapcastConnection::builder], finished with [SnapcastConnectionBuilder::connect]
+#[derive(Debug)]
+pub struct SnapcastConnectionBuilder {
+  pub(crate) record_to: Option<PathBuf>,
+  pub(crate) on_wire: Option<WireTap>,
+  pub(crate) track_state: bool,
+  pub(crate) ignore_art_data: bool,
+  pub(crate) redact_art_in_logs: bool,
+  pub(crate) min_supported_version: Option<GetRpcVersionResult>,
+  pub(crate) reject_unsupported_version: bool,
+  pub(crate) reconnect: bool,
+  pub(crate) retry_policy: RetryPolicy,
+  pub(crate) bind_local: Option<std::net::SocketAddr>,
+  pub(crate) connect_timeout: Option<Duration>,
+  #[cfg(feature = "socks")]
+  pub(crate) proxy: Option<crate::ProxyConfig>,
+  pub(crate) validate_seek: bool,
+  pub(crate) id_generator: IdGenerator,
+  pub(crate) resend_on_reconnect: Option<usize>,
+  pub(crate) auto_refresh_on_connect: bool,
+  pub(crate) metrics: MetricsHook,
+  pub(crate) clock: ClockHook,
+  pub(crate) dry_run: bool,
+  pub(crate) ping_timeout: Option<Duration>,
+  pub(crate) prefetch_stream_properties: Option<Duration>,
+  pub(crate) auto_flush: bool,
+  pub(crate) optimistic_updates: bool,
+  pub(crate) span: Option<tracing::Span>,
+  pub(crate) initial_state: Option<Server>,
+  pub(crate) on_status: StatusCallback,
+}
+
+impl Default for SnapcastConnectionBuilder {
+  fn default() -> Self {
+    Self {
+      record_to: None,
+      on_wire: None,
+      track_state: true,
+      ignore_art_data: false,
+      redact_art_in_logs: true,
+      min_supported_version: None,
+      reject_unsupported_version: false,
+      reconnect: true,
+      retry_policy: RetryPolicy::default(),
+      bind_local: None,
+      connect_timeout: None,
+      #[cfg(feature = "socks")]
+      proxy: None,
+      validate_seek: false,
+      id_generator: IdGenerator::default(),
+      resend_on_reconnect: None,
+      auto_refresh_on_connect: true,
+      metrics: MetricsHook::default(),
+      clock: ClockHook::default(),
+      dry_run: false,
+      ping_timeout: None,
+      prefetch_stream_properties: None,
+      auto_flush: true,
+      optimistic_updates: false,
+      span: None,
+      initial_state: None,
+      on_status: StatusCallback::default(),
+    }
+  }
+}
+
+impl SnapcastConnectionBuilder {
+  /// record every raw inbound line and outbound request to a tape file, with timestamps
+  ///
+  /// the tape can be fed back through the decoder with [SnapcastConnection::replay]
+  ///
+  /// # args
+  /// `path`: the path of the tape file, which is created or truncated on connect
+  pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+    self.record_to = Some(path.into());
+    self
+  }
+
+  /// register a callback invoked with the exact bytes of every line sent to or received from the server, e.g. to
+  /// debug the protocol against an unusual server without enabling trace logging
+  ///
+  /// outgoing requests are passed after framing, including their trailing newline. incoming lines are passed as they
+  /// arrived, including their line ending and empty lines, before they are deserialized. the callback runs while
+  /// encoding and decoding, so it should return quickly.
+  ///
+  /// # args
+  /// `callback`: the function to call with the [Direction] and the bytes of each line
+  ///
+  /// # example
+  /// ```no_run
+  /// let mut client = SnapcastConnection::builder()
+  ///   .on_wire(|direction, bytes| eprintln!("{:?} {}", direction, String::from_utf8_lossy(bytes)))
+  ///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+  ///   .await
+  ///   .expect("could not connect");
+  /// ```
+  pub fn on_wire(mut self, callback: impl Fn(Direction, &[u8]) + Send + Sync + 'static) -> Self {
+    self.on_wire = Some(WireTap::new(callback));
+    self
+  }
+
+  /// enable or disable automatic state tracking, enabled by default
+  ///
+  /// when disabled, [SnapcastConnection::recv] still deserializes and returns every message, but
+  /// [SnapcastConnection::state] is never updated and will stay empty. this saves the memory and cpu
+  /// of mirroring the server for consumers that only forward messages.
+  ///
+  /// # args
+  /// `track_state`: whether to keep [SnapcastConnection::state] up to date
+  pub fn track_state(mut self, track_state: bool) -> Self {
+    self.track_state = track_state;
+    self
+  }
+
+  /// drop the base64 `data` of every [ArtData](crate::stream::ArtData) while deserializing, disabled by default
+  ///
+  /// the `extension` is kept and `data` is left empty, which avoids carrying kilobytes of cover art through
+  /// every `Server.OnUpdate` and `Stream.OnProperties` for consumers that do not display it
+  ///
+  /// # args
+  /// `ignore_art_data`: whether to drop art data
+  pub fn ignore_art_data(mut self, ignore_art_data: bool) -> Self {
+    self.ignore_art_data = ignore_art_data;
+    self
+  }
+
+  /// replace the base64 `data` of every `artData` with its length in trace logs, enabled by default
+  ///
+  /// only the logged json is affected, deserialized messages keep the full art data
+  ///
+  /// # args
+  /// `redact`: whether to redact art data in logs
+  pub fn redact_art_in_logs(mut self, redact: bool) -> Self {
+    self.redact_art_in_logs = redact;
+    self
+  }
+
+  /// check the rpc version of the server on connect, warning if it is older than `version`
+  ///
+  /// [TESTED_RPC_VERSION](crate::server::TESTED_RPC_VERSION) is the version this crate was tested against. \
+  /// the check sends a `Server.GetRPCVersion` request and waits for its result before returning the connection.
+  ///
+  /// # args
+  /// `version`: the minimum supported rpc version
+  pub fn min_supported_version(mut self, version: GetRpcVersionResult) -> Self {
+    self.min_supported_version = Some(version);
+    self
+  }
+
+  /// fail to connect with [ClientError::UnsupportedVersion] instead of warning when the server is older than
+  /// the version set with [SnapcastConnectionBuilder::min_supported_version], disabled by default
+  ///
+  /// # args
+  /// `reject`: whether to reject unsupported servers
+  pub fn reject_unsupported_version(mut self, reject: bool) -> Self {
+    self.reject_unsupported_version = reject;
+    self
+  }
+
+  /// enable or disable automatic reconnection, enabled by default
+  ///
+  /// when disabled, a plain tcp stream is used: once the server closes the socket,
+  /// [ConnectionStatus::Disconnected] fires once and [SnapcastConnection::recv] returns [None]. \
+  /// when enabled, [ConnectionStatus::Disconnected] fires and [SnapcastConnection::recv] keeps waiting until the
+  /// connection is back, with an exponential backoff between attempts.
+  ///
+  /// # args
+  /// `reconnect`: whether to reconnect when the connection is lost
+  pub fn reconnect(mut self, reconnect: bool) -> Self {
+    self.reconnect = reconnect;
+    self
+  }
+
+  /// decide before every reconnect attempt whether to make it, e.g. to stop reconnecting once the user logged out
+  ///
+  /// the attempts are counted from 1 after each disconnect. [RetryDecision::Continue] waits for the default
+  /// exponential backoff, [RetryDecision::Backoff] for the given delay, and [RetryDecision::Stop] gives up:
+  /// [ConnectionStatus::ReconnectFailed] fires and [SnapcastConnection::recv] returns the error of the lost connection,
+  /// then [None]. has no effect if [SnapcastConnectionBuilder::reconnect] is disabled.
+  ///
+  /// # args
+  /// `callback`: the function to call with the number of the upcoming attempt
+  ///
+  /// # example
+  /// ```no_run
+  /// let logged_in = Arc::new(AtomicBool::new(true));
+  /// let still_logged_in = logged_in.clone();
+  /// let mut client = SnapcastConnection::builder()
+  ///   .on_reconnect_attempt(move |attempt| match attempt {
+  ///     _ if !still_logged_in.load(Ordering::Relaxed) => RetryDecision::Stop,
+  ///     1..=3 => RetryDecision::Backoff(Duration::from_secs(1)),
+  ///     _ => RetryDecision::Continue,
+  ///   })
+  ///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+  ///   .await
+  ///   .expect("could not connect");
+  /// ```
+  pub fn on_reconnect_attempt(mut self, callback: impl Fn(usize) -> RetryDecision + Send + Sync + 'static) -> Self {
+    self.retry_policy = RetryPolicy::new(callback);
+    self
+  }
+
+  /// bind the local end of the connection to `address` before connecting, including on every reconnect
+  ///
+  /// use this to make the connection originate from a specific interface on multi-homed machines. \
+  /// a port of 0 lets the os pick the local port.
+  ///
+  /// # args
+  /// `address`: the local address to bind to, of the same ip version as the server address
+  pub fn bind_local(mut self, address: std::net::SocketAddr) -> Self {
+    self.bind_local = Some(address);
+    self
+  }
+
+  /// give up on establishing the tcp connection after `timeout`, applied to the initial connect and every
+  /// reconnect attempt
+  ///
+  /// an initial connect that times out fails with [ClientError::Timeout]. without a timeout, connecting to an
+  /// unreachable host blocks for as long as the os allows.
+  ///
+  /// # args
+  /// `timeout`: how long a single connection attempt may take
+  pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+    self.connect_timeout = Some(timeout);
+    self
+  }
+
+  /// tunnel the connection, and every reconnect, through a socks5 proxy, enabled by the `socks` feature
+  ///
+  /// the proxy connects to the address passed to [SnapcastConnectionBuilder::connect], everything after the tunnel
+  /// is established behaves as with a direct connection. [SnapcastConnection::peer_addr] reports the address
+  /// of the proxy.
+  ///
+  /// # args
+  /// `proxy`: the [ProxyConfig](crate::ProxyConfig) of the proxy to connect through
+  ///
+  /// # example
+  /// ```no_run
+  /// let proxy = ProxyConfig::socks5("10.0.0.1:1080".parse().expect("could not parse proxy address"))
+  ///   .with_credentials("user", "password");
+  /// let mut client = SnapcastConnection::builder()
+  ///   .proxy(proxy)
+  ///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+  ///   .await
+  ///   .expect("could not connect");
+  /// ```
+  #[cfg(feature = "socks")]
+  pub fn proxy(mut self, proxy: crate::ProxyConfig) -> Self {
+    self.proxy = Some(proxy);
+    self
+  }
+
+  /// check `Seek` and `SetPosition` commands against the position and duration of the stream in the state before
+  /// sending them, disabled by default
+  ///
+  /// the current position is extrapolated from the last `Stream.OnProperties`, see
+  /// [State::stream_progress](crate::State::stream_progress), as snapserver does not report it while a track
+  /// plays. a target that is negative or past the known duration is rejected with [ClientError::InvalidParams].
+  /// commands for streams without a known duration are always sent.
+  ///
+  /// # args
+  /// `validate`: whether to validate seek targets
+  pub fn validate_seek(mut self, validate: bool) -> Self {
+    self.validate_seek = validate;
+    self
+  }
+
+  /// generate the id of every request with `generator` instead of a random v4 [Uuid]
+  ///
+  /// ids must be unique among the requests awaiting a result, or results will be matched to the wrong request
+  ///
+  /// # args
+  /// `generator`: the function to call for each new request id
+  pub fn id_generator(mut self, generator: impl Fn() -> Uuid + Send + Sync + 'static) -> Self {
+    self.id_generator = IdGenerator(Arc::new(generator));
+    self
+  }
+
+  /// re-send set-commands that were not answered before the connection dropped once it is reestablished,
+  /// disabled by default
+  ///
+  /// only `Set*` commands are kept, as they are idempotent: sending one twice leaves the server in the same state.
+  /// commands written while disconnected are already held back until the reconnect, so only those that were on the
+  /// old socket without a result are re-sent, each with a new id. the oldest command is dropped with a warning once
+  /// more than `limit` are waiting for a result. has no effect if [SnapcastConnectionBuilder::reconnect] is disabled.
+  ///
+  /// # args
+  /// `limit`: the maximum number of unanswered commands to keep
+  pub fn resend_on_reconnect(mut self, limit: usize) -> Self {
+    self.resend_on_reconnect = Some(limit);
+    self
+  }
+
+  /// send a `Server.GetStatus` request whenever the connection is established, enabled by default
+  ///
+  /// the request is sent from the next call to [SnapcastConnection::recv] after [ConnectionStatus::Connected] fired,
+  /// so [SnapcastConnection::state] is repopulated after every reconnect without any user code. it is skipped if a
+  /// `Server.GetStatus` was already sent on the current connection, so calling
+  /// [SnapcastConnection::server_get_status] right after connecting does not request the status twice.
+  ///
+  /// # args
+  /// `refresh`: whether to request the server status on connect
+  pub fn auto_refresh_on_connect(mut self, refresh: bool) -> Self {
+    self.auto_refresh_on_connect = refresh;
+    self
+  }
+
+  /// load the server status and the properties of every stream before [connect](SnapcastConnectionBuilder::connect)
+  /// returns, disabled by default
+  ///
+  /// without it, [SnapcastConnection::state] stays empty until the result of the automatic `Server.GetStatus` is
+  /// received by [SnapcastConnection::recv], so now-playing metadata is blank at first. the server has no request
+  /// for the properties of a single stream, so for every stream it reports without properties, connecting waits for
+  /// its `Stream.OnProperties` notification. streams that still have no properties after `timeout` are left to later
+  /// notifications. messages received while waiting are buffered for [SnapcastConnection::recv].
+  ///
+  /// # args
+  /// `timeout`: how long to wait for the properties of the streams that have none
+  pub fn prefetch_stream_properties(mut self, timeout: Duration) -> Self {
+    self.prefetch_stream_properties = Some(timeout);
+    self
+  }
+
+  /// flush every command sent with [SnapcastConnection::send] or one of its wrappers to the socket right away,
+  /// enabled by default
+  ///
+  /// when disabled, commands are only queued in the write buffer until [SnapcastConnection::flush] is called, so a
+  /// burst of commands is written with a single syscall. the buffer is also flushed by
+  /// [SnapcastConnection::send_batch], [SnapcastConnection::send_and_wait] and its wrappers, and once it grows too
+  /// large.
+  ///
+  /// # args
+  /// `auto_flush`: whether to flush after every command
+  pub fn auto_flush(mut self, auto_flush: bool) -> Self {
+    self.auto_flush = auto_flush;
+    self
+  }
+
+  /// apply renames to [SnapcastConnection::state] as soon as they are sent, disabled by default
+  ///
+  /// [SnapcastConnection::client_set_name] and [SnapcastConnection::group_set_name] update the name in the state
+  /// right away instead of when the result arrives, so a ui does not show the old name in between. the previous name
+  /// is restored if the server responds with an error. has no effect if [SnapcastConnectionBuilder::track_state] is
+  /// disabled.
+  ///
+  /// # args
+  /// `optimistic`: whether to apply renames before the server confirmed them
+  pub fn optimistic_updates(mut self, optimistic: bool) -> Self {
+    self.optimistic_updates = optimistic;
+    self
+  }
+
+  /// record every event of the connection inside `span`, instead of the default `snapcast` span with the address
+  /// of the server
+  ///
+  /// each request additionally gets a `request` span with its `id` and `method` below it, entered while the request
+  /// is encoded and when its result is received, so a command can be followed through a structured log. tracing
+  /// targets are fixed when the crate is compiled, so give the span a distinct name or fields to filter on instead.
+  ///
+  /// # args
+  /// `span`: the parent span of all events of the connection
+  ///
+  /// # example
+  /// ```no_run
+  /// let mut client = SnapcastConnection::builder()
+  ///   .span(tracing::info_span!("kitchen_speakers"))
+  ///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+  ///   .await
+  ///   .expect("could not connect");
+  /// ```
+  pub fn span(mut self, span: tracing::Span) -> Self {
+    self.span = Some(span);
+    self
+  }
+
+  /// log commands that would change the server instead of sending them, disabled by default
+  ///
+  /// held back commands are serialized exactly as they would be sent, logged at info level, and collected for
+  /// [SnapcastConnection::take_dry_run_requests]. they are never written to the socket and never wait for a result,
+  /// so [SnapcastConnection::send_and_wait] fails with [ClientError::DryRun]. read-only `*.GetStatus` and
+  /// `Server.GetRPCVersion` commands are still sent, so the state stays up to date.
+  ///
+  /// # args
+  /// `dry_run`: whether to hold back commands that would change the server
+  pub fn dry_run(mut self, dry_run: bool) -> Self {
+    self.dry_run = dry_run;
+    self
+  }
+
+  /// give up on [SnapcastConnection::ping] after `timeout`
+  ///
+  /// a ping without a reply in time fails with [ClientError::Timeout]. without a timeout, a ping waits until the
+  /// server replies or the connection closes.
+  ///
+  /// # args
+  /// `timeout`: how long to wait for the reply to a ping
+  pub fn ping_timeout(mut self, timeout: Duration) -> Self {
+    self.ping_timeout = Some(timeout);
+    self
+  }
+
+  /// report message, error, reconnect, and byte counts to `metrics`
+  ///
+  /// # args
+  /// `metrics`: the [Metrics] implementation to call
+  pub fn metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+    self.metrics = MetricsHook::new(metrics);
+    self
+  }
+
+  /// seed the state with a cached server status, so it can be shown before the real status arrives
+  ///
+  /// the seeded entries are marked [unconfirmed](crate::State::is_unconfirmed) until an update from the server touches
+  /// them, and [State::is_ready](crate::State::is_ready) stays false until the first full server status, which
+  /// replaces the seed and removes entries the server no longer has
+  ///
+  /// # args
+  /// `server`: a [Server] cached earlier, e.g. from [State::to_server](crate::State::to_server)
+  pub fn with_initial_state(mut self, server: Server) -> Self {
+    self.initial_state = Some(server);
+    self
+  }
+
+  /// take every timestamp of the connection from `clock` instead of the real time, e.g. to test timeouts without
+  /// waiting
+  ///
+  /// # args
+  /// `clock`: the [Clock] implementation to call
+  pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+    self.clock = ClockHook::new(clock);
+    self
+  }
+
+  /// register a callback invoked whenever the [ConnectionStatus] of the connection changes
+  ///
+  /// # args
+  /// `callback`: the function to call with each new status
+  pub fn on_status(mut self, callback: impl Fn(ConnectionStatus) + Send + Sync + 'static) -> Self {
+    self.on_status = StatusCallback::new(callback);
+    self
+  }
+
+  pub(crate) fn transport_options(&self, address: std::net::SocketAddr, state: &WrappedState) -> TransportOptions {
+    TransportOptions {
+      target: TcpTarget {
+        address,
+        bind_local: self.bind_local,
+        connect_timeout: self.connect_timeout,
+        #[cfg(feature = "socks")]
+        proxy: self.proxy.clone(),
+        peer_addr: Arc::default(),
+      },
+      reconnect: self.reconnect,
+      retry: self.retry_policy.clone(),
+      on_status: StatusHook::new(
+        self.on_status.clone(),
+        state.ready_flag(),
+        self.metrics.clone(),
+        self.clock.clone(),
+      ),
+    }
+  }
+
+  /// open a new connection to a Snapcast server with the configured options
+  ///
+  /// # args
+  /// `address`: [std::net::SocketAddr] - the address of the Snapcast server
+  ///
+  /// # returns
+  /// a new [SnapcastConnection], or a [ClientError] if the connection could not be established
+  pub async fn connect(self, address: std::net::SocketAddr) -> Result<SnapcastConnection, ClientError> {
+    SnapcastConnection::connect_with(self, address).await
+  }
+
+  /// take over a tcp connection that was already established with the configured options, e.g. after a custom
+  /// handshake
+  ///
+  /// the stream cannot be re-established, so [reconnect](SnapcastConnectionBuilder::reconnect) is disabled, and the
+  /// options for opening a connection ([bind_local](SnapcastConnectionBuilder::bind_local),
+  /// [connect_timeout](SnapcastConnectionBuilder::connect_timeout), and the proxy) are ignored
+  ///
+  /// # args
+  /// `stream`: [tokio::net::TcpStream] - a stream connected to the Snapcast server
+  ///
+  /// # returns
+  /// a new [SnapcastConnection], or a [ClientError] if the stream is not connected
+  pub async fn connect_stream(self, stream: tokio::net::TcpStream) -> Result<SnapcastConnection, ClientError> {
+    SnapcastConnection::connect_stream(self, stream).await
+  }
+}
@@ -0,0 +1,123 @@
+//! the source of time for a connection
+//!
+//! every timestamp the connection takes goes through a [Clock], so time-based behavior can be tested without waiting
+//! on the wall clock
+
+use std::{
+  fmt,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+/// tells a [SnapcastConnection](crate::SnapcastConnection) what time it is
+///
+/// used for [SnapcastConnection::last_message_at](crate::SnapcastConnection::last_message_at),
+/// [SnapcastConnection::connected_since](crate::SnapcastConnection::connected_since), the downtime of
+/// [ConnectionStatus::Reconnected](crate::ConnectionStatus::Reconnected), the latency measured by
+/// [SnapcastConnection::ping](crate::SnapcastConnection::ping), and the window in which notifications are recognized
+/// as echoes of sent commands. the [State](crate::State) of the connection stamps received stream properties with
+/// it, so [State::stream_progress](crate::State::stream_progress) and the seek validation of
+/// [SnapcastConnectionBuilder::validate_seek](crate::SnapcastConnectionBuilder::validate_seek) follow it too.
+///
+/// # example
+/// ```no_run
+/// let clock = ManualClock::new();
+/// let mut client = SnapcastConnection::builder()
+///   .clock(clock.clone())
+///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+///   .await
+///   .expect("could not connect");
+///
+/// clock.advance(std::time::Duration::from_secs(5));
+/// ```
+pub trait Clock: Send + Sync {
+  /// the current time
+  fn now(&self) -> Instant;
+}
+
+/// the real time from [Instant::now], used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+}
+
+/// the time of the tokio runtime, which stands still while it is paused with `tokio::time::pause`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+  fn now(&self) -> Instant {
+    tokio::time::Instant::now().into_std()
+  }
+}
+
+/// a virtual clock that only moves when it is [advanced](ManualClock::advance)
+///
+/// clones share the same time, so one clone can be given to the connection while another advances it
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+  now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+  /// create a clock starting at the current real time
+  pub fn new() -> Self {
+    Self {
+      now: Arc::new(Mutex::new(Instant::now())),
+    }
+  }
+
+  /// move the clock forward
+  ///
+  /// # args
+  /// `duration`: how far to move the clock
+  pub fn advance(&self, duration: Duration) {
+    *self.now.lock().expect("mutex poisoned") += duration;
+  }
+}
+
+impl Default for ManualClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    *self.now.lock().expect("mutex poisoned")
+  }
+}
+
+/// the [Clock] of a connection, shared between the connection and the transport
+#[derive(Clone)]
+pub(crate) struct ClockHook(Arc<dyn Clock>);
+
+impl ClockHook {
+  pub(crate) fn new(clock: impl Clock + 'static) -> Self {
+    Self(Arc::new(clock))
+  }
+}
+
+impl std::ops::Deref for ClockHook {
+  type Target = dyn Clock;
+
+  fn deref(&self) -> &Self::Target {
+    self.0.as_ref()
+  }
+}
+
+impl Default for ClockHook {
+  fn default() -> Self {
+    Self::new(SystemClock)
+  }
+}
+
+impl fmt::Debug for ClockHook {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("ClockHook")
+  }
+}
@@ -1,17 +1,38 @@
-use stubborn_io::StubbornTcpStream;
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
+  builder::IdGenerator,
+  clock::ClockHook,
   errors,
-  protocol::{self, client, group, server, stream, Request, RequestMethod, SentRequests},
-  state::WrappedState,
-  Message, Method, ValidMessage,
+  handle::{ClientHandle, GroupHandle, StreamHandle},
+  metrics::MetricsHook,
+  protocol::{
+    self, client, group, redact_art_data, server, stream, DeserializerOptions, ForgottenRequests, JsonRpcVersion,
+    Request, RequestMethod, SentRequests, SentRequestsView, SnapcastDeserializer,
+  },
+  state::{StateUpdate, WrappedState},
+  tape::{self, Direction, Tape, WireTap},
+  transport::{BoxedIo, TransportOptions},
+  ConnectionStatus, Message, Method, Notification, NotificationType, Origin, SnapcastConnectionBuilder, SnapcastResult,
+  State, StateGroup, ValidMessage,
 };
 
-type Sender =
-  futures::stream::SplitSink<tokio_util::codec::Framed<StubbornTcpStream<std::net::SocketAddr>, Communication>, Method>;
-type Receiver =
-  futures::stream::SplitStream<tokio_util::codec::Framed<StubbornTcpStream<std::net::SocketAddr>, Communication>>;
+/// how long a sent set-command is remembered to recognize notifications that echo it
+const ECHO_WINDOW: Duration = Duration::from_secs(2);
+/// how many sent set-commands are remembered at most
+const RECENT_COMMANDS: usize = 32;
+/// how long a request whose [SnapcastConnection::send_and_wait] was dropped is remembered to correlate its late result
+const FORGET_WINDOW: Duration = Duration::from_secs(60);
+
+type Sender = futures::stream::SplitSink<tokio_util::codec::Framed<BoxedIo, Communication>, Request>;
+type Receiver = futures::stream::SplitStream<tokio_util::codec::Framed<BoxedIo, Communication>>;
 
 /// Struct representing a connection to a Snapcast server.
 /// Contains the current state of the server and methods to interact with it.
@@ -19,11 +40,43 @@ type Receiver =
 /// call `SnapcastConnection::open` to create a new connection.
 pub struct SnapcastConnection {
   /// The current state of the server. The state is Send + Sync, so it can be shared between threads.
+  ///
+  /// stays empty if state tracking was disabled with [SnapcastConnectionBuilder::track_state]
   pub state: WrappedState,
 
   // internal
+  track_state: bool,
+  validate_seek: bool,
+  id_generator: IdGenerator,
+  purgatory: Arc<SentRequests>,
+  /// requests whose [SnapcastConnection::send_and_wait] was dropped, so their late results can still be correlated
+  forgotten: Arc<ForgottenRequests>,
+  transport: TransportOptions,
+  /// the codec every connection is framed with, including those opened by [SnapcastConnection::reconnect]
+  codec: Communication,
   sender: Sender,
   receiver: Receiver,
+  backlog: VecDeque<Result<ValidMessage, ClientError>>,
+  last_message_at: Option<Instant>,
+  resend: Option<ResendQueue>,
+  auto_refresh: bool,
+  status_requested_on: Option<usize>,
+  /// the connection whose catch-up in [SnapcastConnection::on_connected] has completed
+  caught_up_on: Option<usize>,
+  metrics: MetricsHook,
+  dry_run: Option<Vec<String>>,
+  ping_timeout: Option<Duration>,
+  auto_flush: bool,
+  optimistic_updates: bool,
+  /// the updates restoring the state of each optimistically applied request, oldest first
+  reverts: Vec<(Uuid, StateUpdate)>,
+  span: tracing::Span,
+  recent_commands: VecDeque<(Instant, Method)>,
+  clock: ClockHook,
+  /// whether the connection was taken over from a stream, which [SnapcastConnection::reconnect] cannot re-establish
+  adopted: bool,
+  /// whether [ConnectionStatus::Disconnected] was already emitted for the end of a connection without reconnection
+  disconnected_emitted: bool,
 }
 
 impl SnapcastConnection {
@@ -40,513 +93,2252 @@ impl SnapcastConnection {
   /// let mut client = SnapcastConnection::open("127.0.0.1:1705".parse().expect("could not parse socket address")).await;
   /// ```
   pub async fn open(address: std::net::SocketAddr) -> Self {
-    let state = WrappedState::default();
-    let (sender, receiver) = Communication::init(address).await;
-
-    Self {
-      state,
-      sender,
-      receiver,
-    }
+    Self::builder()
+      .connect(address)
+      .await
+      .expect("could not connect to snapcast server")
   }
 
-  /// send a raw command to the Snapcast server
-  ///
-  /// # args
-  /// `command`: [Method] - the command to send
+  /// create a [SnapcastConnectionBuilder] to configure a connection before opening it
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// a default [SnapcastConnectionBuilder]
   ///
   /// # example
   /// ```no_run
-  /// client.send(Method::ServerGetStatus).await.expect("could not send command");
+  /// let mut client = SnapcastConnection::builder()
+  ///   .record_to("snapcast.tape")
+  ///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+  ///   .await
+  ///   .expect("could not connect");
   /// ```
-  pub async fn send(&mut self, command: Method) -> Result<(), ClientError> {
-    use futures::SinkExt;
-
-    self.sender.send(command).await
+  pub fn builder() -> SnapcastConnectionBuilder {
+    SnapcastConnectionBuilder::default()
   }
 
-  /// receive a message from the Snapcast server
+  /// take over a tcp connection that was already established, e.g. after a custom handshake
   ///
-  /// uses a [futures::stream::Next] under the hood, so: \
-  /// creates a future that resolves to the next item in the stream
+  /// the connection is used as-is with the default options, see [SnapcastConnectionBuilder::connect_stream]
+  ///
+  /// # args
+  /// `stream`: [tokio::net::TcpStream] - a stream connected to the Snapcast server
   ///
   /// # returns
-  /// an [Option] containing an [Ok] with a [ValidMessage] if a message was received, \
-  /// an [Option] containing an [Err] with a [ClientError] if there was an error, \
-  /// or [None] if the stream has ended
+  /// a new [SnapcastConnection], or a [ClientError] if the stream is not connected
   ///
   /// # example
   /// ```no_run
-  /// let message = client.recv().await.expect("could not receive message");
+  /// let stream = tokio::net::TcpStream::connect("127.0.0.1:1705").await.expect("could not connect");
+  /// let mut client = SnapcastConnection::from_stream(stream).await.expect("could not take over stream");
   /// ```
-  pub async fn recv(&mut self) -> Option<Result<ValidMessage, ClientError>> {
-    use futures::StreamExt;
+  pub async fn from_stream(stream: tokio::net::TcpStream) -> Result<Self, ClientError> {
+    Self::builder().connect_stream(stream).await
+  }
+
+  pub(crate) async fn connect_with(
+    builder: SnapcastConnectionBuilder,
+    address: std::net::SocketAddr,
+  ) -> Result<Self, ClientError> {
+    Self::connect_over(builder, address, None).await
+  }
 
-    let message = self.receiver.next().await;
+  pub(crate) async fn connect_stream(
+    mut builder: SnapcastConnectionBuilder,
+    stream: tokio::net::TcpStream,
+  ) -> Result<Self, ClientError> {
+    let address = stream.peer_addr()?;
+    // a stream that was handed over cannot be re-established
+    builder.reconnect = false;
 
-    if let Some(Ok(message)) = message {
-      match &message {
-        Message::Error { error, .. } => return Some(Err(error.clone().into())),
-        Message::Result { result, .. } => self.state.handle_result(*result.clone()),
-        Message::Notification { method, .. } => self.state.handle_notification(*method.clone()),
-      };
+    Self::connect_over(builder, address, Some(stream)).await
+  }
+
+  /// set up the connection to `address`, over `stream` if it is already connected
+  async fn connect_over(
+    builder: SnapcastConnectionBuilder,
+    address: std::net::SocketAddr,
+    stream: Option<tokio::net::TcpStream>,
+  ) -> Result<Self, ClientError> {
+    let state = WrappedState::new(State::with_clock(builder.clock.clone()));
+    if let Some(server) = builder.initial_state.clone() {
+      state.seed(server);
+    }
+    let transport = builder.transport_options(address, &state);
+    let tape = builder.record_to.map(Tape::create).transpose()?;
+    let purgatory = Arc::new(SentRequests::new());
+    let forgotten = Arc::new(ForgottenRequests::new());
+    let codec = Communication {
+      purgatory: purgatory.clone(),
+      forgotten: forgotten.clone(),
+      options: DeserializerOptions {
+        ignore_art_data: builder.ignore_art_data,
+      },
+      redact_art_in_logs: builder.redact_art_in_logs,
+      tape,
+      wire_tap: builder.on_wire.clone(),
+      metrics: builder.metrics.clone(),
+    };
+    let span = builder
+      .span
+      .clone()
+      .unwrap_or_else(|| tracing::info_span!("snapcast", server = %address));
+    let adopted = stream.is_some();
+    let (sender, receiver) = Communication::init(&transport, stream, codec.clone())
+      .instrument(span.clone())
+      .await?;
+
+    let mut connection = Self {
+      state,
+      track_state: builder.track_state,
+      validate_seek: builder.validate_seek,
+      id_generator: builder.id_generator,
+      purgatory,
+      forgotten,
+      transport,
+      codec,
+      sender,
+      receiver,
+      backlog: VecDeque::new(),
+      last_message_at: None,
+      resend: builder.resend_on_reconnect.map(ResendQueue::new),
+      auto_refresh: builder.auto_refresh_on_connect,
+      status_requested_on: None,
+      caught_up_on: None,
+      metrics: builder.metrics,
+      dry_run: builder.dry_run.then(Vec::new),
+      ping_timeout: builder.ping_timeout,
+      auto_flush: builder.auto_flush,
+      optimistic_updates: builder.optimistic_updates && builder.track_state,
+      reverts: Vec::new(),
+      span,
+      recent_commands: VecDeque::new(),
+      clock: builder.clock,
+      adopted,
+      disconnected_emitted: false,
+    };
+
+    if let Some(minimum) = builder.min_supported_version {
+      connection
+        .check_version(minimum, builder.reject_unsupported_version)
+        .await?;
+    }
 
-      Some(Ok(
-        message
-          .try_into()
-          .expect("this should never fail bc error has returned already"),
-      ))
-    } else if let Some(Err(err)) = message {
-      Some(Err(err))
-    } else {
-      None
+    if let Some(timeout) = builder.prefetch_stream_properties {
+      connection.prefetch_stream_properties(timeout).await?;
     }
+
+    Ok(connection)
   }
 
-  // client methods
-  /// request the current status of a client from the Snapcast server
+  /// load the server status into the state while connecting and wait up to `timeout` for the properties of the
+  /// streams that have none, see [SnapcastConnectionBuilder::prefetch_stream_properties]
+  async fn prefetch_stream_properties(&mut self, timeout: Duration) -> Result<(), ClientError> {
+    let server = self.fetch_server_status().await?;
+    let mut missing: HashSet<String> = server
+      .streams
+      .into_iter()
+      .filter(|stream| stream.properties.is_none())
+      .map(|stream| stream.id)
+      .collect();
+    if missing.is_empty() {
+      return Ok(());
+    }
+
+    tracing::debug!("waiting for the properties of streams {:?}", missing);
+    let waiting = async {
+      while !missing.is_empty() {
+        let message = self.receive().await.ok_or(ClientError::ConnectionClosed)?;
+        let message = message.and_then(|message| self.process(message));
+        if let Ok(ValidMessage::Notification { method, .. }) = &message {
+          if let Notification::StreamOnProperties { params } = method.as_ref() {
+            missing.remove(&params.id);
+          }
+        }
+        self.backlog.push_back(message);
+      }
+
+      Ok(())
+    };
+    let waited = tokio::time::timeout(timeout, waiting).await;
+    if !missing.is_empty() {
+      tracing::debug!(
+        "streams {:?} have no properties yet, leaving them to later notifications",
+        missing
+      );
+    }
+
+    waited.unwrap_or(Ok(()))
+  }
+
+  async fn check_version(&mut self, minimum: server::GetRpcVersionResult, reject: bool) -> Result<(), ClientError> {
+    let found = self.fetch_rpc_version().await?;
+
+    if found < minimum {
+      if reject {
+        return Err(ClientError::UnsupportedVersion { found, minimum });
+      }
+
+      tracing::warn!(
+        "snapserver rpc version {}.{}.{} is older than the minimum supported {}.{}.{}",
+        found.major,
+        found.minor,
+        found.patch,
+        minimum.major,
+        minimum.minor,
+        minimum.patch
+      );
+    }
+
+    Ok(())
+  }
+
+  /// replay a tape recorded with [SnapcastConnectionBuilder::record_to] into a fresh [State]
   ///
-  /// wrapper for sending a [ClientGetStatus](Method::ClientGetStatus) command
+  /// outbound requests on the tape are used to correlate results, inbound messages are decoded and applied to
+  /// the state exactly as [SnapcastConnection::recv] would. no network connection is made.
   ///
   /// # args
-  /// `id`: [String] - the id of the client
+  /// `path`: the path of the recorded tape
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the [State] after every inbound message has been applied, or a [ClientError] if the tape could not be read
   ///
   /// # example
   /// ```no_run
-  /// client.client_get_status("client_id".to_string()).await.expect("could not get client status");
+  /// let state = SnapcastConnection::replay("snapcast.tape").expect("could not replay tape");
   /// ```
-  pub async fn client_get_status(&mut self, id: String) -> Result<(), ClientError> {
-    self
-      .send(Method::ClientGetStatus {
-        params: client::GetStatusParams { id },
-      })
-      .await
+  pub fn replay(path: impl AsRef<std::path::Path>) -> Result<State, ClientError> {
+    tape::replay(path.as_ref())
   }
 
-  /// set the volume and mute status of a client
-  ///
-  /// wrapper for sending a [ClientSetVolume](Method::ClientSetVolume) command
+  /// the requests that have been sent but have not received a result or error yet
   ///
-  /// # args
-  /// `id`: [String] - the id of the client
-  /// `volume`: [client::ClientVolume] - the volume and mute status to set
+  /// a read-only snapshot for diagnosing lost commands
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the id and [RequestMethod] of each request in flight, in no particular order
   ///
   /// # example
   /// ```no_run
-  /// client.client_set_mute("client_id".to_string(), client::ClientVolume { mute: false, volume: 50 }).await.expect("could not set client mute");
+  /// for (id, method) in client.pending() {
+  ///   println!("{} is waiting for a {:?} result", id, method);
+  /// }
   /// ```
-  pub async fn client_set_volume(&mut self, id: String, volume: client::ClientVolume) -> Result<(), ClientError> {
-    self
-      .send(Method::ClientSetVolume {
-        params: client::SetVolumeParams { id, volume },
-      })
-      .await
+  pub fn pending(&self) -> Vec<(Uuid, RequestMethod)> {
+    self.sent_requests().snapshot()
   }
 
-  /// set the latency of a client
+  /// a live, read-only view of the requests that were sent and are waiting for a response
   ///
-  /// wrapper for sending a [ClientSetLatency](Method::ClientSetLatency) command
-  ///
-  /// # args
-  /// `id`: [String] - the id of the client
-  /// `latency`: [usize] - the latency to set
+  /// unlike [pending](SnapcastConnection::pending), the view shares the correlation map of the connection, so it can
+  /// be handed to another task to inspect the requests in flight, e.g. by a router correlating its own requests
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// a [SentRequestsView] of this connection
   ///
   /// # example
   /// ```no_run
-  /// client.client_set_latency("client_id".to_string(), 100).await.expect("could not set client latency");
+  /// let sent = client.sent_requests();
+  /// tokio::spawn(async move {
+  ///   loop {
+  ///     println!("{} requests in flight", sent.len());
+  ///     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+  ///   }
+  /// });
   /// ```
-  pub async fn client_set_latency(&mut self, id: String, latency: usize) -> Result<(), ClientError> {
-    self
-      .send(Method::ClientSetLatency {
-        params: client::SetLatencyParams { id, latency },
-      })
-      .await
+  pub fn sent_requests(&self) -> SentRequestsView {
+    SentRequestsView::new(self.purgatory.clone())
   }
 
-  /// set the name of a client
-  ///
-  /// wrapper for sending a [ClientSetName](Method::ClientSetName) command
-  ///
-  /// # args
-  /// `id`: [String] - the id of the client
-  /// `name`: [String] - the name to set
+  /// the time the last message was successfully decoded
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
-  ///
-  /// # example
-  /// ```no_run
-  /// client.client_set_name("client_id".to_string(), "new_name".to_string()).await.expect("could not set client name");
-  /// ```
-  pub async fn client_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
-    self
-      .send(Method::ClientSetName {
-        params: client::SetNameParams { id, name },
-      })
-      .await
+  /// the [Instant] of the last message, or [None] if no message has been received yet
+  pub fn last_message_at(&self) -> Option<Instant> {
+    self.last_message_at
   }
 
-  // group methods
-  /// request the current status of a group from the Snapcast server
-  ///
-  /// wrapper for sending a [GroupGetStatus](Method::GroupGetStatus) command
-  ///
-  /// # args
-  /// `id`: [String] - the id of the group
+  /// the time the current connection was established
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
-  ///
-  /// # example
-  /// ```no_run
-  /// client.group_get_status("group_id".to_string()).await.expect("could not get group status");
-  /// ```
-  pub async fn group_get_status(&mut self, id: String) -> Result<(), ClientError> {
-    self
-      .send(Method::GroupGetStatus {
-        params: group::GetStatusParams { id },
-      })
-      .await
+  /// the [Instant] of the most recent [ConnectionStatus::Connected], or [None] if currently disconnected
+  pub fn connected_since(&self) -> Option<Instant> {
+    self.transport.on_status.connected_since()
   }
 
-  /// set the mute status of a group
-  ///
-  /// wrapper for sending a [GroupSetMute](Method::GroupSetMute) command
-  ///
-  /// # args
-  /// `id`: [String] - the id of the group
-  /// `mute`: [bool] - the mute status to set
+  /// the remote address the current connection is established with
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
-  ///
-  /// # example
-  /// ```no_run
-  /// client.group_set_mute("group_id".to_string(), true).await.expect("could not set group mute");
-  /// ```
-  pub async fn group_set_mute(&mut self, id: String, mute: bool) -> Result<(), ClientError> {
-    self
-      .send(Method::GroupSetMute {
-        params: group::SetMuteParams { id, mute },
-      })
-      .await
+  /// the [std::net::SocketAddr] of the server, or [None] if currently disconnected
+  pub fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+    self.connected_since()?;
+    self.transport.target.peer_addr()
   }
 
-  /// set the stream of a group
-  ///
-  /// wrapper for sending a [GroupSetStream](Method::GroupSetStream) command
+  /// send a raw command to the Snapcast server
   ///
   /// # args
-  /// `id`: [String] - the id of the group
-  /// `stream_id`: [String] - the id of the stream to set
+  /// `command`: [Method] - the command to send
   ///
   /// # returns
   /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
   ///
   /// # example
   /// ```no_run
-  /// client.group_set_stream("group_id".to_string(), "stream_id".to_string()).await.expect("could not set group stream");
+  /// client.send(Method::ServerGetStatus).await.expect("could not send command");
   /// ```
-  pub async fn group_set_stream(&mut self, id: String, stream_id: String) -> Result<(), ClientError> {
-    self
-      .send(Method::GroupSetStream {
-        params: group::SetStreamParams { id, stream_id },
-      })
-      .await
+  pub async fn send(&mut self, command: Method) -> Result<(), ClientError> {
+    let request = self.request(command);
+    if self.hold_back(&request)?.is_some() {
+      return Ok(());
+    }
+
+    let flush = self.auto_flush;
+    self.write(request, flush).await
   }
 
-  /// set the clients of a group
-  ///
-  /// wrapper for sending a [GroupSetClients](Method::GroupSetClients) command
-  ///
-  /// # args
-  /// `id`: [String] - the id of the group
-  /// `clients`: [Vec]<[String]> - the ids of the clients to set
+  /// send a command and apply `update` to the state right away if optimistic updates are enabled, restoring the
+  /// state with `revert` if the server responds with an error
+  async fn send_optimistic(
+    &mut self,
+    command: Method,
+    update: impl FnOnce(&State) -> Option<(StateUpdate, StateUpdate)>,
+  ) -> Result<(), ClientError> {
+    let request = self.request(command);
+    if self.hold_back(&request)?.is_some() {
+      return Ok(());
+    }
+
+    let id = request.id;
+    if self.optimistic_updates {
+      if let Some((update, revert)) = update(&self.state) {
+        self.state.apply(update);
+        self.reverts.push((id, revert));
+      }
+    }
+
+    let flush = self.auto_flush;
+    let written = self.write(request, flush).await;
+    if written.is_err() {
+      if let Some(revert) = self.take_revert(id) {
+        self.state.apply(revert);
+      }
+    }
+
+    written
+  }
+
+  /// forget the optimistic update of request `id`, returning the update that restores the state
+  fn take_revert(&mut self, id: Uuid) -> Option<StateUpdate> {
+    let index = self.reverts.iter().position(|(reverted, _)| *reverted == id)?;
+    Some(self.reverts.remove(index).1)
+  }
+
+  /// write a request to the socket inside its [request span](SnapcastConnection::request_span), flushing it if
+  /// `flush` is set
+  async fn write(&mut self, request: Request, flush: bool) -> Result<(), ClientError> {
+    use futures::SinkExt;
+
+    let span = self.request_span(request.id, &RequestMethod::from(&request.method));
+    let tracked = self.tracked(&request);
+    self.remember(&request);
+    let sender = &mut self.sender;
+    async move {
+      if flush {
+        sender.send(request).await
+      } else {
+        sender.feed(request).await
+      }
+    }
+    .instrument(span)
+    .await?;
+    self.track(tracked);
+
+    Ok(())
+  }
+
+  /// the span of a single request, below the span of the connection
+  fn request_span(&self, id: Uuid, method: &dyn std::fmt::Debug) -> tracing::Span {
+    tracing::debug_span!(parent: &self.span, "request", %id, ?method)
+  }
+
+  /// write the commands queued while [auto flush](SnapcastConnectionBuilder::auto_flush) is disabled to the socket
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// an empty [Ok] once the queued commands were written, or a [ClientError] if there was an error
   ///
   /// # example
   /// ```no_run
-  /// client.group_set_clients("group_id".to_string(), vec!["client_id".to_string()]).await.expect("could not set group clients");
+  /// client.group_set_mute("group_id".to_string(), true).await.expect("could not queue command");
+  /// client.group_set_name("group_id".to_string(), "Kitchen".to_string()).await.expect("could not queue command");
+  /// client.flush().await.expect("could not flush commands");
   /// ```
-  pub async fn group_set_clients(&mut self, id: String, clients: Vec<String>) -> Result<(), ClientError> {
-    self
-      .send(Method::GroupSetClients {
-        params: group::SetClientsParams { id, clients },
-      })
-      .await
+  pub async fn flush(&mut self) -> Result<(), ClientError> {
+    use futures::SinkExt;
+
+    self.sender.flush().await?;
+
+    Ok(())
   }
 
-  /// set the name of a group
+  /// whether [SnapcastConnection::send] flushes every command right away, see
+  /// [SnapcastConnectionBuilder::auto_flush]
+  pub fn auto_flush(&self) -> bool {
+    self.auto_flush
+  }
+
+  /// send several raw commands to the Snapcast server in a single write
   ///
-  /// wrapper for sending a [GroupSetName](Method::GroupSetName) command
+  /// each command is encoded with its own id, and all of them are flushed to the socket at once. \
+  /// the server processes newline-delimited messages sequentially, so the results arrive in order.
   ///
   /// # args
-  /// `id`: [String] - the id of the group
-  /// `name`: [String] - the name to set
+  /// `commands`: [Vec]<[Method]> - the commands to send
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the ids of the sent requests in the same order as `commands`, or a [ClientError] if there was an error
   ///
   /// # example
   /// ```no_run
-  /// client.group_set_name("group_id".to_string(), "new_name".to_string()).await.expect("could not set group name");
+  /// let ids = client.send_batch(vec![Method::ServerGetRPCVersion, Method::ServerGetStatus]).await.expect("could not send commands");
   /// ```
-  pub async fn group_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
-    self
-      .send(Method::GroupSetName {
-        params: group::SetNameParams { id, name },
-      })
-      .await
+  pub async fn send_batch(&mut self, commands: Vec<Method>) -> Result<Vec<Uuid>, ClientError> {
+    use futures::SinkExt;
+
+    let mut ids = Vec::with_capacity(commands.len());
+    let mut tracked = Vec::new();
+    for command in commands {
+      let request = self.request(command);
+      ids.push(request.id);
+      if self.hold_back(&request)?.is_some() {
+        continue;
+      }
+
+      tracked.extend(self.tracked(&request));
+      self.remember(&request);
+      self.sender.feed(request).await?;
+    }
+    self.sender.flush().await?;
+    tracked.into_iter().for_each(|request| self.track(Some(request)));
+
+    Ok(ids)
   }
 
-  // server methods
-  /// request the rpc version of the Snapcast server
-  ///
-  /// wrapper for sending a [ServerGetStatus](Method::ServerGetStatus) command
-  ///
-  /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  fn request(&mut self, method: Method) -> Request {
+    if method == Method::ServerGetStatus {
+      self.status_requested_on = Some(self.transport.on_status.connections());
+    }
+
+    Request {
+      id: self.id_generator.generate(),
+      jsonrpc: JsonRpcVersion,
+      method,
+    }
+  }
+
+  /// in dry-run mode, log and record a request that would change the server instead of sending it
   ///
-  /// # example
-  /// ```no_run
-  /// client.server_get_rpc_version().await.expect("could not get server rpc version");
-  /// ```
-  pub async fn server_get_rpc_version(&mut self) -> Result<(), ClientError> {
-    self.send(Method::ServerGetRPCVersion).await
+  /// returns the serialized request if it was held back
+  fn hold_back(&mut self, request: &Request) -> Result<Option<String>, ClientError> {
+    let Some(held_back) = self.dry_run.as_mut() else {
+      return Ok(None);
+    };
+    if is_read_only(&request.method) {
+      return Ok(None);
+    }
+
+    let serialized = serde_json::to_string(request)?;
+    tracing::info!("dry run, not sending: {}", serialized);
+    held_back.push(serialized.clone());
+
+    Ok(Some(serialized))
   }
 
-  /// request the current status of the Snapcast server, this is a full refresh for state
+  /// the requests held back since the last call, when dry-run mode is enabled
   ///
-  /// wrapper for sending a [ServerGetStatus](Method::ServerGetStatus) command
+  /// see [SnapcastConnectionBuilder::dry_run]
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the serialized requests in the order they would have been sent, exactly as they would have been written
+  /// without the trailing newline
   ///
   /// # example
   /// ```no_run
-  /// client.server_get_status().await.expect("could not get server status");
+  /// client.group_set_clients("group_id".to_string(), vec!["client_id".to_string()]).await.expect("could not set clients");
+  /// for request in client.take_dry_run_requests() {
+  ///   println!("would send {}", request);
+  /// }
   /// ```
-  pub async fn server_get_status(&mut self) -> Result<(), ClientError> {
-    self.send(Method::ServerGetStatus).await
+  pub fn take_dry_run_requests(&mut self) -> Vec<String> {
+    self.dry_run.as_mut().map(std::mem::take).unwrap_or_default()
   }
 
-  /// forcefully delete a client from the Snapcast server
+  /// a copy of `request` to re-send after a reconnect, if it is a set-command and resending is enabled
+  fn tracked(&self, request: &Request) -> Option<(Uuid, Method)> {
+    self.resend.as_ref()?;
+    is_set_command(&request.method).then(|| (request.id, request.method.clone()))
+  }
+
+  /// remember a written request until its result arrives, tagged with the connection it was written on
+  fn track(&mut self, tracked: Option<(Uuid, Method)>) {
+    let connection = self.transport.on_status.connections();
+    if let (Some(resend), Some((id, method))) = (self.resend.as_mut(), tracked) {
+      resend.push(id, method, connection);
+    }
+  }
+
+  /// forget a request that must not be re-sent anymore because its result arrived
+  fn acknowledge(&mut self, id: Uuid) {
+    if let Some(resend) = self.resend.as_mut() {
+      resend.acknowledge(id);
+    }
+  }
+
+  /// remember a written set-command for a while to recognize the notifications that echo it
+  fn remember(&mut self, request: &Request) {
+    if !is_set_command(&request.method) {
+      return;
+    }
+
+    if self.recent_commands.len() == RECENT_COMMANDS {
+      self.recent_commands.pop_front();
+    }
+    self
+      .recent_commands
+      .push_back((self.clock.now(), request.method.clone()));
+  }
+
+  /// whether `notification` echoes a recently sent command, forgetting that command if so
+  fn origin_of(&mut self, notification: &Notification) -> Origin {
+    let now = self.clock.now();
+    while self
+      .recent_commands
+      .front()
+      .is_some_and(|(sent_at, _)| now.saturating_duration_since(*sent_at) > ECHO_WINDOW)
+    {
+      self.recent_commands.pop_front();
+    }
+
+    match self
+      .recent_commands
+      .iter()
+      .position(|(_, command)| notification.mirrors(command))
+    {
+      Some(index) => {
+        self.recent_commands.remove(index);
+        Origin::Echo
+      }
+      None => Origin::Remote,
+    }
+  }
+
+  /// catch up after [ConnectionStatus::Connected] fired: re-send unanswered commands and refresh the state
   ///
-  /// wrapper for sending a [ServerDeleteClient](Method::ServerDeleteClient) command
+  /// resumable: if the catch-up is cut short, the next call picks up where it stopped, and anything already queued
+  /// on the socket is flushed
+  async fn on_connected(&mut self) -> Result<(), ClientError> {
+    // results of requests forgotten on an earlier connection can no longer arrive
+    self.forgotten.clear();
+    self.resend_unanswered().await?;
+
+    let connection = self.transport.on_status.connections();
+    if self.auto_refresh && self.status_requested_on != Some(connection) {
+      tracing::debug!("refreshing state after connect");
+      self.send(Method::ServerGetStatus).await?;
+    }
+    self.flush().await?;
+
+    Ok(())
+  }
+
+  /// re-send the set-commands that were written on an earlier connection and never answered
   ///
-  /// # args
-  /// `id`: [String] - the id of the client to delete
+  /// a command stays in the resend queue and in [sent_requests](SnapcastConnection::sent_requests) until its
+  /// replacement has been flushed, so a failed write or a dropped future loses nothing
+  async fn resend_unanswered(&mut self) -> Result<(), ClientError> {
+    use futures::SinkExt;
+
+    let connection = self.transport.on_status.connections();
+    let Some(stale) = self.resend.as_ref().map(|resend| resend.stale(connection)) else {
+      return Ok(());
+    };
+    if stale.is_empty() {
+      return Ok(());
+    }
+
+    tracing::info!("re-sending {} unanswered commands after reconnect", stale.len());
+    let mut replaced = Vec::with_capacity(stale.len());
+    for (id, method) in stale {
+      let request = self.request(method);
+      replaced.push((id, request.id, self.tracked(&request)));
+      self.remember(&request);
+      self.sender.feed(request).await?;
+    }
+    self.sender.flush().await?;
+
+    for (id, replacement, tracked) in replaced {
+      self.purgatory.remove(&id);
+      self.acknowledge(id);
+      self.track(tracked);
+      // the optimistic update is now confirmed or reverted by the result of the replacement
+      if let Some((reverted, _)) = self.reverts.iter_mut().find(|(reverted, _)| *reverted == id) {
+        *reverted = replacement;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// receive a message from the Snapcast server
+  ///
+  /// uses a [futures::stream::Next] under the hood, so: \
+  /// creates a future that resolves to the next item in the stream
+  ///
+  /// messages that arrived while awaiting a response with [SnapcastConnection::send_and_wait] are returned first
+  ///
+  /// when the server closes the connection, the outcome depends on [SnapcastConnectionBuilder::reconnect]: \
+  /// with reconnection disabled, [ConnectionStatus::Disconnected] fires and [None] is returned, so a
+  /// `while let Some(message) = client.recv().await` loop ends. \
+  /// with reconnection enabled (the default), [ConnectionStatus::Disconnected] fires and the call keeps waiting while
+  /// the connection is re-established, so the loop never ends on its own. [None] is only returned if the transport
+  /// gives up, after the error that made it give up. \
+  /// a message that cannot be decoded, e.g. a result with a field the `strict` feature rejects, is returned as an
+  /// [Err] and the connection stays open, so the next call returns the message after it.
+  ///
+  /// dropping the future, e.g. in a `tokio::select!`, never loses a received message. if it is dropped while catching
+  /// up after a reconnect, the next call resumes the catch-up, so no command re-sent with
+  /// [SnapcastConnectionBuilder::resend_on_reconnect] and no refresh of
+  /// [SnapcastConnectionBuilder::auto_refresh_on_connect] is lost.
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// an [Option] containing an [Ok] with a [ValidMessage] if a message was received, \
+  /// an [Option] containing an [Err] with a [ClientError] if there was an error, \
+  /// or [None] if the stream has ended
   ///
   /// # example
   /// ```no_run
-  /// client.server_delete_client("client_id".to_string()).await.expect("could not delete client");
+  /// let message = client.recv().await.expect("could not receive message");
   /// ```
-  pub async fn server_delete_client(&mut self, id: String) -> Result<(), ClientError> {
-    self
-      .send(Method::ServerDeleteClient {
-        params: server::DeleteClientParams { id },
-      })
-      .await
+  pub async fn recv(&mut self) -> Option<Result<ValidMessage, ClientError>> {
+    if let Some(message) = self.backlog.pop_front() {
+      return Some(message);
+    }
+
+    let message = self.receive().await?;
+    Some(message.and_then(|message| self.process(message)))
   }
 
-  // stream methods
-  /// add a new stream to the Snapcast server
+  /// receive a message from the Snapcast server, or stop once `token` is cancelled
   ///
-  /// wrapper for sending a [StreamAddStream](Method::StreamAddStream) command
+  /// a shorthand for racing [SnapcastConnection::recv] against a shutdown signal in a `tokio::select!`. messages
+  /// that have not been received when the token is cancelled stay buffered, so nothing is lost.
   ///
   /// # args
-  /// `stream_uri`: [String] - the uri of the stream to add
+  /// `token`: [CancellationToken] - the token that signals shutdown
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// what [SnapcastConnection::recv] returns, or [None] if the token is or gets cancelled first
   ///
   /// # example
   /// ```no_run
-  /// client.stream_add_stream("librespot:///usr/bin/librespot?name=Spotify&...".to_string()).await.expect("could not add stream");
+  /// let token = tokio_util::sync::CancellationToken::new();
+  /// let shutdown = token.clone();
+  /// tokio::spawn(async move {
+  ///   tokio::signal::ctrl_c().await.expect("could not listen for ctrl-c");
+  ///   shutdown.cancel();
+  /// });
+  ///
+  /// while let Some(message) = client.recv_until(token.clone()).await {
+  ///   println!("{:?}", message);
+  /// }
   /// ```
-  pub async fn stream_add_stream(&mut self, stream_uri: String) -> Result<(), ClientError> {
-    self
-      .send(Method::StreamAddStream {
-        params: stream::AddStreamParams { stream_uri },
-      })
-      .await
+  pub async fn recv_until(&mut self, token: CancellationToken) -> Option<Result<ValidMessage, ClientError>> {
+    use futures::future::Either;
+
+    if token.is_cancelled() {
+      return None;
+    }
+
+    let message = std::pin::pin!(self.recv());
+    let cancelled = std::pin::pin!(token.cancelled());
+    match futures::future::select(message, cancelled).await {
+      Either::Left((message, _)) => message,
+      Either::Right(_) => None,
+    }
   }
 
-  /// remove a stream from the Snapcast server
+  /// a stream of every message received from the Snapcast server, for use with stream combinators
   ///
-  /// wrapper for sending a [StreamRemoveStream](Method::StreamRemoveStream) command
+  /// drives [SnapcastConnection::recv] under the hood, so state is updated before each message is yielded. the
+  /// stream ends when the connection does.
   ///
-  /// # args
-  /// `id`: [String] - the id of the stream to remove
+  /// # returns
+  /// a [futures::Stream] of the items [SnapcastConnection::recv] returns, borrowing the connection
+  ///
+  /// # example
+  /// ```no_run
+  /// use futures::StreamExt;
+  ///
+  /// let mut messages = Box::pin(client.messages().filter_map(|message| async move { message.ok() }));
+  /// while let Some(message) = messages.next().await {
+  ///   println!("{:?}", message);
+  /// }
+  /// ```
+  pub fn messages(&mut self) -> impl futures::Stream<Item = Result<ValidMessage, ClientError>> + '_ {
+    futures::stream::unfold(self, |connection| async move {
+      let message = connection.recv().await?;
+      Some((message, connection))
+    })
+  }
+
+  /// turn the connection into a stream of every message received from the Snapcast server
+  ///
+  /// like [SnapcastConnection::messages], but owning the connection so the stream can be moved into a task. the
+  /// state stays reachable through a clone of [SnapcastConnection::state] taken beforehand.
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// a `'static` [futures::Stream] of the items [SnapcastConnection::recv] returns
   ///
   /// # example
   /// ```no_run
-  /// client.stream_remove_stream("stream_id".to_string()).await.expect("could not remove stream");
+  /// use futures::StreamExt;
+  ///
+  /// let state = client.state.clone();
+  /// let mut messages = Box::pin(client.into_stream());
+  /// while let Some(message) = messages.next().await {
+  ///   println!("{:?} with {} groups", message, state.groups.len());
+  /// }
   /// ```
-  pub async fn stream_remove_stream(&mut self, id: String) -> Result<(), ClientError> {
-    self
-      .send(Method::StreamRemoveStream {
-        params: stream::RemoveStreamParams { id },
-      })
-      .await
+  pub fn into_stream(self) -> impl futures::Stream<Item = Result<ValidMessage, ClientError>> {
+    futures::stream::unfold(self, |mut connection| async move {
+      let message = connection.recv().await?;
+      Some((message, connection))
+    })
   }
 
-  /// control a stream on the Snapcast server
+  /// a stream of the notifications pushed by the Snapcast server
   ///
-  /// wrapper for sending a [StreamControl](Method::StreamControl) command
+  /// drives [SnapcastConnection::recv] under the hood, so state is updated before each notification is yielded. \
+  /// results of sent commands are applied to state and skipped, errors are logged and skipped.
+  /// the stream ends when the connection does.
+  ///
+  /// # returns
+  /// a [futures::Stream] of [Notification]s borrowing the connection
+  ///
+  /// # example
+  /// ```no_run
+  /// use futures::StreamExt;
+  ///
+  /// let mut notifications = Box::pin(client.notifications());
+  /// while let Some(notification) = notifications.next().await {
+  ///   println!("{:?}", notification);
+  /// }
+  /// ```
+  pub fn notifications(&mut self) -> impl futures::Stream<Item = Notification> + '_ {
+    futures::stream::unfold(self, |connection| async move {
+      loop {
+        match connection.recv().await? {
+          Ok(ValidMessage::Notification { method, .. }) => return Some((*method, connection)),
+          Ok(ValidMessage::Result { .. }) => continue,
+          Err(err) => tracing::warn!("skipping error while streaming notifications: {}", err),
+        }
+      }
+    })
+  }
+
+  /// wait until a notification of type `kind` arrives, e.g. to wait for a stream to start playing
+  ///
+  /// a matching notification already buffered for [SnapcastConnection::recv] is returned first. state is updated as
+  /// usual while waiting, and any other messages received in the meantime are buffered and returned by subsequent
+  /// calls to [SnapcastConnection::recv].
   ///
   /// # args
-  /// `id`: [String] - the id of the stream to control
-  /// `command`: [stream::ControlCommand] - the command to send to the stream
+  /// `kind`: [NotificationType] - the type of notification to wait for
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the first [Notification] of type `kind`, or [ClientError::ConnectionClosed] if the connection ended first
   ///
   /// # example
   /// ```no_run
-  /// client.stream_control("stream_id".to_string(), stream::ControlCommand::Pause).await.expect("could not control stream");
+  /// let notification = client.await_notification(NotificationType::StreamOnUpdate).await.expect("connection closed");
   /// ```
-  pub async fn stream_control(&mut self, id: String, command: stream::ControlCommand) -> Result<(), ClientError> {
-    self
-      .send(Method::StreamControl {
-        params: stream::ControlParams { id, command },
-      })
-      .await
+  pub async fn await_notification(&mut self, kind: NotificationType) -> Result<Notification, ClientError> {
+    let buffered = self.backlog.iter().position(
+      |message| matches!(message, Ok(ValidMessage::Notification { method, .. }) if method.kind() == Some(kind)),
+    );
+    if let Some(Ok(ValidMessage::Notification { method, .. })) = buffered.and_then(|index| self.backlog.remove(index)) {
+      return Ok(*method);
+    }
+
+    loop {
+      let message = self.receive().await.ok_or(ClientError::ConnectionClosed)?;
+      match message.and_then(|message| self.process(message)) {
+        Ok(ValidMessage::Notification { method, .. }) if method.kind() == Some(kind) => return Ok(*method),
+        message => self.backlog.push_back(message),
+      }
+    }
   }
 
-  /// set the property of a stream on the Snapcast server
+  /// wait until a notification of type `kind` arrives, giving up after `timeout`
   ///
-  /// wrapper for sending a [StreamSetProperty](Method::StreamSetProperty) command
+  /// see [SnapcastConnection::await_notification]
   ///
   /// # args
-  /// `id`: [String] - the id of the stream to control
-  /// `properties`: [stream::SetPropertyProperties] - the properties to set on the stream
+  /// `kind`: [NotificationType] - the type of notification to wait for
+  /// `timeout`: how long to wait for the notification
   ///
   /// # returns
-  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  /// the first [Notification] of type `kind`, [ClientError::Timeout] if none arrived in time, or
+  /// [ClientError::ConnectionClosed] if the connection ended first
   ///
   /// # example
   /// ```no_run
-  /// client.stream_set_property("stream_id".to_string(), stream::SetPropertyProperties::Shuffle(true)).await.expect("could not set stream property");
+  /// let notification = client
+  ///   .await_notification_timeout(NotificationType::StreamOnUpdate, std::time::Duration::from_secs(10))
+  ///   .await
+  ///   .expect("stream did not update");
   /// ```
-  pub async fn stream_set_property(
+  pub async fn await_notification_timeout(
     &mut self,
-    id: String,
-    properties: stream::SetPropertyProperties,
-  ) -> Result<(), ClientError> {
-    self
-      .send(Method::StreamSetProperty {
-        params: stream::SetPropertyParams { id, properties },
-      })
+    kind: NotificationType,
+    timeout: Duration,
+  ) -> Result<Notification, ClientError> {
+    tokio::time::timeout(timeout, self.await_notification(kind))
       .await
+      .map_err(|_| ClientError::Timeout)?
   }
-}
-
-#[derive(Debug, Clone, Default)]
-struct Communication {
-  purgatory: SentRequests,
-}
 
-impl Communication {
-  async fn init(address: std::net::SocketAddr) -> (Sender, Receiver) {
-    use futures::stream::StreamExt;
-    use tokio_util::codec::Decoder;
+  /// send a raw command to the Snapcast server and wait for its result
+  ///
+  /// state is updated as usual while waiting, and any other messages received in the meantime are
+  /// buffered and returned by subsequent calls to [SnapcastConnection::recv]
+  ///
+  /// the future is cancel-safe: dropping it before the result arrives, e.g. in a `tokio::select!`, forgets the
+  /// request so it is no longer [pending](SnapcastConnection::pending). a result that still arrives on the same
+  /// connection within a minute is correlated to the request, applied to state, and returned by
+  /// [SnapcastConnection::recv].
+  ///
+  /// # args
+  /// `command`: [Method] - the command to send
+  ///
+  /// # returns
+  /// the [SnapcastResult] of the command, or a [ClientError] if there was an error or the server responded with one
+  ///
+  /// # example
+  /// ```no_run
+  /// let result = client.send_and_wait(Method::ServerGetRPCVersion).await.expect("could not get rpc version");
+  /// ```
+  pub async fn send_and_wait(&mut self, command: Method) -> Result<SnapcastResult, ClientError> {
+    let request = self.request(command);
+    let id = request.id;
+    if let Some(request) = self.hold_back(&request)? {
+      return Err(ClientError::DryRun(request));
+    }
+    let _forget = ForgetOnDrop {
+      purgatory: self.purgatory.clone(),
+      forgotten: self.forgotten.clone(),
+      clock: self.clock.clone(),
+      id,
+    };
 
-    let client = Self::default();
+    self.write(request, true).await?;
 
-    tracing::info!("connecting to snapcast server at {}", address);
-    let stream = StubbornTcpStream::connect(address).await.unwrap();
-    let (writer, reader) = client.framed(stream).split();
+    loop {
+      let message = self.receive().await.ok_or(ClientError::ConnectionClosed)?;
 
-    (writer, reader)
+      match message {
+        Ok(Message::Error {
+          id: response_id,
+          error,
+          method,
+          ..
+        }) if response_id == id => return Err(ClientError::response(id, method, error)),
+        Ok(message @ Message::Result { id: response_id, .. }) if response_id == id => {
+          if let ValidMessage::Result { result, .. } = self.process(message)? {
+            return Ok(*result);
+          }
+        }
+        message => {
+          let message = message.and_then(|message| self.process(message));
+          self.backlog.push_back(message);
+        }
+      }
+    }
   }
-}
-
-impl tokio_util::codec::Decoder for Communication {
-  type Item = Message;
-  type Error = ClientError;
 
-  fn decode(&mut self, src: &mut tokio_util::bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-    use tokio_util::bytes::Buf;
+  /// wait until every sent request has been answered, e.g. to flush a burst of commands before shutting down
+  ///
+  /// messages received in the meantime are applied to state and buffered for [SnapcastConnection::recv], like in
+  /// [SnapcastConnection::send_and_wait]
+  ///
+  /// # args
+  /// `timeout`: how long to wait for the outstanding results
+  ///
+  /// # returns
+  /// an empty [Ok] once no request is [pending](SnapcastConnection::pending), [ClientError::Unacknowledged] with
+  /// the ids still pending when the timeout elapsed, or [ClientError::ConnectionClosed]
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_mute("group_id".to_string(), true).await.expect("could not set group mute");
+  /// client.drain(std::time::Duration::from_secs(5)).await.expect("commands were not acknowledged");
+  /// ```
+  pub async fn drain(&mut self, timeout: Duration) -> Result<(), ClientError> {
+    let deadline = tokio::time::Instant::now() + timeout;
 
-    if src.is_empty() {
-      return Ok(None);
+    while !self.purgatory.is_empty() {
+      match tokio::time::timeout_at(deadline, self.receive()).await {
+        Ok(Some(message)) => {
+          let message = message.and_then(|message| self.process(message));
+          self.backlog.push_back(message);
+        }
+        Ok(None) => return Err(ClientError::ConnectionClosed),
+        Err(_) => {
+          let ids = self.purgatory.iter().map(|entry| *entry.key()).collect();
+          return Err(ClientError::Unacknowledged(ids));
+        }
+      }
     }
 
-    // tracing::trace!("decoding: {:?}", src);
+    Ok(())
+  }
 
-    let lf_pos = src.as_ref().iter().position(|b| *b == b'\n');
-    if let Some(lf_pos) = lf_pos {
-      let data = src.split_to(lf_pos);
-      src.advance(1);
+  /// check that the Snapcast server is responsive and measure how fast it replies
+  ///
+  /// sends a [ServerGetRPCVersion](Method::ServerGetRPCVersion) command and waits for its result like
+  /// [send_and_wait](SnapcastConnection::send_and_wait), so other messages received in the meantime are buffered
+  ///
+  /// # returns
+  /// the round-trip time of the request, [ClientError::Timeout] if the
+  /// [ping timeout](SnapcastConnectionBuilder::ping_timeout) elapsed first, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let latency = client.ping().await.expect("server is not responsive");
+  /// println!("server replied in {:?}", latency);
+  /// ```
+  pub async fn ping(&mut self) -> Result<Duration, ClientError> {
+    let timeout = self.ping_timeout;
+    let started_at = self.clock.now();
+    let reply = self.send_and_wait(Method::ServerGetRPCVersion);
+    match timeout {
+      Some(timeout) => tokio::time::timeout(timeout, reply)
+        .await
+        .map_err(|_| ClientError::Timeout)??,
+      None => reply.await?,
+    };
 
-      tracing::debug!("received complete message with length: {}", data.len());
-      let message = std::str::from_utf8(&data).unwrap();
-      tracing::trace!("completed json message: {:?}", message);
+    Ok(self.clock.now().saturating_duration_since(started_at))
+  }
 
-      let message = Message::try_from((message, &self.purgatory))?;
-      tracing::trace!("completed deserialized message: {:?}", message);
+  /// drop the current connection to the Snapcast server and establish a new one
+  ///
+  /// for when the application knows the connection is bad before the socket does, e.g. after several
+  /// [ping](SnapcastConnection::ping) timeouts. once the new connection is established,
+  /// [ConnectionStatus::Disconnected] fires for the old one, followed by [ConnectionStatus::Connected] and
+  /// [ConnectionStatus::Reconnected], just like an automatic reconnect. \
+  /// responses to requests sent over the old connection can no longer arrive, so they are removed from
+  /// [sent_requests](SnapcastConnection::sent_requests). they are re-sent if
+  /// [resend_on_reconnect](SnapcastConnectionBuilder::resend_on_reconnect) is enabled, and the state is refreshed on
+  /// the next [recv](SnapcastConnection::recv) if [auto_refresh_on_connect](SnapcastConnectionBuilder::auto_refresh_on_connect)
+  /// is. [optimistic updates](SnapcastConnectionBuilder::optimistic_updates) of requests that are not re-sent are
+  /// reverted. messages that were already received stay buffered.
+  ///
+  /// # returns
+  /// an empty [Ok] once the new connection is established, or a [ClientError] if it could not be, in which case the
+  /// old connection stays in use. a connection taken over with [SnapcastConnection::from_stream] cannot be
+  /// re-established and always fails with an [Unsupported](std::io::ErrorKind::Unsupported) [ClientError::Io].
+  ///
+  /// # example
+  /// ```no_run
+  /// if client.ping().await.is_err() {
+  ///   client.reconnect().await.expect("could not reconnect");
+  /// }
+  /// ```
+  pub async fn reconnect(&mut self) -> Result<(), ClientError> {
+    use futures::StreamExt;
+    use tokio_util::codec::Framed;
 
-      return Ok(Some(message));
-    }
+    let span = self.span.clone();
+    async {
+      if self.adopted {
+        return Err(ClientError::Io(std::io::Error::new(
+          std::io::ErrorKind::Unsupported,
+          "a connection taken over from a stream cannot be re-established",
+        )));
+      }
 
-    Ok(None)
-  }
-}
+      tracing::info!("reconnecting to snapcast server at {}", self.transport.target.address);
+      let stream = self.transport.connect_quietly().await.map_err(|err| match err.kind() {
+        std::io::ErrorKind::TimedOut => ClientError::Timeout,
+        _ => err.into(),
+      })?;
+      self.transport.on_status.emit(ConnectionStatus::Disconnected);
+      self.transport.on_status.emit(ConnectionStatus::Connected);
 
-impl tokio_util::codec::Encoder<Method> for Communication {
-  type Error = ClientError;
+      // replacing the sender and receiver closes the old socket
+      (self.sender, self.receiver) = Framed::new(stream, self.codec.clone()).split();
+      self.purgatory.clear();
+      self.forgotten.clear();
+      self.revert_unconfirmable();
+      self.disconnected_emitted = false;
 
-  fn encode(&mut self, method: Method, dst: &mut tokio_util::bytes::BytesMut) -> Result<(), Self::Error> {
-    tracing::trace!("encoding: {:?}", method);
+      Ok(())
+    }
+    .instrument(span)
+    .await
+  }
 
-    let id = Uuid::new_v4();
-    let command: RequestMethod = (&method).into();
-    tracing::debug!("sending command: {:?}", command);
-    self.purgatory.insert(id, command);
+  /// restore the state changed by optimistic updates whose requests were written on a connection that is gone and
+  /// will not be re-sent, newest first, so their results can no longer confirm them
+  fn revert_unconfirmable(&mut self) {
+    let reverts = std::mem::take(&mut self.reverts);
+    for (id, revert) in reverts.into_iter().rev() {
+      if self.resend.as_ref().is_some_and(|resend| resend.contains(id)) {
+        self.reverts.insert(0, (id, revert));
+      } else {
+        tracing::debug!("reverting optimistic update of request {} sent before reconnecting", id);
+        self.state.apply(revert);
+      }
+    }
+  }
 
-    let data = Request {
-      id,
-      jsonrpc: "2.0".to_string(),
-      method,
-    };
+  /// request the rpc version of the Snapcast server and wait for it
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a
+  /// [ServerGetRPCVersion](Method::ServerGetRPCVersion) command
+  ///
+  /// # returns
+  /// the [server::GetRpcVersionResult] reported by the Snapcast server, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let version = client.fetch_rpc_version().await.expect("could not fetch rpc version");
+  /// if version.as_tuple() >= (2, 1, 0) {
+  ///   println!("server supports rpc 2.1");
+  /// }
+  /// ```
+  pub async fn fetch_rpc_version(&mut self) -> Result<server::GetRpcVersionResult, ClientError> {
+    match self.send_and_wait(Method::ServerGetRPCVersion).await? {
+      SnapcastResult::ServerGetRPCVersion(result) => Ok(result),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
 
-    let string: String = data.try_into()?;
-    let string = format!("{}\n", string);
-    tracing::trace!("sending: {:?}", string);
+  /// request the current status of the Snapcast server and wait for the full [server::Server]
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a [ServerGetStatus](Method::ServerGetStatus) command
+  ///
+  /// # returns
+  /// the [server::Server] reported by the Snapcast server, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let server = client.fetch_server_status().await.expect("could not fetch server status");
+  /// ```
+  pub async fn fetch_server_status(&mut self) -> Result<server::Server, ClientError> {
+    match self.send_and_wait(Method::ServerGetStatus).await? {
+      SnapcastResult::ServerGetStatus(result) => Ok(result.server),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
 
-    dst.extend_from_slice(string.as_bytes());
+  /// request the current status of a client and wait for the [client::Client]
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a [ClientGetStatus](Method::ClientGetStatus) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client
+  ///
+  /// # returns
+  /// the [client::Client] reported by the Snapcast server, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let snapclient = client.fetch_client_status("client_id".to_string()).await.expect("could not fetch client status");
+  /// ```
+  pub async fn fetch_client_status(&mut self, id: String) -> Result<client::Client, ClientError> {
+    let command = Method::ClientGetStatus {
+      params: client::GetStatusParams { id },
+    };
 
-    Ok(())
+    match self.send_and_wait(command).await? {
+      SnapcastResult::ClientGetStatus(result) => Ok(result.client),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
   }
-}
 
-/// Error type for the Snapcast client
-#[derive(Debug, thiserror::Error)]
-pub enum ClientError {
+  /// request the current status of a group and wait for the [group::Group]
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a [GroupGetStatus](Method::GroupGetStatus) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  ///
+  /// # returns
+  /// the [group::Group] reported by the Snapcast server, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let group = client.fetch_group_status("group_id".to_string()).await.expect("could not fetch group status");
+  /// ```
+  pub async fn fetch_group_status(&mut self, id: String) -> Result<group::Group, ClientError> {
+    let command = Method::GroupGetStatus {
+      params: group::GetStatusParams { id },
+    };
+
+    match self.send_and_wait(command).await? {
+      SnapcastResult::GroupGetStatus(result) => Ok(result.group),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
+
+  /// refresh a single group and its clients, cheaper than a full [server_get_status](SnapcastConnection::server_get_status)
+  /// for recovering from a suspected desync on a big server
+  ///
+  /// wrapper for [fetch_group_status](SnapcastConnection::fetch_group_status) that also applies every client of the
+  /// group to the state
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  ///
+  /// # returns
+  /// the updated [StateGroup], or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// let group = client.resync_group("group_id".to_string()).await.expect("could not resync group");
+  /// ```
+  pub async fn resync_group(&mut self, id: String) -> Result<StateGroup, ClientError> {
+    let group = self.fetch_group_status(id).await?;
+    if !self.track_state {
+      return Ok(StateGroup::from(&group));
+    }
+
+    for client in &group.clients {
+      self.state.apply(StateUpdate::Client(client.clone()));
+    }
+
+    Ok(self.state.group(&group.id).unwrap_or_else(|| StateGroup::from(&group)))
+  }
+
+  /// get a handle to send commands to a single group without repeating its id
+  ///
+  /// # args
+  /// `id`: the id of the group
+  ///
+  /// # returns
+  /// a [GroupHandle] borrowing this connection
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_handle("group_id").set_mute(true).await.expect("could not mute group");
+  /// ```
+  pub fn group_handle(&mut self, id: impl Into<String>) -> GroupHandle<'_> {
+    GroupHandle::new(self, id.into())
+  }
+
+  /// get a handle to send commands to a single client without repeating its id
+  ///
+  /// # args
+  /// `id`: the id of the client
+  ///
+  /// # returns
+  /// a [ClientHandle] borrowing this connection
+  ///
+  /// # example
+  /// ```no_run
+  /// client.client_handle("client_id").set_latency(100).await.expect("could not set client latency");
+  /// ```
+  pub fn client_handle(&mut self, id: impl Into<String>) -> ClientHandle<'_> {
+    ClientHandle::new(self, id.into())
+  }
+
+  /// get a handle to send commands to a single stream without repeating its id
+  ///
+  /// # args
+  /// `id`: the id of the stream
+  ///
+  /// # returns
+  /// a [StreamHandle] borrowing this connection
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_handle("stream_id").control(stream::ControlCommand::Play).await.expect("could not play stream");
+  /// ```
+  pub fn stream_handle(&mut self, id: impl Into<String>) -> StreamHandle<'_> {
+    StreamHandle::new(self, id.into())
+  }
+
+  async fn receive(&mut self) -> Option<Result<Message, ClientError>> {
+    use futures::{future::Either, StreamExt};
+
+    let span = self.span.clone();
+    let message = async {
+      loop {
+        if self.resend.is_none() && !self.auto_refresh {
+          break self.receiver.next().await;
+        }
+
+        let connection = self.transport.on_status.connections();
+        if self.caught_up_on != Some(connection) {
+          if let Err(err) = self.on_connected().await {
+            tracing::warn!("could not catch up after connecting: {}", err);
+          }
+          self.caught_up_on = Some(connection);
+        }
+
+        let reconnected = self.transport.on_status.reconnected();
+        let notified = std::pin::pin!(reconnected.notified());
+        match futures::future::select(self.receiver.next(), notified).await {
+          Either::Left((message, _)) => break message,
+          Either::Right(_) => continue,
+        }
+      }
+    }
+    .instrument(span)
+    .await
+    .map(|item| item.and_then(|message| message));
+
+    match &message {
+      Some(Ok(message)) => {
+        self.last_message_at = Some(self.clock.now());
+        match message {
+          Message::Notification { method, .. } => self.metrics.on_notification(method),
+          Message::Result { id, result, method, .. } => {
+            let _entered = self.request_span(*id, method).entered();
+            tracing::debug!("received result");
+            self.acknowledge(*id);
+            self.metrics.on_result(result);
+          }
+          Message::Error { id, error, method, .. } => {
+            let _entered = self.request_span(*id, method).entered();
+            tracing::debug!("received error: {}", error);
+            self.acknowledge(*id);
+            self
+              .metrics
+              .on_error(&ClientError::response(*id, method.clone(), error.clone()));
+          }
+        }
+      }
+      Some(Err(err)) => self.metrics.on_error(err),
+      None if !self.transport.reconnect && !self.disconnected_emitted => {
+        self.disconnected_emitted = true;
+        self.transport.on_status.emit(ConnectionStatus::Disconnected);
+      }
+      None => {}
+    }
+
+    message
+  }
+
+  fn process(&mut self, message: Message) -> Result<ValidMessage, ClientError> {
+    match message {
+      Message::Error { id, error, method, .. } => {
+        if let Some(revert) = self.take_revert(id) {
+          tracing::debug!("reverting optimistic update of failed request {}", id);
+          self.state.apply(revert);
+        }
+
+        Err(ClientError::response(id, method, error))
+      }
+      Message::Result {
+        id,
+        jsonrpc,
+        result,
+        method,
+      } => {
+        self.take_revert(id);
+        if self.track_state {
+          self.state.handle_result(&result);
+        }
+
+        Ok(ValidMessage::Result {
+          id,
+          jsonrpc,
+          result,
+          method,
+        })
+      }
+      Message::Notification { jsonrpc, method } => {
+        if self.track_state {
+          self.state.handle_notification(&method);
+        }
+
+        let origin = self.origin_of(&method);
+        Ok(ValidMessage::Notification {
+          jsonrpc,
+          method,
+          origin,
+        })
+      }
+    }
+  }
+
+  // client methods
+  /// request the current status of a client from the Snapcast server
+  ///
+  /// wrapper for sending a [ClientGetStatus](Method::ClientGetStatus) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.client_get_status("client_id".to_string()).await.expect("could not get client status");
+  /// ```
+  pub async fn client_get_status(&mut self, id: String) -> Result<(), ClientError> {
+    self
+      .send(Method::ClientGetStatus {
+        params: client::GetStatusParams { id },
+      })
+      .await
+  }
+
+  /// set the volume and mute status of a client
+  ///
+  /// wrapper for sending a [ClientSetVolume](Method::ClientSetVolume) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client
+  /// `volume`: [client::ClientVolume] - the volume and mute status to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.client_set_mute("client_id".to_string(), client::ClientVolume { mute: false, volume: 50 }).await.expect("could not set client mute");
+  /// ```
+  pub async fn client_set_volume(&mut self, id: String, volume: client::ClientVolume) -> Result<(), ClientError> {
+    self
+      .send(Method::ClientSetVolume {
+        params: client::SetVolumeParams { id, volume },
+      })
+      .await
+  }
+
+  /// set the volume and mute status of several clients at once, e.g. to recall a scene
+  ///
+  /// sends a [ClientSetVolume](Method::ClientSetVolume) command for every client with
+  /// [send_batch](SnapcastConnection::send_batch), so they are written in a single flush, ordered by client id
+  ///
+  /// # args
+  /// `volumes`: [HashMap]<[String], [client::ClientVolume]> - the volume of each client, by client id
+  ///
+  /// # returns
+  /// the ids of the sent requests in the order of the client ids, or a [ClientError] if there was an error. \
+  /// nothing is sent if any volume is invalid: [ClientError::InvalidParams] is returned if a percent is above 100,
+  /// and, when the state is populated, [ClientError::UnknownIds] with every unknown client id.
+  ///
+  /// # example
+  /// ```no_run
+  /// let movie_mode = HashMap::from([
+  ///   ("living_room".to_string(), client::ClientVolume::unmuted(80)),
+  ///   ("kitchen".to_string(), client::ClientVolume::muted(20)),
+  /// ]);
+  /// client.set_client_volumes(movie_mode).await.expect("could not set client volumes");
+  /// ```
+  pub async fn set_client_volumes(
+    &mut self,
+    volumes: HashMap<String, client::ClientVolume>,
+  ) -> Result<Vec<Uuid>, ClientError> {
+    let mut volumes: Vec<_> = volumes.into_iter().collect();
+    volumes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    if let Some((id, volume)) = volumes.iter().find(|(_, volume)| volume.percent > 100) {
+      return Err(ClientError::InvalidParams(format!(
+        "volume of client {} is {} percent, above 100",
+        id, volume.percent
+      )));
+    }
+
+    if !self.state.clients.is_empty() {
+      let unknown: Vec<_> = volumes
+        .iter()
+        .map(|(id, _)| id)
+        .filter(|id| !self.state.clients.contains_key(id.as_str()))
+        .cloned()
+        .collect();
+      if !unknown.is_empty() {
+        return Err(ClientError::UnknownIds(unknown));
+      }
+    }
+
+    if volumes.is_empty() {
+      return Ok(vec![]);
+    }
+
+    let commands = volumes
+      .into_iter()
+      .map(|(id, volume)| Method::ClientSetVolume {
+        params: client::SetVolumeParams { id, volume },
+      })
+      .collect();
+    self.send_batch(commands).await
+  }
+
+  /// set the latency of a client
+  ///
+  /// wrapper for sending a [ClientSetLatency](Method::ClientSetLatency) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client
+  /// `latency`: [usize] - the latency to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.client_set_latency("client_id".to_string(), 100).await.expect("could not set client latency");
+  /// ```
+  pub async fn client_set_latency(&mut self, id: String, latency: usize) -> Result<(), ClientError> {
+    self
+      .send(Method::ClientSetLatency {
+        params: client::SetLatencyParams { id, latency },
+      })
+      .await
+  }
+
+  /// set the name of a client
+  ///
+  /// wrapper for sending a [ClientSetName](Method::ClientSetName) command. the name is applied to the state before the
+  /// result arrives if [optimistic updates](SnapcastConnectionBuilder::optimistic_updates) are enabled.
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client
+  /// `name`: [String] - the name to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.client_set_name("client_id".to_string(), "new_name".to_string()).await.expect("could not set client name");
+  /// ```
+  pub async fn client_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
+    let command = Method::ClientSetName {
+      params: client::SetNameParams {
+        id: id.clone(),
+        name: name.clone(),
+      },
+    };
+
+    self
+      .send_optimistic(command, |state| {
+        let previous = state.client(&id)?.config.name;
+        let revert = StateUpdate::ClientName {
+          id: id.clone(),
+          name: previous,
+        };
+        Some((StateUpdate::ClientName { id, name }, revert))
+      })
+      .await
+  }
+
+  // group methods
+  /// request the current status of a group from the Snapcast server
+  ///
+  /// wrapper for sending a [GroupGetStatus](Method::GroupGetStatus) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_get_status("group_id".to_string()).await.expect("could not get group status");
+  /// ```
+  pub async fn group_get_status(&mut self, id: String) -> Result<(), ClientError> {
+    self
+      .send(Method::GroupGetStatus {
+        params: group::GetStatusParams { id },
+      })
+      .await
+  }
+
+  /// set the mute status of a group
+  ///
+  /// wrapper for sending a [GroupSetMute](Method::GroupSetMute) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  /// `mute`: [bool] - the mute status to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_mute("group_id".to_string(), true).await.expect("could not set group mute");
+  /// ```
+  pub async fn group_set_mute(&mut self, id: String, mute: bool) -> Result<(), ClientError> {
+    self
+      .send(Method::GroupSetMute {
+        params: group::SetMuteParams { id, mute },
+      })
+      .await
+  }
+
+  /// set the volume of a group
+  ///
+  /// snapcast has no group volume, so this sends a [ClientSetVolume](Method::ClientSetVolume) command for every
+  /// client of the group whose volume changes. the volumes are taken from [State::group_volume_targets], which keeps
+  /// the clients' relative volumes and does not drift them apart over repeated adjustments.
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  /// `percent`: [u8] - the mean volume the clients of the group should have, clamped to 100
+  ///
+  /// # returns
+  /// an empty [Ok] if the commands were sent successfully, or a [ClientError] if there was an error. \
+  /// [ClientError::NotInState] is returned if the group or all of its clients are missing from the state.
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_volume("group_id".to_string(), 40).await.expect("could not set group volume");
+  /// ```
+  pub async fn group_set_volume(&mut self, id: String, percent: u8) -> Result<(), ClientError> {
+    let targets = self
+      .state
+      .group_volume_targets(&id, percent)
+      .ok_or_else(|| ClientError::NotInState(id.clone()))?;
+
+    let commands: Vec<Method> = targets
+      .into_iter()
+      .filter(|(client, volume)| {
+        self
+          .state
+          .client(client)
+          .is_some_and(|current| current.config.volume != *volume)
+      })
+      .map(|(id, volume)| Method::ClientSetVolume {
+        params: client::SetVolumeParams { id, volume },
+      })
+      .collect();
+
+    if !commands.is_empty() {
+      self.send_batch(commands).await?;
+    }
+
+    Ok(())
+  }
+
+  /// set the stream of a group
+  ///
+  /// wrapper for sending a [GroupSetStream](Method::GroupSetStream) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  /// `stream_id`: [String] - the id of the stream to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_stream("group_id".to_string(), "stream_id".to_string()).await.expect("could not set group stream");
+  /// ```
+  pub async fn group_set_stream(&mut self, id: String, stream_id: String) -> Result<(), ClientError> {
+    self
+      .send(Method::GroupSetStream {
+        params: group::SetStreamParams { id, stream_id },
+      })
+      .await
+  }
+
+  /// set the mute status of every group in the state
+  ///
+  /// sends a [GroupSetMute](Method::GroupSetMute) command for each group that is not already in the desired state,
+  /// all in a single write
+  ///
+  /// # args
+  /// `mute`: [bool] - whether to mute or unmute every group
+  ///
+  /// # returns
+  /// an empty [Ok] if the commands were sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.set_all_groups_muted(true).await.expect("could not mute all groups");
+  /// ```
+  pub async fn set_all_groups_muted(&mut self, mute: bool) -> Result<(), ClientError> {
+    let commands: Vec<Method> = self
+      .state
+      .groups_snapshot()
+      .into_iter()
+      .filter(|group| group.muted != mute)
+      .map(|group| Method::GroupSetMute {
+        params: group::SetMuteParams { id: group.id, mute },
+      })
+      .collect();
+
+    if !commands.is_empty() {
+      self.send_batch(commands).await?;
+    }
+
+    Ok(())
+  }
+
+  /// set the clients of a group
+  ///
+  /// wrapper for sending a [GroupSetClients](Method::GroupSetClients) command. clients removed from the group are
+  /// split off into new groups, see [create_group](SnapcastConnection::create_group)
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  /// `clients`: [Vec]<[String]> - the ids of the clients to set, duplicates are dropped
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error. \
+  /// when the state is populated, ids of clients not in the state are rejected with [ClientError::UnknownIds]
+  /// listing every unknown id before anything is sent.
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_clients("group_id".to_string(), vec!["client_id".to_string()]).await.expect("could not set group clients");
+  /// ```
+  pub async fn group_set_clients(&mut self, id: String, clients: Vec<String>) -> Result<(), ClientError> {
+    let command = self.set_clients_command(id, clients)?;
+    self.send(command).await
+  }
+
+  /// gather clients into a group of their own and wait until the state reflects it
+  ///
+  /// snapcast has no request for creating a group, groups are only created and removed as a side effect of
+  /// [GroupSetClients](Method::GroupSetClients): the listed clients are moved into the group, and every client the
+  /// group had that is not listed is split off into a new group of its own. the server generates the id of every new
+  /// group and removes groups that are left without clients.
+  ///
+  /// this sends [GroupSetClients](Method::GroupSetClients) to the group of the first client, so that group keeps its
+  /// id and ends up with exactly `client_ids`. the [server::Server] the server answers with is applied to the state,
+  /// look the group up with the id of any of its clients to find it.
+  ///
+  /// # args
+  /// `client_ids`: [Vec]<[String]> - the ids of the clients of the group, duplicates are dropped
+  ///
+  /// # returns
+  /// an empty [Ok] once the server regrouped the clients, or a [ClientError] if there was an error. \
+  /// the group of the first client is looked up in the state, so [ClientError::InvalidParams] is returned for an
+  /// empty list and [ClientError::NotInState] if the first client is not in any group. the other clients are
+  /// validated like in [SnapcastConnection::group_set_clients].
+  ///
+  /// # example
+  /// ```no_run
+  /// client.create_group(vec!["kitchen".to_string(), "living_room".to_string()]).await.expect("could not create group");
+  /// ```
+  pub async fn create_group(&mut self, client_ids: Vec<String>) -> Result<(), ClientError> {
+    let first = client_ids
+      .first()
+      .ok_or_else(|| ClientError::InvalidParams("a group needs at least one client".to_string()))?;
+    let group = self
+      .state
+      .groups
+      .iter()
+      .find(|group| group.clients.contains(first))
+      .map(|group| group.id.clone())
+      .ok_or_else(|| ClientError::NotInState(first.clone()))?;
+
+    let command = self.set_clients_command(group, client_ids)?;
+    match self.send_and_wait(command).await? {
+      SnapcastResult::GroupSetClients(_) => Ok(()),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
+
+  /// drop duplicate clients and reject clients that are not in a populated state
+  fn set_clients_command(&self, id: String, clients: Vec<String>) -> Result<Method, ClientError> {
+    let mut seen = HashSet::new();
+    let clients: Vec<String> = clients
+      .into_iter()
+      .filter(|client| seen.insert(client.clone()))
+      .collect();
+
+    if !self.state.clients.is_empty() {
+      let mut unknown: Vec<_> = clients
+        .iter()
+        .filter(|client| !self.state.clients.contains_key(*client))
+        .cloned()
+        .collect();
+      if !unknown.is_empty() {
+        unknown.sort();
+        return Err(ClientError::UnknownIds(unknown));
+      }
+    }
+
+    Ok(Method::GroupSetClients {
+      params: group::SetClientsParams { id, clients },
+    })
+  }
+
+  /// set the name of a group
+  ///
+  /// wrapper for sending a [GroupSetName](Method::GroupSetName) command. the name is applied to the state before the
+  /// result arrives if [optimistic updates](SnapcastConnectionBuilder::optimistic_updates) are enabled.
+  ///
+  /// # args
+  /// `id`: [String] - the id of the group
+  /// `name`: [String] - the name to set
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.group_set_name("group_id".to_string(), "new_name".to_string()).await.expect("could not set group name");
+  /// ```
+  pub async fn group_set_name(&mut self, id: String, name: String) -> Result<(), ClientError> {
+    let command = Method::GroupSetName {
+      params: group::SetNameParams {
+        id: id.clone(),
+        name: name.clone(),
+      },
+    };
+
+    self
+      .send_optimistic(command, |state| {
+        let previous = state.group(&id)?.name;
+        let revert = StateUpdate::GroupName {
+          id: id.clone(),
+          name: previous,
+        };
+        Some((StateUpdate::GroupName { id, name }, revert))
+      })
+      .await
+  }
+
+  // server methods
+  /// request the rpc version of the Snapcast server
+  ///
+  /// wrapper for sending a [ServerGetStatus](Method::ServerGetStatus) command
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.server_get_rpc_version().await.expect("could not get server rpc version");
+  /// ```
+  pub async fn server_get_rpc_version(&mut self) -> Result<(), ClientError> {
+    self.send(Method::ServerGetRPCVersion).await
+  }
+
+  /// request the current status of the Snapcast server, this is a full refresh for state
+  ///
+  /// wrapper for sending a [ServerGetStatus](Method::ServerGetStatus) command
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.server_get_status().await.expect("could not get server status");
+  /// ```
+  pub async fn server_get_status(&mut self) -> Result<(), ClientError> {
+    self.send(Method::ServerGetStatus).await
+  }
+
+  /// forcefully delete a client from the Snapcast server
+  ///
+  /// wrapper for sending a [ServerDeleteClient](Method::ServerDeleteClient) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the client to delete
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.server_delete_client("client_id".to_string()).await.expect("could not delete client");
+  /// ```
+  pub async fn server_delete_client(&mut self, id: String) -> Result<(), ClientError> {
+    self
+      .send(Method::ServerDeleteClient {
+        params: server::DeleteClientParams { id },
+      })
+      .await
+  }
+
+  // stream methods
+  /// add a new stream to the Snapcast server
+  ///
+  /// wrapper for sending a [StreamAddStream](Method::StreamAddStream) command
+  ///
+  /// # args
+  /// `stream_uri`: [String] - the uri of the stream to add
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_add_stream("librespot:///usr/bin/librespot?name=Spotify&...".to_string()).await.expect("could not add stream");
+  /// ```
+  pub async fn stream_add_stream(&mut self, stream_uri: String) -> Result<(), ClientError> {
+    self
+      .send(Method::StreamAddStream {
+        params: stream::AddStreamParams { stream_uri },
+      })
+      .await
+  }
+
+  /// remove a stream from the Snapcast server
+  ///
+  /// wrapper for sending a [StreamRemoveStream](Method::StreamRemoveStream) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the stream to remove
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_remove_stream("stream_id".to_string()).await.expect("could not remove stream");
+  /// ```
+  pub async fn stream_remove_stream(&mut self, id: String) -> Result<(), ClientError> {
+    self
+      .send(Method::StreamRemoveStream {
+        params: stream::RemoveStreamParams { id },
+      })
+      .await
+  }
+
+  /// control a stream on the Snapcast server
+  ///
+  /// wrapper for sending a [StreamControl](Method::StreamControl) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the stream to control
+  /// `command`: [stream::ControlCommand] - the command to send to the stream
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error. \
+  /// if [enabled](SnapcastConnectionBuilder::validate_seek), seek targets outside of the known duration of the
+  /// stream are rejected with [ClientError::InvalidParams]. snapservers older than 0.26.0 do not know the method, it
+  /// is rejected with [ClientError::MethodUnavailable] if such a server is in the state.
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_control("stream_id".to_string(), stream::ControlCommand::Pause).await.expect("could not control stream");
+  /// ```
+  pub async fn stream_control(&mut self, id: String, command: stream::ControlCommand) -> Result<(), ClientError> {
+    self.check_snapserver_version(RequestMethod::StreamControl)?;
+    if self.validate_seek {
+      self.check_seek(&id, &command)?;
+    }
+
+    self
+      .send(Method::StreamControl {
+        params: stream::ControlParams { id, command },
+      })
+      .await
+  }
+
+  /// control a stream on the Snapcast server and wait until the server accepted the command
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a [StreamControl](Method::StreamControl)
+  /// command. the server answers with an opaque [stream::ControlResult], so only success is reported. the effect of
+  /// the command, e.g. the new position after a seek, arrives with the next `Stream.OnProperties` notification.
+  ///
+  /// # args
+  /// `id`: [String] - the id of the stream to control
+  /// `command`: [stream::ControlCommand] - the command to send to the stream
+  ///
+  /// # returns
+  /// an empty [Ok] once the server accepted the command, or a [ClientError] if there was an error or the server
+  /// rejected it. seek targets and the snapserver version are validated like in [SnapcastConnection::stream_control].
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_control_and_wait("stream_id".to_string(), stream::ControlCommand::Next).await.expect("could not skip track");
+  /// ```
+  pub async fn stream_control_and_wait(
+    &mut self,
+    id: String,
+    command: stream::ControlCommand,
+  ) -> Result<(), ClientError> {
+    self.check_snapserver_version(RequestMethod::StreamControl)?;
+    if self.validate_seek {
+      self.check_seek(&id, &command)?;
+    }
+
+    let command = Method::StreamControl {
+      params: stream::ControlParams { id, command },
+    };
+    match self.send_and_wait(command).await? {
+      SnapcastResult::StreamControl(_) => Ok(()),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
+
+  /// reject methods the snapserver in the state does not know yet, unknown versions are let through
+  fn check_snapserver_version(&self, method: RequestMethod) -> Result<(), ClientError> {
+    const MINIMUM: &str = "0.26.0";
+
+    let Some(found) = self.state.snapserver_version() else {
+      return Ok(());
+    };
+    match (parse_version(&found), parse_version(MINIMUM)) {
+      (Some(version), Some(minimum)) if version < minimum => Err(ClientError::MethodUnavailable {
+        method,
+        found,
+        minimum: MINIMUM,
+      }),
+      _ => Ok(()),
+    }
+  }
+
+  fn check_seek(&self, id: &str, command: &stream::ControlCommand) -> Result<(), ClientError> {
+    let Some(properties) = self
+      .state
+      .streams
+      .get(id)
+      .and_then(|stream| stream.as_ref().and_then(|stream| stream.properties.clone()))
+    else {
+      return Ok(());
+    };
+    let duration = properties
+      .metadata
+      .as_ref()
+      .and_then(|metadata| metadata.duration_secs());
+
+    let target = match *command {
+      stream::ControlCommand::SetPosition { position } => position,
+      // the stored position is only updated when the properties change, so extrapolate it to now
+      stream::ControlCommand::Seek { offset } => match (properties.position, self.state.stream_progress(id)) {
+        (Some(_), Some(progress)) => progress.elapsed(self.clock.now()).as_secs_f64() + offset,
+        _ => return Ok(()),
+      },
+      _ => return Ok(()),
+    };
+
+    if target < 0.0 || duration.is_some_and(|duration| target > duration) {
+      return Err(ClientError::InvalidParams(format!(
+        "seek target {} is outside of stream {} with duration {:?}",
+        target, id, duration
+      )));
+    }
+
+    Ok(())
+  }
+
+  /// set the property of a stream on the Snapcast server
+  ///
+  /// wrapper for sending a [StreamSetProperty](Method::StreamSetProperty) command
+  ///
+  /// # args
+  /// `id`: [String] - the id of the stream to control
+  /// `properties`: [stream::SetPropertyProperties] - the properties to set on the stream
+  ///
+  /// # returns
+  /// an empty [Ok] if the command was sent successfully, or a [ClientError] if there was an error. snapservers older
+  /// than 0.26.0 do not know the method, it is rejected with [ClientError::MethodUnavailable] if such a server is in
+  /// the state.
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_set_property("stream_id".to_string(), stream::SetPropertyProperties::Shuffle(true)).await.expect("could not set stream property");
+  /// ```
+  pub async fn stream_set_property(
+    &mut self,
+    id: String,
+    properties: stream::SetPropertyProperties,
+  ) -> Result<(), ClientError> {
+    self.check_snapserver_version(RequestMethod::StreamSetProperty)?;
+    self
+      .send(Method::StreamSetProperty {
+        params: stream::SetPropertyParams { id, properties },
+      })
+      .await
+  }
+
+  /// set the property of a stream on the Snapcast server and wait until the server accepted it
+  ///
+  /// wrapper for [send_and_wait](SnapcastConnection::send_and_wait) with a
+  /// [StreamSetProperty](Method::StreamSetProperty) command. the server answers with an opaque
+  /// [stream::SetPropertiesResult], so only success is reported.
+  ///
+  /// # args
+  /// `id`: [String] - the id of the stream to control
+  /// `properties`: [stream::SetPropertyProperties] - the properties to set on the stream
+  ///
+  /// # returns
+  /// an empty [Ok] once the server accepted the property, or a [ClientError] if there was an error or the server
+  /// rejected it. the snapserver version is validated like in [SnapcastConnection::stream_set_property].
+  ///
+  /// # example
+  /// ```no_run
+  /// client.stream_set_property_and_wait("stream_id".to_string(), stream::SetPropertyProperties::Shuffle(true)).await.expect("could not set stream property");
+  /// ```
+  pub async fn stream_set_property_and_wait(
+    &mut self,
+    id: String,
+    properties: stream::SetPropertyProperties,
+  ) -> Result<(), ClientError> {
+    self.check_snapserver_version(RequestMethod::StreamSetProperty)?;
+    let command = Method::StreamSetProperty {
+      params: stream::SetPropertyParams { id, properties },
+    };
+    match self.send_and_wait(command).await? {
+      SnapcastResult::StreamSetProperty(_) => Ok(()),
+      result => Err(ClientError::UnexpectedResult(Box::new(result))),
+    }
+  }
+}
+
+impl Drop for SnapcastConnection {
+  fn drop(&mut self) {
+    if self.purgatory.is_empty() {
+      return;
+    }
+
+    let pending: Vec<RequestMethod> = self.purgatory.iter().map(|entry| entry.value().clone()).collect();
+    tracing::warn!(
+      "snapcast connection dropped with {} requests still waiting for a result: {:?}",
+      pending.len(),
+      pending
+    );
+  }
+}
+
+/// moves a request from purgatory to the forgotten requests once [SnapcastConnection::send_and_wait] returns or is
+/// dropped without its result, expiring the requests forgotten longer than [FORGET_WINDOW] ago
+struct ForgetOnDrop {
+  purgatory: Arc<SentRequests>,
+  forgotten: Arc<ForgottenRequests>,
+  clock: ClockHook,
+  id: Uuid,
+}
+
+impl Drop for ForgetOnDrop {
+  fn drop(&mut self) {
+    if let Some((id, method)) = self.purgatory.remove(&self.id) {
+      let now = self.clock.now();
+      self
+        .forgotten
+        .retain(|_, (forgotten_at, _)| now.saturating_duration_since(*forgotten_at) <= FORGET_WINDOW);
+      self.forgotten.insert(id, (now, method));
+    }
+  }
+}
+
+/// whether `method` only reads from the server, which is still sent in dry-run mode
+fn is_read_only(method: &Method) -> bool {
+  matches!(
+    method,
+    Method::ClientGetStatus { .. }
+      | Method::GroupGetStatus { .. }
+      | Method::ServerGetRPCVersion
+      | Method::ServerGetStatus
+  )
+}
+
+/// whether `method` is a `Set*` command, which can safely be sent more than once
+fn is_set_command(method: &Method) -> bool {
+  matches!(
+    method,
+    Method::ClientSetVolume { .. }
+      | Method::ClientSetLatency { .. }
+      | Method::ClientSetName { .. }
+      | Method::GroupSetMute { .. }
+      | Method::GroupSetStream { .. }
+      | Method::GroupSetClients { .. }
+      | Method::GroupSetName { .. }
+      | Method::StreamSetProperty { .. }
+  )
+}
+
+/// unanswered set-commands kept for [SnapcastConnectionBuilder::resend_on_reconnect], oldest first
+#[derive(Debug)]
+struct ResendQueue {
+  limit: usize,
+  unanswered: VecDeque<(Uuid, Method, usize)>,
+}
+
+impl ResendQueue {
+  fn new(limit: usize) -> Self {
+    Self {
+      limit,
+      unanswered: VecDeque::new(),
+    }
+  }
+
+  fn push(&mut self, id: Uuid, method: Method, connection: usize) {
+    self.unanswered.push_back((id, method, connection));
+    while self.unanswered.len() > self.limit {
+      if let Some((id, method, _)) = self.unanswered.pop_front() {
+        tracing::warn!("resend queue is full, dropping unanswered request {}: {:?}", id, method);
+      }
+    }
+  }
+
+  fn contains(&self, id: Uuid) -> bool {
+    self.unanswered.iter().any(|(unanswered, ..)| *unanswered == id)
+  }
+
+  fn acknowledge(&mut self, id: Uuid) {
+    self.unanswered.retain(|(unanswered, ..)| *unanswered != id);
+  }
+
+  /// the commands written before connection number `connection`, which stay queued until they are acknowledged
+  fn stale(&self, connection: usize) -> Vec<(Uuid, Method)> {
+    self
+      .unanswered
+      .iter()
+      .filter(|(.., written_on)| *written_on < connection)
+      .map(|(id, method, _)| (*id, method.clone()))
+      .collect()
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Communication {
+  purgatory: Arc<SentRequests>,
+  forgotten: Arc<ForgottenRequests>,
+  options: DeserializerOptions,
+  redact_art_in_logs: bool,
+  tape: Option<Tape>,
+  wire_tap: Option<WireTap>,
+  metrics: MetricsHook,
+}
+
+impl Communication {
+  async fn init(
+    transport: &TransportOptions,
+    stream: Option<tokio::net::TcpStream>,
+    codec: Self,
+  ) -> Result<(Sender, Receiver), ClientError> {
+    use futures::stream::StreamExt;
+    use tokio_util::codec::Decoder;
+
+    let stream = match stream {
+      Some(stream) => transport.adopt(stream),
+      None => transport.connect().await.map_err(|err| match err.kind() {
+        std::io::ErrorKind::TimedOut => ClientError::Timeout,
+        _ => err.into(),
+      })?,
+    };
+    let (writer, reader) = codec.framed(stream).split();
+
+    Ok((writer, reader))
+  }
+}
+
+/// a line that could not be decoded is yielded as an [Err] item, so the stream goes on with the next line. only
+/// errors of the underlying io end the stream
+impl tokio_util::codec::Decoder for Communication {
+  type Item = Result<Message, ClientError>;
+  type Error = ClientError;
+
+  fn decode(&mut self, src: &mut tokio_util::bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    use tokio_util::bytes::Buf;
+
+    // blank lines are skipped in a loop, a long run of them must not grow the stack
+    while let Some(lf_pos) = src.as_ref().iter().position(|b| *b == b'\n') {
+      if let Some(wire_tap) = &self.wire_tap {
+        wire_tap.tap(Direction::Inbound, &src[..=lf_pos]);
+      }
+      let mut data = src.split_to(lf_pos);
+      src.advance(1);
+
+      // tolerate \r\n line endings from proxies that normalize them
+      if data.last() == Some(&b'\r') {
+        data.truncate(data.len() - 1);
+      }
+      if data.is_empty() {
+        continue;
+      }
+      self.metrics.on_bytes_received(data.len());
+
+      tracing::debug!("received complete message with length: {}", data.len());
+      let message = match std::str::from_utf8(&data) {
+        Ok(message) => message,
+        Err(err) => {
+          tracing::warn!("received a message that is not valid utf-8: {}", err);
+          return Ok(Some(Err(ClientError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            err,
+          )))));
+        }
+      };
+      if self.redact_art_in_logs && tracing::enabled!(tracing::Level::TRACE) {
+        tracing::trace!("completed json message: {:?}", redact_art_data(message));
+      } else {
+        tracing::trace!("completed json message: {:?}", message);
+      }
+      if let Some(tape) = &self.tape {
+        tape.record(Direction::Inbound, message);
+      }
+
+      let message = SnapcastDeserializer::de_with_forgotten(message, &self.purgatory, &self.forgotten, self.options)
+        .map_err(ClientError::from);
+      tracing::trace!("completed deserialized message: {:?}", message);
+
+      return Ok(Some(message));
+    }
+
+    Ok(None)
+  }
+}
+
+impl tokio_util::codec::Encoder<Request> for Communication {
+  type Error = ClientError;
+
+  fn encode(&mut self, data: Request, dst: &mut tokio_util::bytes::BytesMut) -> Result<(), Self::Error> {
+    tracing::trace!("encoding: {:?}", data);
+
+    let command: RequestMethod = (&data.method).into();
+    tracing::debug!("sending command: {:?}", command);
+    self.purgatory.insert(data.id, command);
+
+    let string: String = data.try_into()?;
+    if let Some(tape) = &self.tape {
+      tape.record(Direction::Outbound, &string);
+    }
+    let string = format!("{}\n", string);
+    tracing::trace!("sending: {:?}", string);
+    if let Some(wire_tap) = &self.wire_tap {
+      wire_tap.tap(Direction::Outbound, string.as_bytes());
+    }
+    self.metrics.on_bytes_sent(string.len());
+
+    dst.extend_from_slice(string.as_bytes());
+
+    Ok(())
+  }
+}
+
+/// parse the leading `major.minor.patch` of a snapserver version like `0.26.0` or `0.27.0-beta.1`
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+  let mut parts = version.split(['.', '-', '+']).map(|part| part.parse::<u32>().ok());
+
+  Some((parts.next()??, parts.next()??, parts.next().flatten().unwrap_or(0)))
+}
+
+/// Error type for the Snapcast client
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
   /// An error returned by the Snapcast server
   #[error("Snapcast error: {0}")]
   Snapcast(#[from] errors::SnapcastError),
+  /// An error returned by the Snapcast server in response to a request sent on this connection
+  #[error("Request {id} ({method:?}) failed: {source}")]
+  Request {
+    /// the id of the failed request
+    id: Uuid,
+    /// the method of the failed request
+    method: RequestMethod,
+    /// the error returned by the server
+    source: errors::SnapcastError,
+  },
   /// An error communicating with the Snapcast server
   #[error("Communication error: {0}")]
   Io(#[from] std::io::Error),
@@ -556,7 +2348,2105 @@ pub enum ClientError {
   /// An error deserializing the json from the Snapcast server
   #[error("JSON Deserialization error: {0}")]
   JsonDeserialization(#[from] serde_json::Error),
+  /// The connection was closed before a response was received
+  #[error("Connection closed")]
+  ConnectionClosed,
+  /// The server's rpc version is older than the minimum supported version
+  #[error("Unsupported rpc version {}.{}.{}, minimum is {}.{}.{}", found.major, found.minor, found.patch, minimum.major, minimum.minor, minimum.patch)]
+  UnsupportedVersion {
+    /// the rpc version reported by the server
+    found: server::GetRpcVersionResult,
+    /// the minimum supported rpc version
+    minimum: server::GetRpcVersionResult,
+  },
+  /// The server responded to a request with a result of an unexpected type
+  #[error("Unexpected result: {0:?}")]
+  UnexpectedResult(Box<SnapcastResult>),
+  /// An operation did not complete in time, e.g. establishing the connection, a [ping](SnapcastConnection::ping), or
+  /// [waiting for a notification](SnapcastConnection::await_notification_timeout)
+  #[error("Timed out")]
+  Timeout,
+  /// The params of a command were rejected before sending
+  #[error("Invalid params: {0}")]
+  InvalidParams(String),
+  /// The requests that were still waiting for a result when [SnapcastConnection::drain] timed out
+  #[error("Unacknowledged requests: {0:?}")]
+  Unacknowledged(Vec<Uuid>),
+  /// The command was not sent because the connection is in dry-run mode, carries the serialized request
+  #[error("Dry run: {0}")]
+  DryRun(String),
+  /// A single id the command acts on does not exist in the state, lists of ids are reported with
+  /// [ClientError::UnknownIds]
+  #[error("Not in state: {0}")]
+  NotInState(String),
+  /// Several ids do not exist in the state, in ascending order
+  #[error("Not in state: {}", .0.join(", "))]
+  UnknownIds(Vec<String>),
+  /// The snapserver in the state is too old for the method, the command was not sent
+  #[error("{method:?} needs snapserver {minimum} or newer, found {found}")]
+  MethodUnavailable {
+    /// the method that was not sent
+    method: RequestMethod,
+    /// the snapserver version in the state
+    found: String,
+    /// the first snapserver version that supports the method
+    minimum: &'static str,
+  },
   /// An unknown error
   #[error("Unknown error: {0}")]
   Unknown(String),
 }
+
+impl ClientError {
+  /// the error for an error response, carrying the request it belongs to if it could be correlated
+  fn response(id: Uuid, method: Option<RequestMethod>, error: errors::SnapcastError) -> Self {
+    match method {
+      Some(method) => ClientError::Request {
+        id,
+        method,
+        source: error,
+      },
+      None => ClientError::Snapcast(error),
+    }
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpListener,
+  };
+
+  use super::*;
+  use crate::RetryDecision;
+
+  pub(crate) const SERVER_STATUS: &str = r#"{"server":{"groups":[{"clients":[{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":false,"name":"","stream_id":"stream 1"}],"server":{"host":{"arch":"x86_64","ip":"","mac":"","name":"T400","os":"Linux Mint 17.3 Rosa"},"snapserver":{"controlProtocolVersion":1,"name":"Snapserver","protocolVersion":1,"version":"0.10.0"}},"streams":[{"id":"stream 1","status":"idle","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{"name":"stream 1"},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}]}}"#;
+
+  /// spawns a server that answers each request with the lines returned by `handler`
+  pub(crate) async fn mock_server<F>(handler: F) -> std::net::SocketAddr
+  where
+    F: Fn(Request) -> Vec<String> + Send + 'static,
+  {
+    use tokio::io::AsyncWriteExt;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+      let (socket, _) = listener.accept().await.unwrap();
+      let (reader, mut writer) = socket.into_split();
+      let mut lines = BufReader::new(reader).lines();
+
+      while let Ok(Some(line)) = lines.next_line().await {
+        for response in handler(serde_json::from_str(&line).unwrap()) {
+          writer.write_all(format!("{}\n", response).as_bytes()).await.unwrap();
+        }
+      }
+    });
+
+    address
+  }
+
+  #[tokio::test]
+  async fn fetch_server_status_buffers_other_messages() {
+    let address = mock_server(|request| {
+      vec![
+        r#"{"jsonrpc":"2.0","method":"Client.OnNameChanged","params":{"id":"00:21:6a:7d:74:fc","name":"Kitchen"}}"#
+          .to_string(),
+        format!(
+          r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+          request.id, SERVER_STATUS
+        ),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    assert!(client.connected_since().is_some());
+    assert!(client.last_message_at().is_none());
+
+    let server = client.fetch_server_status().await.unwrap();
+    assert!(client.last_message_at().is_some());
+
+    assert_eq!(server.groups[0].clients[0].id, "00:21:6a:7d:74:fc");
+    assert!(client.state.clients.contains_key("00:21:6a:7d:74:fc"));
+    assert!(matches!(
+      client.recv().await,
+      Some(Ok(ValidMessage::Notification { .. }))
+    ));
+  }
+
+  #[tokio::test]
+  async fn notifications_skip_results() {
+    use futures::StreamExt;
+
+    let address = mock_server(|request| {
+      vec![
+        format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#, request.id, SERVER_STATUS),
+        r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#
+          .to_string(),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.server_get_status().await.unwrap();
+
+    let notification = Box::pin(client.notifications()).next().await.unwrap();
+    assert!(matches!(notification, Notification::GroupOnMute { .. }));
+    assert!(
+      client
+        .state
+        .groups
+        .get("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1")
+        .unwrap()
+        .muted
+    );
+  }
+
+  #[tokio::test]
+  async fn stream_messages() {
+    use futures::StreamExt;
+
+    let address = mock_server(|request| {
+      vec![
+        format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#, request.id, SERVER_STATUS),
+        r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#
+          .to_string(),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    client.server_get_status().await.unwrap();
+
+    let first = Box::pin(client.messages()).next().await.unwrap();
+    assert!(matches!(first, Ok(ValidMessage::Result { .. })));
+
+    let state = client.state.clone();
+    let mut messages = Box::pin(client.into_stream());
+    let second = messages.next().await.unwrap();
+    assert!(matches!(second, Ok(ValidMessage::Notification { .. })));
+    assert!(state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap().muted);
+  }
+
+  #[tokio::test]
+  async fn send_and_wait_returns_server_errors() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","error":{{"code":-32603,"message":"Internal error"}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    let result = client.fetch_group_status("unknown".to_string()).await;
+
+    assert!(matches!(
+      result,
+      Err(ClientError::Request {
+        method: RequestMethod::GroupGetStatus,
+        source: errors::SnapcastError::InternalError(_),
+        ..
+      })
+    ));
+
+    // errors of batched requests point back to the request that failed
+    let ids = client
+      .send_batch(vec![Method::ServerGetRPCVersion, Method::ServerGetStatus])
+      .await
+      .unwrap();
+    let error = client.recv().await.unwrap().unwrap_err();
+    assert!(
+      matches!(error, ClientError::Request { id, method: RequestMethod::ServerGetRPCVersion, .. } if id == ids[0])
+    );
+    client.recv().await.unwrap().unwrap_err();
+    assert!(client.pending().is_empty());
+  }
+
+  #[tokio::test]
+  async fn reject_unsupported_version() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"major":1,"minor":2,"patch":0}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let result = SnapcastConnection::builder()
+      .min_supported_version(server::TESTED_RPC_VERSION)
+      .reject_unsupported_version(true)
+      .connect(address)
+      .await;
+
+    assert!(matches!(result, Err(ClientError::UnsupportedVersion { found, .. }) if found.major == 1));
+  }
+
+  #[tokio::test]
+  async fn recv_ends_without_reconnect() {
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (socket, _) = listener.accept().await.unwrap();
+      drop(socket);
+    });
+
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let mut client = SnapcastConnection::builder()
+      .reconnect(false)
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert!(client.recv().await.is_none());
+    assert!(client.recv().await.is_none());
+    assert_eq!(
+      *statuses.lock().unwrap(),
+      vec![ConnectionStatus::Connected, ConnectionStatus::Disconnected]
+    );
+  }
+
+  #[tokio::test]
+  async fn recv_continues_after_undecodable_message() {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (mut socket, _) = listener.accept().await.unwrap();
+      socket
+        .write_all(
+          b"{\"id\":\"00000000-0000-0000-0000-000000000000\",\"jsonrpc\":\"2.0\",\"result\":{\"unknown\":1}}\n\xff\n",
+        )
+        .await
+        .unwrap();
+      socket
+        .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnMute\",\"params\":{\"id\":\"group\",\"mute\":true}}\n")
+        .await
+        .unwrap();
+      std::future::pending::<()>().await;
+    });
+
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let mut client = SnapcastConnection::builder()
+      .reconnect(false)
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert!(matches!(
+      client.recv().await,
+      Some(Err(ClientError::Deserialization(_)))
+    ));
+    assert!(
+      matches!(client.recv().await, Some(Err(ClientError::Io(err))) if err.kind() == std::io::ErrorKind::InvalidData)
+    );
+    let message = tokio::time::timeout(Duration::from_millis(500), client.recv())
+      .await
+      .unwrap();
+    assert!(matches!(
+      message,
+      Some(Ok(ValidMessage::Notification { method, .. })) if matches!(*method, Notification::GroupOnMute { .. })
+    ));
+    assert_eq!(*statuses.lock().unwrap(), vec![ConnectionStatus::Connected]);
+  }
+
+  #[tokio::test]
+  async fn recv_waits_for_reconnect() {
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (socket, _) = listener.accept().await.unwrap();
+      drop(socket);
+    });
+
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert!(tokio::time::timeout(Duration::from_millis(200), client.recv())
+      .await
+      .is_err());
+    assert_eq!(
+      *statuses.lock().unwrap(),
+      vec![ConnectionStatus::Connected, ConnectionStatus::Disconnected]
+    );
+  }
+
+  #[tokio::test]
+  async fn reconnect_on_demand() {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+      // leave the first request unanswered, the old socket must be closed by the reconnect
+      let (mut socket, _) = listener.accept().await.unwrap();
+      let (socket, _) = tokio::join!(listener.accept(), async {
+        let mut buffer = Vec::new();
+        socket.read_to_end(&mut buffer).await.unwrap();
+      });
+
+      let (reader, mut writer) = socket.unwrap().0.into_split();
+      let mut lines = BufReader::new(reader).lines();
+      let request: Request = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+      let response = format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#, request.id);
+      tokio::io::AsyncWriteExt::write_all(&mut writer, format!("{}\n", response).as_bytes())
+        .await
+        .unwrap();
+      lines.next_line().await
+    });
+
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .connect(address)
+      .await
+      .unwrap();
+
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    assert_eq!(client.sent_requests().len(), 1);
+
+    client.reconnect().await.unwrap();
+    assert!(client.sent_requests().is_empty());
+    {
+      let statuses = statuses.lock().unwrap();
+      assert_eq!(
+        statuses[..3],
+        [
+          ConnectionStatus::Connected,
+          ConnectionStatus::Disconnected,
+          ConnectionStatus::Connected
+        ]
+      );
+      assert!(matches!(statuses[3], ConnectionStatus::Reconnected { .. }));
+    }
+
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    assert!(matches!(
+      client.recv().await.unwrap().unwrap(),
+      ValidMessage::Result { .. }
+    ));
+
+    drop(client);
+    assert!(server.await.unwrap().unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn reconnect_attempt_can_stop() {
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      // close the listener with the connection, so reconnecting fails
+      let (socket, _) = listener.accept().await.unwrap();
+      drop(socket);
+    });
+
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let attempts = Arc::new(Mutex::new(vec![]));
+    let made = attempts.clone();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .on_reconnect_attempt(move |attempt| {
+        made.lock().unwrap().push(attempt);
+        match attempt {
+          1 => RetryDecision::Backoff(Duration::from_millis(10)),
+          _ => RetryDecision::Stop,
+        }
+      })
+      .connect(address)
+      .await
+      .unwrap();
+
+    let ended = tokio::time::timeout(Duration::from_secs(2), async {
+      while let Some(message) = client.recv().await {
+        assert!(message.is_err());
+      }
+    });
+    ended.await.unwrap();
+
+    assert_eq!(*attempts.lock().unwrap(), vec![1, 2]);
+    assert_eq!(
+      *statuses.lock().unwrap(),
+      vec![
+        ConnectionStatus::Connected,
+        ConnectionStatus::Disconnected,
+        ConnectionStatus::ConnectFailed,
+        ConnectionStatus::ReconnectFailed
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn take_over_connected_stream() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )]
+    })
+    .await;
+
+    let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect_stream(stream)
+      .await
+      .unwrap();
+    assert_eq!(client.peer_addr(), Some(address));
+    assert!(client.connected_since().is_some());
+
+    client.fetch_server_status().await.unwrap();
+    assert!(client.state.clients.contains_key("00:21:6a:7d:74:fc"));
+
+    let result = client.reconnect().await;
+    assert!(matches!(result, Err(ClientError::Io(err)) if err.kind() == std::io::ErrorKind::Unsupported));
+    assert!(client.connected_since().is_some());
+  }
+
+  #[tokio::test]
+  async fn failed_reconnect_keeps_old_connection() {
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let connecting = SnapcastConnection::builder()
+      .reconnect(false)
+      .auto_refresh_on_connect(false)
+      .on_status(move |status| recorded.lock().unwrap().push(status))
+      .connect(address);
+    let (client, socket) = tokio::join!(connecting, listener.accept());
+    let (mut client, _socket) = (client.unwrap(), socket.unwrap());
+
+    // nothing accepts the new connection anymore
+    drop(listener);
+    assert!(client.reconnect().await.is_err());
+    assert!(client.connected_since().is_some());
+    assert_eq!(
+      *statuses.lock().unwrap(),
+      vec![ConnectionStatus::Connected, ConnectionStatus::ConnectFailed]
+    );
+  }
+
+  #[tokio::test]
+  async fn create_group_uses_group_of_first_client() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method.clone()).unwrap();
+      let mut status: serde_json::Value = serde_json::from_str(SERVER_STATUS).unwrap();
+      if let Method::GroupSetClients { .. } = request.method {
+        status["server"]["groups"][0]["name"] = "Together".into();
+      }
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, status
+      )]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    assert!(matches!(
+      client.create_group(vec![]).await,
+      Err(ClientError::InvalidParams(_))
+    ));
+    assert!(matches!(
+      client.create_group(vec!["00:21:6a:7d:74:fc".to_string()]).await,
+      Err(ClientError::NotInState(_))
+    ));
+
+    client.fetch_server_status().await.unwrap();
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+    client
+      .create_group(vec!["00:21:6a:7d:74:fc".to_string(), "00:21:6a:7d:74:fc".to_string()])
+      .await
+      .unwrap();
+    assert_eq!(
+      received.recv().await,
+      Some(Method::GroupSetClients {
+        params: group::SetClientsParams {
+          id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+          clients: vec!["00:21:6a:7d:74:fc".to_string()],
+        }
+      })
+    );
+    assert_eq!(
+      client.state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap().name,
+      "Together"
+    );
+  }
+
+  #[tokio::test]
+  async fn initial_state_until_status_arrives() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )]
+    })
+    .await;
+
+    let mut cached: server::Server =
+      serde_json::from_value(serde_json::from_str::<serde_json::Value>(SERVER_STATUS).unwrap()["server"].clone())
+        .unwrap();
+    cached.groups[0].name = "Cached".to_string();
+
+    let mut client = SnapcastConnection::builder()
+      .with_initial_state(cached)
+      .connect(address)
+      .await
+      .unwrap();
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1";
+    assert_eq!(client.state.group(group).unwrap().name, "Cached");
+    assert!(client.state.is_unconfirmed(group));
+    assert!(!client.state.is_ready());
+
+    client.recv().await.unwrap().unwrap();
+    assert_eq!(client.state.group(group).unwrap().name, "");
+    assert!(!client.state.is_unconfirmed(group));
+    assert!(client.state.is_ready());
+  }
+
+  #[tokio::test]
+  async fn recv_until_cancelled() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    let token = CancellationToken::new();
+
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    assert!(matches!(
+      client.recv_until(token.clone()).await,
+      Some(Ok(ValidMessage::Result { .. }))
+    ));
+
+    let cancel = token.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      cancel.cancel();
+    });
+    assert!(client.recv_until(token.clone()).await.is_none());
+    assert!(client.recv_until(token).await.is_none());
+  }
+
+  #[tokio::test]
+  async fn send_batch_writes_every_request() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+      let (socket, _) = listener.accept().await.unwrap();
+      let mut lines = BufReader::new(socket).lines();
+
+      let mut requests = vec![];
+      for _ in 0..2 {
+        let line = lines.next_line().await.unwrap().unwrap();
+        requests.push(serde_json::from_str::<Request>(&line).unwrap());
+      }
+
+      requests
+    });
+
+    let mut client = SnapcastConnection::open(address).await;
+    let ids = client
+      .send_batch(vec![Method::ServerGetRPCVersion, Method::ServerGetStatus])
+      .await
+      .unwrap();
+
+    let requests = server.await.unwrap();
+    assert_eq!(ids, requests.iter().map(|r| r.id).collect::<Vec<_>>());
+    assert_eq!(requests[0].method, Method::ServerGetRPCVersion);
+    assert_eq!(requests[1].method, Method::ServerGetStatus);
+  }
+
+  #[tokio::test]
+  async fn group_set_clients_dedups_and_validates() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      method => {
+        requests.send(method).unwrap();
+        vec![]
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.fetch_server_status().await.unwrap();
+
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string();
+    let result = client
+      .group_set_clients(
+        group.clone(),
+        vec![
+          "00:21:6a:7d:74:fe".to_string(),
+          "00:21:6a:7d:74:fc".to_string(),
+          "00:21:6a:7d:74:fd".to_string(),
+        ],
+      )
+      .await;
+    assert!(matches!(result, Err(ClientError::UnknownIds(ids)) if ids == ["00:21:6a:7d:74:fd", "00:21:6a:7d:74:fe"]));
+
+    client
+      .group_set_clients(
+        group.clone(),
+        vec!["00:21:6a:7d:74:fc".to_string(), "00:21:6a:7d:74:fc".to_string()],
+      )
+      .await
+      .unwrap();
+    assert_eq!(
+      received.recv().await.unwrap(),
+      Method::GroupSetClients {
+        params: group::SetClientsParams {
+          id: group,
+          clients: vec!["00:21:6a:7d:74:fc".to_string()],
+        },
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn handles_send_their_id() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method).unwrap();
+      vec![]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    let mut group = client.group_handle("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1");
+    group.set_mute(true).await.unwrap();
+    group.set_name("Kitchen".to_string()).await.unwrap();
+    client.client_handle("00:21:6a:7d:74:fc").set_latency(20).await.unwrap();
+
+    assert_eq!(
+      received.recv().await.unwrap(),
+      Method::GroupSetMute {
+        params: group::SetMuteParams {
+          id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+          mute: true,
+        },
+      }
+    );
+    assert!(matches!(
+      received.recv().await.unwrap(),
+      Method::GroupSetName { params } if params.name == "Kitchen"
+    ));
+    assert!(matches!(
+      received.recv().await.unwrap(),
+      Method::ClientSetLatency { params } if params.id == "00:21:6a:7d:74:fc" && params.latency == 20
+    ));
+  }
+
+  #[tokio::test]
+  async fn stream_control_validates_seek() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method).unwrap();
+      vec![
+        r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"stream 1","properties":{"canControl":true,"canGoNext":false,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":true,"position":30.0,"metadata":{"duration":100.0}}}}"#
+          .to_string(),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .validate_seek(true)
+      .connect(address)
+      .await
+      .unwrap();
+    client
+      .state
+      .streams
+      .insert("stream 1".to_string(), Some(serde_json::from_str(r#"{"id":"stream 1","status":"playing","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}"#).unwrap()));
+
+    let stream = "stream 1".to_string();
+    client
+      .stream_control(stream.clone(), stream::ControlCommand::Seek { offset: 500.0 })
+      .await
+      .unwrap();
+    client.recv().await.unwrap().unwrap();
+
+    let result = client
+      .stream_control(stream.clone(), stream::ControlCommand::Seek { offset: -40.0 })
+      .await;
+    assert!(matches!(result, Err(ClientError::InvalidParams(_))));
+    let result = client
+      .stream_control(stream.clone(), stream::ControlCommand::SetPosition { position: 101.0 })
+      .await;
+    assert!(matches!(result, Err(ClientError::InvalidParams(_))));
+
+    client
+      .stream_control(stream, stream::ControlCommand::Seek { offset: 60.0 })
+      .await
+      .unwrap();
+    assert!(matches!(received.recv().await, Some(Method::StreamControl { .. })));
+    assert!(
+      matches!(received.recv().await, Some(Method::StreamControl { params }) if params.command == stream::ControlCommand::Seek { offset: 60.0 })
+    );
+  }
+
+  #[tokio::test]
+  async fn seek_validation_extrapolates_stale_position() {
+    let clock = crate::ManualClock::new();
+    let address = mock_server(|_| vec![]).await;
+    let mut client = SnapcastConnection::builder()
+      .clock(clock.clone())
+      .auto_refresh_on_connect(false)
+      .validate_seek(true)
+      .connect(address)
+      .await
+      .unwrap();
+    client.state.apply(StateUpdate::Stream {
+      id: "stream 1".to_string(),
+      stream: Some(Box::new(serde_json::from_str(r#"{"id":"stream 1","status":"playing","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"},"properties":{"canControl":true,"canGoNext":false,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":true,"playbackStatus":"playing","position":9.8,"metadata":{"duration":10.0}}}"#).unwrap())),
+    });
+    let stream = "stream 1".to_string();
+
+    // the stored position of 9.8 seconds is out of date once the track played on
+    clock.advance(Duration::from_millis(300));
+    let result = client
+      .stream_control(stream.clone(), stream::ControlCommand::Seek { offset: 0.1 })
+      .await;
+    assert!(matches!(result, Err(ClientError::InvalidParams(_))));
+    client
+      .stream_control(stream, stream::ControlCommand::Seek { offset: -9.9 })
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn custom_id_generator() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+      let (socket, _) = listener.accept().await.unwrap();
+      let mut lines = BufReader::new(socket).lines();
+
+      let mut requests = vec![];
+      for _ in 0..2 {
+        requests.push(lines.next_line().await.unwrap().unwrap());
+      }
+
+      requests
+    });
+
+    let counter = AtomicU64::new(0);
+    let mut client = SnapcastConnection::builder()
+      .id_generator(move || Uuid::from_u128(counter.fetch_add(1, Ordering::Relaxed).into()))
+      .connect(address)
+      .await
+      .unwrap();
+    client.server_get_rpc_version().await.unwrap();
+    client.server_get_status().await.unwrap();
+
+    assert_eq!(
+      server.await.unwrap(),
+      vec![
+        r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","method":"Server.GetRPCVersion"}"#,
+        r#"{"id":"00000000-0000-0000-0000-000000000001","jsonrpc":"2.0","method":"Server.GetStatus"}"#,
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn pending_requests() {
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.server_get_rpc_version().await.unwrap();
+    client.fetch_server_status().await.unwrap();
+
+    let pending = client.pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].1, RequestMethod::ServerGetRPCVersion);
+  }
+
+  #[tokio::test]
+  async fn bind_local_address() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let local = std::net::TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+
+    let server = tokio::spawn(async move { listener.accept().await.unwrap().1 });
+    let client = SnapcastConnection::builder()
+      .reconnect(false)
+      .bind_local(local)
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert_eq!(server.await.unwrap(), local);
+    assert_eq!(client.peer_addr(), Some(address));
+  }
+
+  #[tokio::test]
+  async fn set_all_groups_muted_skips_muted_groups() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      method => {
+        requests.send(method).unwrap();
+        vec![]
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.fetch_server_status().await.unwrap();
+
+    client.set_all_groups_muted(false).await.unwrap();
+    client.set_all_groups_muted(true).await.unwrap();
+
+    assert!(matches!(
+      received.recv().await.unwrap(),
+      Method::GroupSetMute { params } if params.mute
+    ));
+    assert!(received.try_recv().is_err());
+  }
+
+  #[tokio::test]
+  async fn set_client_volumes_in_one_batch() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      method => {
+        requests.send(method).unwrap();
+        vec![]
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.fetch_server_status().await.unwrap();
+
+    let result = client
+      .set_client_volumes(HashMap::from([
+        ("00:21:6a:7d:74:fc".to_string(), client::ClientVolume::unmuted(80)),
+        ("kitchen".to_string(), client::ClientVolume::unmuted(20)),
+        ("attic".to_string(), client::ClientVolume::unmuted(20)),
+      ]))
+      .await;
+    assert!(matches!(result, Err(ClientError::UnknownIds(ids)) if ids == ["attic", "kitchen"]));
+
+    let result = client
+      .set_client_volumes(HashMap::from([(
+        "00:21:6a:7d:74:fc".to_string(),
+        client::ClientVolume {
+          muted: false,
+          percent: 120,
+        },
+      )]))
+      .await;
+    assert!(matches!(result, Err(ClientError::InvalidParams(_))));
+
+    let ids = client
+      .set_client_volumes(HashMap::from([(
+        "00:21:6a:7d:74:fc".to_string(),
+        client::ClientVolume::muted(30),
+      )]))
+      .await
+      .unwrap();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(
+      client.sent_requests().method(&ids[0]),
+      Some(RequestMethod::ClientSetVolume("00:21:6a:7d:74:fc".to_string()))
+    );
+
+    let Method::ClientSetVolume { params } = received.recv().await.unwrap() else {
+      panic!("expected a client volume");
+    };
+    assert_eq!(params.volume, client::ClientVolume::muted(30));
+    assert!(received.try_recv().is_err());
+  }
+
+  #[tokio::test]
+  async fn group_set_volume_sets_client_volumes() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      method => {
+        requests.send(method).unwrap();
+        vec![]
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.fetch_server_status().await.unwrap();
+    let group = client.state.groups_snapshot()[0].clone();
+    let volume = client.state.group_volume(&group.id).unwrap().average;
+
+    client.group_set_volume(group.id.clone(), volume).await.unwrap();
+    client.group_set_volume(group.id.clone(), 20).await.unwrap();
+    assert!(matches!(
+      client.group_set_volume("missing".to_string(), 20).await,
+      Err(ClientError::NotInState(_))
+    ));
+
+    let Method::ClientSetVolume { params } = received.recv().await.unwrap() else {
+      panic!("expected a client volume");
+    };
+    assert!(group.clients.contains(&params.id));
+    assert_eq!(params.volume.percent, 20);
+    assert!(received.try_recv().is_err());
+  }
+
+  #[tokio::test]
+  async fn resend_unanswered_after_reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+      // drop the first connection without answering
+      let (socket, _) = listener.accept().await.unwrap();
+      let mut lines = BufReader::new(socket).lines();
+      let dropped: Request = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+      drop(lines);
+
+      let (socket, _) = listener.accept().await.unwrap();
+      let (reader, mut writer) = socket.into_split();
+      let mut lines = BufReader::new(reader).lines();
+      let resent: Request = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+      let response = format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#, resent.id);
+      tokio::io::AsyncWriteExt::write_all(&mut writer, format!("{}\n", response).as_bytes())
+        .await
+        .unwrap();
+
+      (dropped, resent)
+    });
+
+    let mut client = SnapcastConnection::builder()
+      .resend_on_reconnect(8)
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+
+    let message = client.recv().await.unwrap().unwrap();
+    assert!(matches!(
+      message,
+      ValidMessage::Result { result, .. } if matches!(*result, SnapcastResult::GroupSetMute(ref id, _) if id == "group")
+    ));
+
+    let (dropped, resent) = server.await.unwrap();
+    assert_eq!(dropped.method, resent.method);
+    assert_ne!(dropped.id, resent.id);
+    assert!(client.pending().is_empty());
+  }
+
+  #[test]
+  fn stale_commands_stay_queued_until_acknowledged() {
+    let mut queue = ResendQueue::new(8);
+    let (old, current) = (Uuid::new_v4(), Uuid::new_v4());
+    queue.push(old, Method::ServerGetStatus, 1);
+    queue.push(current, Method::ServerGetRPCVersion, 2);
+
+    assert_eq!(queue.stale(2), vec![(old, Method::ServerGetStatus)]);
+    // taking a look does not remove them, only the acknowledgement after re-sending does
+    assert_eq!(queue.stale(2).len(), 1);
+    queue.acknowledge(old);
+    assert!(queue.stale(2).is_empty());
+    assert_eq!(queue.stale(3), vec![(current, Method::ServerGetRPCVersion)]);
+  }
+
+  #[tokio::test]
+  async fn auto_refresh_on_connect() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let server = move || {
+      let requests = requests.clone();
+      mock_server(move |request| {
+        requests.send(request.method).unwrap();
+        vec![format!(
+          r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+          request.id, SERVER_STATUS
+        )]
+      })
+    };
+
+    let mut client = SnapcastConnection::open(server().await).await;
+    client.recv().await.unwrap().unwrap();
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+    assert!(client.state.is_ready());
+
+    // not requested twice when the user already asked for the status
+    let mut client = SnapcastConnection::open(server().await).await;
+    client.server_get_status().await.unwrap();
+    client.recv().await.unwrap().unwrap();
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+    assert!(received.try_recv().is_err());
+  }
+
+  #[test]
+  fn decode_crlf_lines() {
+    use tokio_util::codec::Decoder;
+
+    let mut codec = Communication::default();
+    let mut src = tokio_util::bytes::BytesMut::from(
+      "{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnMute\",\"params\":{\"id\":\"group\",\"mute\":true}}\r\n\r\n{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnNameChanged\",\"params\":{\"id\":\"group\",\"name\":\"Kitchen\"}}\r\n",
+    );
+
+    let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+    assert!(
+      matches!(message, Message::Notification { method, .. } if matches!(*method, Notification::GroupOnMute { .. }))
+    );
+    let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+    assert!(matches!(
+      message,
+      Message::Notification { method, .. } if matches!(*method, Notification::GroupOnNameChanged { ref params } if params.name == "Kitchen")
+    ));
+    assert!(codec.decode(&mut src).unwrap().is_none());
+    assert!(src.is_empty());
+  }
+
+  #[test]
+  fn wire_tap_sees_raw_lines() {
+    use std::sync::{Arc, Mutex};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let lines = Arc::new(Mutex::new(vec![]));
+    let tapped = lines.clone();
+    let mut codec = Communication {
+      wire_tap: Some(WireTap::new(move |direction, bytes| {
+        tapped.lock().unwrap().push((direction, bytes.to_vec()))
+      })),
+      ..Default::default()
+    };
+
+    let mut dst = tokio_util::bytes::BytesMut::new();
+    codec
+      .encode(
+        Request {
+          id: Uuid::nil(),
+          jsonrpc: JsonRpcVersion,
+          method: Method::ServerGetStatus,
+        },
+        &mut dst,
+      )
+      .unwrap();
+
+    let mut src = tokio_util::bytes::BytesMut::from(
+      "{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnMute\",\"params\":{\"id\":\"group\",\"mute\":true}}\r\n\n",
+    );
+    while codec.decode(&mut src).unwrap().is_some() {}
+
+    assert_eq!(
+      *lines.lock().unwrap(),
+      vec![
+        (Direction::Outbound, dst.to_vec()),
+        (
+          Direction::Inbound,
+          b"{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnMute\",\"params\":{\"id\":\"group\",\"mute\":true}}\r\n".to_vec()
+        ),
+        (Direction::Inbound, b"\n".to_vec()),
+      ]
+    );
+  }
+
+  #[test]
+  fn trace_logs_redact_art_data() {
+    use std::{
+      fmt::Write,
+      sync::{Arc, Mutex},
+    };
+    use tokio_util::codec::Decoder;
+    use tracing::{
+      field::{Field, Visit},
+      span, Event, Metadata, Subscriber,
+    };
+
+    /// collects the fields of every event
+    struct Collector(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a mut String);
+
+    impl Visit for Fields<'_> {
+      fn record_debug(&mut self, _field: &Field, value: &dyn std::fmt::Debug) {
+        write!(self.0, "{:?} ", value).unwrap();
+      }
+    }
+
+    impl Subscriber for Collector {
+      fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+      }
+
+      fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+      }
+
+      fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+      fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+      fn event(&self, event: &Event<'_>) {
+        let mut line = String::new();
+        event.record(&mut Fields(&mut line));
+        self.0.lock().unwrap().push(line);
+      }
+
+      fn enter(&self, _span: &span::Id) {}
+
+      fn exit(&self, _span: &span::Id) {}
+    }
+
+    let art = "PD94bWwgdmVyc2lvbj0iMS4wIj8+PHN2ZyB4bWxucz0iaHR0cDovL3d3dy53My5vcmcvMjAwMC9zdmciLz4=";
+    let lines = Arc::new(Mutex::new(vec![]));
+    tracing::subscriber::with_default(Collector(lines.clone()), || {
+      let mut codec = Communication {
+        redact_art_in_logs: true,
+        ..Default::default()
+      };
+      let mut src = tokio_util::bytes::BytesMut::from(
+        format!(
+          r#"{{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{{"id":"Spotify","properties":{{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{{"artData":{{"data":"{}","extension":"svg"}},"title":"leave in five"}}}}}}}}{}"#,
+          art, "\n"
+        )
+        .as_str(),
+      );
+      assert!(codec.decode(&mut src).unwrap().is_some());
+    });
+
+    let lines = lines.lock().unwrap();
+    assert!(lines.iter().any(|line| line.contains("leave in five")));
+    assert!(lines.iter().all(|line| !line.contains(art)), "{:?}", lines);
+  }
+
+  #[test]
+  fn decode_many_blank_lines() {
+    use tokio_util::codec::Decoder;
+
+    let mut codec = Communication::default();
+    let mut src = tokio_util::bytes::BytesMut::from("\n".repeat(1 << 20).as_str());
+    src.extend_from_slice(
+      b"{\"jsonrpc\":\"2.0\",\"method\":\"Group.OnMute\",\"params\":{\"id\":\"group\",\"mute\":true}}\n",
+    );
+
+    let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+    assert!(
+      matches!(message, Message::Notification { method, .. } if matches!(*method, Notification::GroupOnMute { .. }))
+    );
+    assert!(src.is_empty());
+  }
+
+  #[test]
+  fn decode_burst_in_order() {
+    use tokio_util::codec::Decoder;
+
+    // the framed codec calls decode until it returns None before reading more, so a burst is drained from the buffer
+    let mut codec = Communication::default();
+    let mut src = tokio_util::bytes::BytesMut::new();
+    for percent in [10, 20, 30] {
+      src.extend_from_slice(
+        format!(
+          "{{\"jsonrpc\":\"2.0\",\"method\":\"Client.OnVolumeChanged\",\"params\":{{\"id\":\"client\",\"volume\":{{\"muted\":false,\"percent\":{}}}}}}}\n",
+          percent
+        )
+        .as_bytes(),
+      );
+    }
+
+    for percent in [10, 20, 30] {
+      let message = codec.decode(&mut src).unwrap().unwrap().unwrap();
+      assert!(matches!(
+        message,
+        Message::Notification { method, .. } if matches!(*method, Notification::ClientOnVolumeChanged { ref params } if params.volume.percent == percent)
+      ));
+    }
+    assert!(codec.decode(&mut src).unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn metrics_count_traffic() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counters {
+      notifications: AtomicUsize,
+      results: AtomicUsize,
+      errors: AtomicUsize,
+      received: AtomicUsize,
+      sent: AtomicUsize,
+    }
+
+    impl crate::Metrics for Arc<Counters> {
+      fn on_notification(&self, _notification: &Notification) {
+        self.notifications.fetch_add(1, Ordering::Relaxed);
+      }
+
+      fn on_result(&self, _result: &SnapcastResult) {
+        self.results.fetch_add(1, Ordering::Relaxed);
+      }
+
+      fn on_error(&self, _error: &ClientError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+      }
+
+      fn on_bytes_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+      }
+
+      fn on_bytes_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+      }
+    }
+
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetStatus => vec![
+        format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#, request.id, SERVER_STATUS),
+        r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#
+          .to_string(),
+      ],
+      _ => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","error":{{"code":-32601,"message":"Method not found"}}}}"#,
+        request.id
+      )],
+    })
+    .await;
+
+    let counters = Arc::new(Counters::default());
+    let mut client = SnapcastConnection::builder()
+      .metrics(counters.clone())
+      .connect(address)
+      .await
+      .unwrap();
+    client.recv().await.unwrap().unwrap();
+    client.recv().await.unwrap().unwrap();
+    assert!(client.send_and_wait(Method::ServerGetRPCVersion).await.is_err());
+
+    assert_eq!(counters.results.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.notifications.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.errors.load(Ordering::Relaxed), 1);
+    assert!(counters.received.load(Ordering::Relaxed) > SERVER_STATUS.len());
+    assert!(counters.sent.load(Ordering::Relaxed) > 0);
+  }
+
+  #[tokio::test]
+  async fn dry_run_holds_back_changes() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method).unwrap();
+      vec![]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .dry_run(true)
+      .connect(address)
+      .await
+      .unwrap();
+    client.server_get_status().await.unwrap();
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    let result = client
+      .send_and_wait(Method::GroupSetName {
+        params: group::SetNameParams {
+          id: "group".to_string(),
+          name: "Kitchen".to_string(),
+        },
+      })
+      .await;
+
+    assert!(matches!(result, Err(ClientError::DryRun(request)) if request.contains("Group.SetName")));
+    let held_back = client.take_dry_run_requests();
+    assert_eq!(held_back.len(), 2);
+    assert!(held_back[0].contains(r#""method":"Group.SetMute""#));
+    assert!(client.take_dry_run_requests().is_empty());
+
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+    assert!(received.try_recv().is_err());
+    assert_eq!(client.pending().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn ping_measures_round_trip() {
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetRPCVersion => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"major":2,"minor":0,"patch":0}}}}"#,
+        request.id
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .ping_timeout(Duration::from_millis(200))
+      .connect(address)
+      .await
+      .unwrap();
+    let latency = client.ping().await.unwrap();
+    assert!(latency < Duration::from_millis(200));
+
+    client.server_get_status().await.unwrap();
+    assert!(client.ping().await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn fetch_rpc_version_returns_version() {
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetRPCVersion => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"major":2,"minor":1,"patch":0}}}}"#,
+        request.id
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert_eq!(client.fetch_rpc_version().await.unwrap().as_tuple(), (2, 1, 0));
+  }
+
+  #[cfg(feature = "strict")]
+  #[tokio::test]
+  async fn strict_result_with_unknown_field_is_an_error() {
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetStatus => {
+        let mut status: serde_json::Value = serde_json::from_str(SERVER_STATUS).unwrap();
+        status["server"]["server"]["host"]["uptime"] = 42.into();
+        vec![format!(
+          r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+          request.id, status
+        )]
+      }
+      _ => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"major":2,"minor":0,"patch":0}}}}"#,
+        request.id
+      )],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .reconnect(false)
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    client.server_get_status().await.unwrap();
+    assert!(matches!(
+      client.recv().await,
+      Some(Err(ClientError::Deserialization(_)))
+    ));
+    assert_eq!(client.fetch_rpc_version().await.unwrap().as_tuple(), (2, 0, 0));
+  }
+
+  #[tokio::test]
+  async fn ping_times_out() {
+    let address = mock_server(|_| vec![]).await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .ping_timeout(Duration::from_millis(50))
+      .connect(address)
+      .await
+      .unwrap();
+    assert!(matches!(client.ping().await, Err(ClientError::Timeout)));
+    assert!(client.pending().is_empty());
+  }
+
+  #[tokio::test]
+  async fn prefetch_stream_properties_on_connect() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method.clone()).unwrap();
+      vec![
+        format!(r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#, request.id, SERVER_STATUS),
+        r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"stream 1","properties":{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{"title":"leave in five"}}}}"#.to_string(),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .prefetch_stream_properties(Duration::from_secs(5))
+      .connect(address)
+      .await
+      .unwrap();
+
+    let stream = client.state.stream("stream 1").unwrap();
+    assert!(stream.properties.is_some());
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+    assert!(received.try_recv().is_err());
+    assert!(matches!(
+      client.recv().await.unwrap().unwrap(),
+      ValidMessage::Notification { method, .. } if method.kind() == Some(NotificationType::StreamOnProperties)
+    ));
+  }
+
+  #[tokio::test]
+  async fn prefetch_stream_properties_times_out() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )]
+    })
+    .await;
+
+    let client = SnapcastConnection::builder()
+      .prefetch_stream_properties(Duration::from_millis(100))
+      .connect(address)
+      .await
+      .unwrap();
+
+    assert!(client.state.stream("stream 1").unwrap().properties.is_none());
+  }
+
+  #[tokio::test]
+  async fn resync_group_applies_clients() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"group":{{"clients":[{{"config":{{"instance":1,"latency":0,"name":"Kitchen","volume":{{"muted":false,"percent":74}}}},"connected":true,"host":{{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"}},"id":"00:21:6a:7d:74:fc","lastSeen":{{"sec":1488026481,"usec":223747}},"snapclient":{{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}}}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":true,"name":"","stream_id":"stream 1"}}}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    let group = client
+      .resync_group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string())
+      .await
+      .unwrap();
+    assert!(group.muted);
+    assert_eq!(group.clients, HashSet::from(["00:21:6a:7d:74:fc".to_string()]));
+    assert_eq!(client.state.group(&group.id), Some(group));
+    assert_eq!(client.state.client("00:21:6a:7d:74:fc").unwrap().config.name, "Kitchen");
+  }
+
+  #[tokio::test]
+  async fn stream_control_and_wait() {
+    let address = mock_server(|request| match request.method {
+      Method::StreamControl { params } if params.command == stream::ControlCommand::Next => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":"ok"}}"#,
+        request.id
+      )],
+      Method::StreamControl { .. } => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","error":{{"code":-32603,"data":"Stream can not be controlled","message":"Internal error"}}}}"#,
+        request.id
+      )],
+      Method::StreamSetProperty { .. } => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":"ok"}}"#,
+        request.id
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    client
+      .stream_control_and_wait("stream 1".to_string(), stream::ControlCommand::Next)
+      .await
+      .unwrap();
+    client
+      .stream_set_property_and_wait("stream 1".to_string(), stream::SetPropertyProperties::Shuffle(true))
+      .await
+      .unwrap();
+    let result = client
+      .stream_control_and_wait("stream 1".to_string(), stream::ControlCommand::Play)
+      .await;
+    assert!(matches!(result, Err(ClientError::Request { .. })));
+  }
+
+  #[tokio::test]
+  async fn stream_methods_need_snapserver_0_26() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      method => {
+        requests.send(method).unwrap();
+        vec![]
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::open(address).await;
+    client.fetch_server_status().await.unwrap();
+    assert_eq!(client.state.snapserver_version().as_deref(), Some("0.10.0"));
+
+    let stream = "stream 1".to_string();
+    let result = client
+      .stream_control(stream.clone(), stream::ControlCommand::Next)
+      .await;
+    assert!(matches!(
+      result,
+      Err(ClientError::MethodUnavailable { method: RequestMethod::StreamControl, ref found, minimum: "0.26.0" }) if found == "0.10.0"
+    ));
+    let result = client
+      .stream_control_and_wait(stream.clone(), stream::ControlCommand::Next)
+      .await;
+    assert!(matches!(result, Err(ClientError::MethodUnavailable { .. })));
+    let result = client
+      .stream_set_property(stream.clone(), stream::SetPropertyProperties::Shuffle(true))
+      .await;
+    assert!(matches!(
+      result,
+      Err(ClientError::MethodUnavailable {
+        method: RequestMethod::StreamSetProperty,
+        ..
+      })
+    ));
+    let result = client
+      .stream_set_property_and_wait(stream, stream::SetPropertyProperties::Shuffle(true))
+      .await;
+    assert!(matches!(result, Err(ClientError::MethodUnavailable { .. })));
+
+    client
+      .group_set_mute("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(), true)
+      .await
+      .unwrap();
+    assert!(matches!(received.recv().await, Some(Method::GroupSetMute { .. })));
+  }
+
+  #[test]
+  fn parse_snapserver_versions() {
+    assert_eq!(parse_version("0.26.0"), Some((0, 26, 0)));
+    assert_eq!(parse_version("0.27.0-beta.1"), Some((0, 27, 0)));
+    assert_eq!(parse_version("0.26"), Some((0, 26, 0)));
+    assert_eq!(parse_version("unknown"), None);
+  }
+
+  #[tokio::test]
+  async fn await_notification_buffers_others() {
+    let address = mock_server(|request| {
+      vec![
+        r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#
+          .to_string(),
+        format!(
+          r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+          request.id, SERVER_STATUS
+        ),
+        r#"{"jsonrpc":"2.0","method":"Client.OnNameChanged","params":{"id":"00:21:6a:7d:74:fc","name":"Kitchen"}}"#
+          .to_string(),
+      ]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    client.server_get_status().await.unwrap();
+
+    let notification = client
+      .await_notification(NotificationType::ClientOnNameChanged)
+      .await
+      .unwrap();
+    assert_eq!(notification.kind(), Some(NotificationType::ClientOnNameChanged));
+    assert_eq!(client.state.client("00:21:6a:7d:74:fc").unwrap().config.name, "Kitchen");
+
+    assert!(matches!(
+      client.recv().await,
+      Some(Ok(ValidMessage::Notification { method, .. })) if method.kind() == Some(NotificationType::GroupOnMute)
+    ));
+    assert!(matches!(client.recv().await, Some(Ok(ValidMessage::Result { .. }))));
+
+    let result = client
+      .await_notification_timeout(NotificationType::StreamOnUpdate, Duration::from_millis(50))
+      .await;
+    assert!(matches!(result, Err(ClientError::Timeout)));
+  }
+
+  #[tokio::test]
+  async fn queue_commands_until_flush() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method).unwrap();
+      vec![]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .auto_flush(false)
+      .connect(address)
+      .await
+      .unwrap();
+    assert!(!client.auto_flush());
+
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    client.server_get_status().await.unwrap();
+    let queued = tokio::time::timeout(Duration::from_millis(50), received.recv()).await;
+    assert!(queued.is_err());
+
+    client.flush().await.unwrap();
+    assert!(matches!(received.recv().await, Some(Method::GroupSetMute { .. })));
+    assert_eq!(received.recv().await, Some(Method::ServerGetStatus));
+  }
+
+  #[tokio::test]
+  async fn optimistic_renames() {
+    let address = mock_server(|request| match request.method {
+      Method::ServerGetStatus => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      )],
+      Method::ClientSetName { .. } => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","error":{{"code":-32603,"message":"Internal error"}}}}"#,
+        request.id
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .optimistic_updates(true)
+      .connect(address)
+      .await
+      .unwrap();
+    client.fetch_server_status().await.unwrap();
+
+    client
+      .group_set_name(
+        "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+        "Kitchen".to_string(),
+      )
+      .await
+      .unwrap();
+    assert_eq!(
+      client.state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap().name,
+      "Kitchen"
+    );
+
+    client
+      .client_set_name("00:21:6a:7d:74:fc".to_string(), "Kitchen".to_string())
+      .await
+      .unwrap();
+    assert_eq!(client.state.client("00:21:6a:7d:74:fc").unwrap().config.name, "Kitchen");
+    assert!(matches!(client.recv().await, Some(Err(ClientError::Request { .. }))));
+    assert_eq!(client.state.client("00:21:6a:7d:74:fc").unwrap().config.name, "");
+  }
+
+  #[tokio::test]
+  async fn reconnect_reverts_optimistic_updates() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      // answer only the status on the first connection, then keep the second one open
+      let (socket, _) = listener.accept().await.unwrap();
+      let (reader, mut writer) = socket.into_split();
+      let mut lines = BufReader::new(reader).lines();
+      let request: Request = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+      let response = format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, SERVER_STATUS
+      );
+      tokio::io::AsyncWriteExt::write_all(&mut writer, format!("{}\n", response).as_bytes())
+        .await
+        .unwrap();
+
+      let (_socket, _) = listener.accept().await.unwrap();
+      std::future::pending::<()>().await;
+    });
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .optimistic_updates(true)
+      .connect(address)
+      .await
+      .unwrap();
+    client.fetch_server_status().await.unwrap();
+
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string();
+    for name in ["Kitchen", "Den"] {
+      client.group_set_name(group.clone(), name.to_string()).await.unwrap();
+    }
+    assert_eq!(client.state.group(&group).unwrap().name, "Den");
+
+    client.reconnect().await.unwrap();
+    assert_eq!(client.state.group(&group).unwrap().name, "");
+  }
+
+  /// a socks5 proxy accepting a single connection, requiring `user`/`secret` if `authenticate` is set
+  #[cfg(feature = "socks")]
+  async fn socks5_proxy(authenticate: bool) -> std::net::SocketAddr {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+      let (mut client, _) = listener.accept().await.unwrap();
+
+      let mut greeting = [0; 2];
+      client.read_exact(&mut greeting).await.unwrap();
+      let mut methods = vec![0; greeting[1] as usize];
+      client.read_exact(&mut methods).await.unwrap();
+      if authenticate {
+        assert!(methods.contains(&2));
+        client.write_all(&[5, 2]).await.unwrap();
+
+        let mut credentials = vec![0; 2];
+        client.read_exact(&mut credentials).await.unwrap();
+        let mut username = vec![0; credentials[1] as usize];
+        client.read_exact(&mut username).await.unwrap();
+        let mut password = vec![0; client.read_u8().await.unwrap() as usize];
+        client.read_exact(&mut password).await.unwrap();
+        assert_eq!((&username[..], &password[..]), (&b"user"[..], &b"secret"[..]));
+        client.write_all(&[1, 0]).await.unwrap();
+      } else {
+        client.write_all(&[5, 0]).await.unwrap();
+      }
+
+      let mut request = [0; 4];
+      client.read_exact(&mut request).await.unwrap();
+      assert_eq!(request[3], 1, "expected an ipv4 target");
+      let mut ip = [0; 4];
+      client.read_exact(&mut ip).await.unwrap();
+      let port = client.read_u16().await.unwrap();
+      client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+
+      let mut server = tokio::net::TcpStream::connect((std::net::Ipv4Addr::from(ip), port))
+        .await
+        .unwrap();
+      let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+    });
+
+    address
+  }
+
+  #[cfg(feature = "socks")]
+  #[tokio::test]
+  async fn connect_through_socks5_proxy() {
+    for authenticate in [false, true] {
+      let address = mock_server(|request| {
+        vec![format!(
+          r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+          request.id, SERVER_STATUS
+        )]
+      })
+      .await;
+      let mut proxy = crate::ProxyConfig::socks5(socks5_proxy(authenticate).await);
+      if authenticate {
+        proxy = proxy.with_credentials("user", "secret");
+      }
+
+      let mut client = SnapcastConnection::builder()
+        .auto_refresh_on_connect(false)
+        .reconnect(false)
+        .proxy(proxy)
+        .connect(address)
+        .await
+        .unwrap();
+      let server = client.fetch_server_status().await.unwrap();
+      assert_eq!(server.groups.len(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn notifications_echoing_commands() {
+    let address = mock_server(|request| {
+      let mut responses = vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#,
+        request.id
+      )];
+      responses.push(r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"group","mute":true}}"#.to_string());
+      responses.push(r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"other","mute":true}}"#.to_string());
+      responses
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+
+    let mut origins = vec![];
+    for _ in 0..3 {
+      origins.push(client.recv().await.unwrap().unwrap().origin());
+    }
+    assert_eq!(origins, [Origin::Local, Origin::Echo, Origin::Remote]);
+  }
+
+  #[tokio::test]
+  async fn echo_window_follows_clock() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let clock = crate::ManualClock::new();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .clock(clock.clone())
+      .connect(address)
+      .await
+      .unwrap();
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    client.recv().await.unwrap().unwrap();
+    assert_eq!(client.last_message_at(), Some(crate::Clock::now(&clock)));
+
+    let echo = Notification::GroupOnMute {
+      params: Box::new(group::OnMuteParams {
+        id: "group".to_string(),
+        mute: true,
+      }),
+    };
+    clock.advance(ECHO_WINDOW + Duration::from_millis(1));
+    assert_eq!(client.origin_of(&echo), Origin::Remote);
+  }
+
+  #[tokio::test]
+  async fn dropped_send_and_wait_is_forgotten() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.method).unwrap();
+      vec![]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    tokio::select! {
+      _ = client.send_and_wait(Method::ServerGetRPCVersion) => panic!("the server never responds"),
+      method = received.recv() => assert_eq!(method, Some(Method::ServerGetRPCVersion)),
+    }
+
+    assert!(client.pending().is_empty());
+  }
+
+  #[tokio::test]
+  async fn late_result_of_dropped_send_and_wait() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let muted = std::sync::Mutex::new(None);
+    let address = mock_server(move |request| {
+      requests.send(request.method.clone()).unwrap();
+      match request.method {
+        Method::GroupSetMute { .. } => {
+          *muted.lock().unwrap() = Some(request.id);
+          vec![]
+        }
+        _ => vec![
+          format!(
+            r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#,
+            muted.lock().unwrap().unwrap()
+          ),
+          r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"group","mute":false}}"#.to_string(),
+        ],
+      }
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+
+    tokio::select! {
+      _ = client.send_and_wait(Method::GroupSetMute {
+        params: group::SetMuteParams { id: "group".to_string(), mute: true },
+      }) => panic!("the server has not responded yet"),
+      method = received.recv() => assert!(matches!(method, Some(Method::GroupSetMute { .. }))),
+    }
+    assert!(client.pending().is_empty());
+
+    client.send(Method::ServerGetRPCVersion).await.unwrap();
+    let result = client.recv().await.unwrap().unwrap();
+    assert!(matches!(
+      result,
+      ValidMessage::Result { result, .. } if matches!(*result, SnapcastResult::GroupSetMute(ref id, _) if id == "group")
+    ));
+    let notification = client.recv().await.unwrap().unwrap();
+    assert!(matches!(
+      notification,
+      ValidMessage::Notification { method, .. } if matches!(*method, Notification::GroupOnMute { .. })
+    ));
+  }
+
+  #[tokio::test]
+  async fn forgotten_requests_expire() {
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let address = mock_server(move |request| {
+      requests.send(request.id).unwrap();
+      vec![]
+    })
+    .await;
+
+    let clock = crate::ManualClock::new();
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .clock(clock.clone())
+      .connect(address)
+      .await
+      .unwrap();
+
+    let mut forgotten = vec![];
+    for _ in 0..2 {
+      tokio::select! {
+        _ = client.send_and_wait(Method::ServerGetRPCVersion) => panic!("the server never responds"),
+        id = received.recv() => forgotten.push(id.unwrap()),
+      }
+      clock.advance(FORGET_WINDOW + Duration::from_secs(1));
+    }
+
+    assert_eq!(client.forgotten.len(), 1);
+    assert!(client.forgotten.contains_key(&forgotten[1]));
+  }
+
+  #[tokio::test]
+  async fn sent_requests_view_follows_connection() {
+    let address = mock_server(|request| {
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":true}}}}"#,
+        request.id
+      )]
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    let sent = client.sent_requests();
+    assert!(sent.is_empty());
+
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    let (id, method) = sent.snapshot().pop().unwrap();
+    assert_eq!(method, RequestMethod::GroupSetMute("group".to_string()));
+    assert_eq!(sent.method(&id), Some(RequestMethod::GroupSetMute("group".to_string())));
+    assert_eq!(sent.len(), 1);
+
+    client.recv().await.unwrap().unwrap();
+    assert!(sent.is_empty());
+    assert_eq!(sent.method(&id), None);
+  }
+
+  #[tokio::test]
+  async fn drain_waits_for_results() {
+    let address = mock_server(|request| match request.method {
+      Method::GroupSetMute { params } => vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{{"mute":{}}}}}"#,
+        request.id, params.mute
+      )],
+      _ => vec![],
+    })
+    .await;
+
+    let mut client = SnapcastConnection::builder()
+      .auto_refresh_on_connect(false)
+      .connect(address)
+      .await
+      .unwrap();
+    client.group_set_mute("group".to_string(), true).await.unwrap();
+    client.group_set_mute("group".to_string(), false).await.unwrap();
+    client.drain(Duration::from_secs(5)).await.unwrap();
+
+    assert!(client.pending().is_empty());
+    for mute in [true, false] {
+      assert!(matches!(
+        client.recv().await.unwrap().unwrap(),
+        ValidMessage::Result { result, .. } if matches!(*result, SnapcastResult::GroupSetMute(_, ref muted) if muted.mute == mute)
+      ));
+    }
+
+    client.send(Method::ServerGetRPCVersion).await.unwrap();
+    let result = client.drain(Duration::from_millis(50)).await;
+    assert!(matches!(result, Err(ClientError::Unacknowledged(ids)) if ids == vec![client.pending()[0].0]));
+  }
+}
@@ -0,0 +1,121 @@
+//! handles that bind a connection to the id of a single group, client, or stream
+//!
+//! each handle mutably borrows the [SnapcastConnection], so only one can be held at a time. create them with
+//! [SnapcastConnection::group_handle], [SnapcastConnection::client_handle], and
+//! [SnapcastConnection::stream_handle].
+
+use crate::{
+  protocol::{client, stream},
+  ClientError, SnapcastConnection,
+};
+
+/// A group on the Snapcast server, bound to a connection
+pub struct GroupHandle<'a> {
+  connection: &'a mut SnapcastConnection,
+  id: String,
+}
+
+impl<'a> GroupHandle<'a> {
+  pub(crate) fn new(connection: &'a mut SnapcastConnection, id: String) -> Self {
+    Self { connection, id }
+  }
+
+  /// the id of the group
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// request the current status of the group, see [SnapcastConnection::group_get_status]
+  pub async fn get_status(&mut self) -> Result<(), ClientError> {
+    self.connection.group_get_status(self.id.clone()).await
+  }
+
+  /// set the mute status of the group, see [SnapcastConnection::group_set_mute]
+  pub async fn set_mute(&mut self, mute: bool) -> Result<(), ClientError> {
+    self.connection.group_set_mute(self.id.clone(), mute).await
+  }
+
+  /// set the stream of the group, see [SnapcastConnection::group_set_stream]
+  pub async fn set_stream(&mut self, stream_id: String) -> Result<(), ClientError> {
+    self.connection.group_set_stream(self.id.clone(), stream_id).await
+  }
+
+  /// set the clients of the group, see [SnapcastConnection::group_set_clients]
+  pub async fn set_clients(&mut self, clients: Vec<String>) -> Result<(), ClientError> {
+    self.connection.group_set_clients(self.id.clone(), clients).await
+  }
+
+  /// set the name of the group, see [SnapcastConnection::group_set_name]
+  pub async fn set_name(&mut self, name: String) -> Result<(), ClientError> {
+    self.connection.group_set_name(self.id.clone(), name).await
+  }
+}
+
+/// A client on the Snapcast server, bound to a connection
+pub struct ClientHandle<'a> {
+  connection: &'a mut SnapcastConnection,
+  id: String,
+}
+
+impl<'a> ClientHandle<'a> {
+  pub(crate) fn new(connection: &'a mut SnapcastConnection, id: String) -> Self {
+    Self { connection, id }
+  }
+
+  /// the id of the client
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// request the current status of the client, see [SnapcastConnection::client_get_status]
+  pub async fn get_status(&mut self) -> Result<(), ClientError> {
+    self.connection.client_get_status(self.id.clone()).await
+  }
+
+  /// set the volume and mute status of the client, see [SnapcastConnection::client_set_volume]
+  pub async fn set_volume(&mut self, volume: client::ClientVolume) -> Result<(), ClientError> {
+    self.connection.client_set_volume(self.id.clone(), volume).await
+  }
+
+  /// set the latency of the client, see [SnapcastConnection::client_set_latency]
+  pub async fn set_latency(&mut self, latency: usize) -> Result<(), ClientError> {
+    self.connection.client_set_latency(self.id.clone(), latency).await
+  }
+
+  /// set the name of the client, see [SnapcastConnection::client_set_name]
+  pub async fn set_name(&mut self, name: String) -> Result<(), ClientError> {
+    self.connection.client_set_name(self.id.clone(), name).await
+  }
+}
+
+/// A stream on the Snapcast server, bound to a connection
+pub struct StreamHandle<'a> {
+  connection: &'a mut SnapcastConnection,
+  id: String,
+}
+
+impl<'a> StreamHandle<'a> {
+  pub(crate) fn new(connection: &'a mut SnapcastConnection, id: String) -> Self {
+    Self { connection, id }
+  }
+
+  /// the id of the stream
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// control the playback of the stream, see [SnapcastConnection::stream_control]
+  pub async fn control(&mut self, command: stream::ControlCommand) -> Result<(), ClientError> {
+    self.connection.stream_control(self.id.clone(), command).await
+  }
+
+  /// set a property of the stream, see [SnapcastConnection::stream_set_property]
+  pub async fn set_property(&mut self, properties: stream::SetPropertyProperties) -> Result<(), ClientError> {
+    self.connection.stream_set_property(self.id.clone(), properties).await
+  }
+
+  /// remove the stream from the server, see [SnapcastConnection::stream_remove_stream]
+  pub async fn remove(self) -> Result<(), ClientError> {
+    self.connection.stream_remove_stream(self.id).await
+  }
+}
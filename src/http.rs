@@ -0,0 +1,58 @@
+//! fetching cover art from the snapserver with reqwest, enabled by the `http` feature
+
+use std::time::Duration;
+
+use crate::stream::ArtError;
+
+/// how long fetching a single piece of cover art may take, including redirects
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// the largest cover art accepted, so a misbehaving server cannot exhaust memory
+const MAX_BODY_LENGTH: usize = 16 * 1024 * 1024;
+
+/// resolve `url` against `base` unless it is already absolute
+///
+/// a relative `url` without a `base` is an [ArtError::RelativeUrl]
+pub(crate) fn resolve(base: Option<&str>, url: &str) -> Result<String, ArtError> {
+  if url.contains("://") {
+    return Ok(url.to_string());
+  }
+
+  match base {
+    Some(base) => Ok(format!(
+      "{}/{}",
+      base.trim_end_matches('/'),
+      url.trim_start_matches('/')
+    )),
+    None => Err(ArtError::RelativeUrl(url.to_string())),
+  }
+}
+
+/// GET an absolute url and return the body of a `200` response, following redirects
+pub(crate) async fn get(url: &str) -> Result<Vec<u8>, ArtError> {
+  let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+  let mut response = client
+    .get(url)
+    .header(reqwest::header::ACCEPT, "image/*")
+    .send()
+    .await?;
+
+  if response.status() != reqwest::StatusCode::OK {
+    return Err(ArtError::Status(response.status().as_u16()));
+  }
+  if response
+    .content_length()
+    .is_some_and(|length| length > MAX_BODY_LENGTH as u64)
+  {
+    return Err(ArtError::TooLarge);
+  }
+
+  let mut body = Vec::new();
+  while let Some(chunk) = response.chunk().await? {
+    if body.len() + chunk.len() > MAX_BODY_LENGTH {
+      return Err(ArtError::TooLarge);
+    }
+    body.extend_from_slice(&chunk);
+  }
+
+  Ok(body)
+}
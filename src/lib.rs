@@ -1,9 +1,29 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod clock;
 mod communication;
+mod handle;
+#[cfg(feature = "http")]
+mod http;
+mod metrics;
+pub mod multi;
 mod protocol;
 mod state;
+mod tape;
+mod transport;
 
+pub use builder::SnapcastConnectionBuilder;
+pub use clock::{Clock, ManualClock, SystemClock, TokioClock};
 pub use communication::{ClientError, SnapcastConnection};
+pub use handle::{ClientHandle, GroupHandle, StreamHandle};
+pub use metrics::Metrics;
+pub use multi::MultiConnection;
 pub use protocol::*;
-pub use state::State;
+pub use state::{GroupOverview, GroupVolumeSummary, State, StateChange, StateCounts, StateGroup, StateUpdate};
+pub use tape::Direction;
+#[cfg(feature = "socks")]
+pub use transport::ProxyConfig;
+pub use transport::{ConnectionStatus, RetryDecision};
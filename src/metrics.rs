@@ -0,0 +1,92 @@
+//! callbacks for collecting metrics about a connection
+//!
+//! the crate does not depend on any metrics library, implement [Metrics] to forward the events to one
+
+use std::{fmt, sync::Arc};
+
+use crate::{ClientError, Notification, SnapcastResult};
+
+/// receives events about the traffic of a [SnapcastConnection](crate::SnapcastConnection)
+///
+/// every method does nothing by default, so only the events of interest need to be implemented. \
+/// the methods are called inline from the connection, so they should be cheap and must not block.
+///
+/// # example
+/// ```no_run
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// #[derive(Default)]
+/// struct Counters {
+///   notifications: AtomicUsize,
+///   errors: AtomicUsize,
+/// }
+///
+/// impl Metrics for Counters {
+///   fn on_notification(&self, _notification: &Notification) {
+///     self.notifications.fetch_add(1, Ordering::Relaxed);
+///   }
+///
+///   fn on_error(&self, _error: &ClientError) {
+///     self.errors.fetch_add(1, Ordering::Relaxed);
+///   }
+/// }
+///
+/// let mut client = SnapcastConnection::builder()
+///   .metrics(Counters::default())
+///   .connect("127.0.0.1:1705".parse().expect("could not parse socket address"))
+///   .await
+///   .expect("could not connect");
+/// ```
+pub trait Metrics: Send + Sync {
+  /// a notification was received
+  fn on_notification(&self, _notification: &Notification) {}
+
+  /// the result of a request was received
+  fn on_result(&self, _result: &SnapcastResult) {}
+
+  /// the server responded with an error, or a received message could not be decoded
+  fn on_error(&self, _error: &ClientError) {}
+
+  /// the connection was established again after it was lost
+  fn on_reconnect(&self) {}
+
+  /// a complete message of `bytes` bytes was read from the socket
+  fn on_bytes_received(&self, _bytes: usize) {}
+
+  /// a request of `bytes` bytes was encoded to be written to the socket
+  fn on_bytes_sent(&self, _bytes: usize) {}
+}
+
+struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// the [Metrics] of a connection, shared between the codec, the transport, and the connection
+#[derive(Clone)]
+pub(crate) struct MetricsHook(Arc<dyn Metrics>);
+
+impl MetricsHook {
+  pub(crate) fn new(metrics: impl Metrics + 'static) -> Self {
+    Self(Arc::new(metrics))
+  }
+}
+
+impl std::ops::Deref for MetricsHook {
+  type Target = dyn Metrics;
+
+  fn deref(&self) -> &Self::Target {
+    self.0.as_ref()
+  }
+}
+
+impl Default for MetricsHook {
+  fn default() -> Self {
+    Self::new(NoMetrics)
+  }
+}
+
+impl fmt::Debug for MetricsHook {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("MetricsHook")
+  }
+}
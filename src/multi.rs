@@ -0,0 +1,304 @@
+//! several connections to different snapservers combined into one view
+//!
+//! every connection is registered under a label. groups, clients, and streams of the merged view are namespaced by
+//! prefixing their id with the label and a slash, e.g. the group `4dcc4e3b` of the server labeled `upstairs` becomes
+//! `upstairs/4dcc4e3b`. labels cannot contain a slash, so a namespaced id is split at its first slash and ids that
+//! contain slashes themselves stay intact.
+
+use std::collections::BTreeMap;
+
+use crate::{
+  protocol::{client::Client, stream::Stream},
+  ClientError, SnapcastConnection, StateGroup, ValidMessage,
+};
+
+/// the separator between the label and the id of a namespaced id
+const SEPARATOR: char = '/';
+
+/// prefix `id` with `label`, see the [module](self) docs
+pub fn namespaced(label: &str, id: &str) -> String {
+  format!("{}{}{}", label, SEPARATOR, id)
+}
+
+/// split a namespaced id into its label and the id on that server, or [None] if it is not namespaced
+pub fn split_namespaced(id: &str) -> Option<(&str, &str)> {
+  id.split_once(SEPARATOR)
+}
+
+/// Connections to several Snapcast servers, see the [module](self) docs
+///
+/// # example
+/// ```no_run
+/// let mut servers = MultiConnection::new();
+/// servers
+///   .insert("upstairs", SnapcastConnection::open("10.0.0.2:1705".parse().expect("could not parse socket address")).await)
+///   .expect("invalid label");
+/// servers
+///   .insert("downstairs", SnapcastConnection::open("10.0.0.3:1705".parse().expect("could not parse socket address")).await)
+///   .expect("invalid label");
+///
+/// let (connection, id) = servers.route("upstairs/4dcc4e3b").expect("unknown server");
+/// connection.group_set_mute(id.to_string(), true).await.expect("could not mute group");
+///
+/// while let Some((label, message)) = servers.recv().await {
+///   println!("{}: {:?}", label, message);
+/// }
+/// ```
+#[derive(Default)]
+pub struct MultiConnection {
+  connections: BTreeMap<String, SnapcastConnection>,
+  /// the index of the connection polled first by the next [MultiConnection::recv], so a busy server cannot starve
+  /// the others
+  next: usize,
+}
+
+impl MultiConnection {
+  /// create an empty set of connections
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// add a connection under `label`
+  ///
+  /// # args
+  /// `label`: the label of the server, which must not be empty or contain a slash
+  /// `connection`: the connection to the server
+  ///
+  /// # returns
+  /// the connection previously registered under `label`, or [ClientError::InvalidParams] if the label is invalid
+  pub fn insert(
+    &mut self,
+    label: impl Into<String>,
+    connection: SnapcastConnection,
+  ) -> Result<Option<SnapcastConnection>, ClientError> {
+    let label = label.into();
+    if label.is_empty() || label.contains(SEPARATOR) {
+      return Err(ClientError::InvalidParams(format!(
+        "server label {:?} must not be empty or contain {:?}",
+        label, SEPARATOR
+      )));
+    }
+
+    Ok(self.connections.insert(label, connection))
+  }
+
+  /// remove the connection registered under `label`
+  pub fn remove(&mut self, label: &str) -> Option<SnapcastConnection> {
+    self.connections.remove(label)
+  }
+
+  /// the labels of every connection, sorted
+  pub fn labels(&self) -> impl Iterator<Item = &str> {
+    self.connections.keys().map(String::as_str)
+  }
+
+  /// the connection registered under `label`
+  pub fn get(&self, label: &str) -> Option<&SnapcastConnection> {
+    self.connections.get(label)
+  }
+
+  /// the connection registered under `label`, for sending commands to it
+  pub fn get_mut(&mut self, label: &str) -> Option<&mut SnapcastConnection> {
+    self.connections.get_mut(label)
+  }
+
+  /// find the connection a namespaced id belongs to
+  ///
+  /// # args
+  /// `id`: a namespaced id, e.g. `upstairs/4dcc4e3b`
+  ///
+  /// # returns
+  /// the connection and the id on that server, or [None] if the id is not namespaced or its label is unknown
+  pub fn route<'a>(&mut self, id: &'a str) -> Option<(&mut SnapcastConnection, &'a str)> {
+    let (label, id) = split_namespaced(id)?;
+    Some((self.connections.get_mut(label)?, id))
+  }
+
+  /// receive the next message from any of the servers
+  ///
+  /// the connections are polled in turn, starting with a different one each call. a connection that ends is
+  /// removed, and messages not yet received from the other connections stay buffered in them. a connection whose
+  /// catch-up after a reconnect is interrupted resumes it on the next call, see [SnapcastConnection::recv].
+  ///
+  /// # returns
+  /// the label of the server and what [SnapcastConnection::recv] returned for it, or [None] once no connection is
+  /// left
+  pub async fn recv(&mut self) -> Option<(String, Result<ValidMessage, ClientError>)> {
+    loop {
+      if self.connections.is_empty() {
+        return None;
+      }
+
+      let count = self.connections.len();
+      let start = self.next % count;
+      self.next = self.next.wrapping_add(1);
+
+      let mut connections: Vec<_> = self.connections.iter_mut().collect();
+      connections.rotate_left(start);
+      let receiving = connections
+        .into_iter()
+        .map(|(label, connection)| Box::pin(async move { (label.clone(), connection.recv().await) }));
+
+      let ((label, message), ..) = futures::future::select_all(receiving).await;
+      match message {
+        Some(message) => return Some((label, message)),
+        None => {
+          tracing::info!("connection to snapserver {} ended", label);
+          self.connections.remove(&label);
+        }
+      }
+    }
+  }
+
+  /// every group of every server, with namespaced ids for the group, its stream, and its clients
+  pub fn groups(&self) -> Vec<StateGroup> {
+    self
+      .connections
+      .iter()
+      .flat_map(|(label, connection)| {
+        connection
+          .state
+          .groups_snapshot()
+          .into_iter()
+          .map(move |group| StateGroup {
+            id: namespaced(label, &group.id),
+            stream_id: namespaced(label, &group.stream_id),
+            clients: group.clients.iter().map(|client| namespaced(label, client)).collect(),
+            ..group
+          })
+      })
+      .collect()
+  }
+
+  /// every client of every server, with namespaced ids
+  pub fn clients(&self) -> Vec<Client> {
+    self
+      .connections
+      .iter()
+      .flat_map(|(label, connection)| {
+        connection
+          .state
+          .clients_snapshot()
+          .into_iter()
+          .map(move |client| Client {
+            id: namespaced(label, &client.id),
+            ..client
+          })
+      })
+      .collect()
+  }
+
+  /// every stream of every server whose details have been loaded, with namespaced ids
+  pub fn streams(&self) -> Vec<Stream> {
+    self
+      .connections
+      .iter()
+      .flat_map(|(label, connection)| {
+        connection.state.loaded_streams().into_iter().map(move |stream| Stream {
+          id: namespaced(label, &stream.id),
+          ..stream
+        })
+      })
+      .collect()
+  }
+
+  /// a single group by its namespaced id, with namespaced ids like in [MultiConnection::groups]
+  pub fn group(&self, id: &str) -> Option<StateGroup> {
+    let (label, id) = split_namespaced(id)?;
+    let group = self.connections.get(label)?.state.group(id)?;
+
+    Some(StateGroup {
+      id: namespaced(label, &group.id),
+      stream_id: namespaced(label, &group.stream_id),
+      clients: group.clients.iter().map(|client| namespaced(label, client)).collect(),
+      ..group
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::communication::tests::{mock_server, SERVER_STATUS};
+  use std::collections::HashSet;
+
+  /// a server answering every request with the server status, and muting the group on Group.SetMute
+  async fn status_server() -> std::net::SocketAddr {
+    mock_server(|request| {
+      let result = match request.method {
+        crate::Method::GroupSetMute { .. } => r#"{"mute":true}"#,
+        _ => SERVER_STATUS,
+      };
+      vec![format!(
+        r#"{{"id":"{}","jsonrpc":"2.0","result":{}}}"#,
+        request.id, result
+      )]
+    })
+    .await
+  }
+
+  #[test]
+  fn namespacing() {
+    assert_eq!(namespaced("upstairs", "stream/1"), "upstairs/stream/1");
+    assert_eq!(split_namespaced("upstairs/stream/1"), Some(("upstairs", "stream/1")));
+    assert_eq!(split_namespaced("stream"), None);
+  }
+
+  #[tokio::test]
+  async fn merge_and_route() {
+    let mut servers = MultiConnection::new();
+    for label in ["upstairs", "downstairs"] {
+      let connection = SnapcastConnection::open(status_server().await).await;
+      assert!(servers.insert(label, connection).unwrap().is_none());
+    }
+    let connection = SnapcastConnection::open(status_server().await).await;
+    assert!(matches!(
+      servers.insert("a/b", connection),
+      Err(ClientError::InvalidParams(_))
+    ));
+
+    let mut labels = HashSet::new();
+    for _ in 0..2 {
+      let (label, message) = servers.recv().await.unwrap();
+      assert!(message.is_ok());
+      labels.insert(label);
+    }
+    assert_eq!(
+      labels,
+      HashSet::from(["upstairs".to_string(), "downstairs".to_string()])
+    );
+
+    let groups: Vec<String> = servers.groups().into_iter().map(|group| group.id).collect();
+    assert_eq!(
+      groups,
+      [
+        "downstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1",
+        "upstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1"
+      ]
+    );
+    let group = servers.group("upstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    assert_eq!(group.stream_id, "upstairs/stream 1");
+    assert_eq!(group.clients, HashSet::from(["upstairs/00:21:6a:7d:74:fc".to_string()]));
+    assert_eq!(servers.clients().len(), 2);
+    assert_eq!(servers.streams()[0].id, "downstairs/stream 1");
+
+    let (connection, id) = servers.route("upstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    connection.group_set_mute(id.to_string(), true).await.unwrap();
+    let (label, message) = servers.recv().await.unwrap();
+    assert_eq!(label, "upstairs");
+    assert!(message.is_ok());
+    assert!(
+      servers
+        .group("upstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1")
+        .unwrap()
+        .muted
+    );
+    assert!(
+      !servers
+        .group("downstairs/4dcc4e3b-c699-a04b-7f0c-8260d23c43e1")
+        .unwrap()
+        .muted
+    );
+    assert!(servers.route("attic/group").is_none());
+  }
+}
@@ -0,0 +1,92 @@
+use std::hash::{Hash, Hasher};
+
+use super::{client::Client, group::Group, stream::Stream};
+
+/// A type that is uniquely identified by an id assigned by the Snapcast server
+pub trait Identifiable {
+  /// the id of the entity
+  fn id(&self) -> &str;
+
+  /// wrap the entity in a [ById], comparing and hashing only by id
+  fn by_id(self) -> ById<Self>
+  where
+    Self: Sized,
+  {
+    ById(self)
+  }
+}
+
+impl Identifiable for Client {
+  fn id(&self) -> &str {
+    &self.id
+  }
+}
+
+impl Identifiable for Group {
+  fn id(&self) -> &str {
+    &self.id
+  }
+}
+
+impl Identifiable for Stream {
+  fn id(&self) -> &str {
+    &self.id
+  }
+}
+
+/// A wrapper that implements [Eq] and [Hash] using only the id of the inner entity
+///
+/// two snapshots of the same client compare equal even if volatile fields like `last_seen` differ,
+/// which makes identity-based collections like [HashSet](std::collections::HashSet) straightforward. \
+/// the structural [PartialEq] of the inner type is unaffected.
+#[derive(Debug, Clone)]
+pub struct ById<T>(pub T);
+
+impl<T> ById<T> {
+  /// unwrap the inner entity
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T: Identifiable> PartialEq for ById<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.0.id() == other.0.id()
+  }
+}
+
+impl<T: Identifiable> Eq for ById<T> {}
+
+impl<T: Identifiable> Hash for ById<T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.0.id().hash(state);
+  }
+}
+
+impl<T> std::ops::Deref for ById<T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+
+  use super::*;
+
+  #[test]
+  fn dedup_clients_by_id() {
+    let json = r#"{"config":{"instance":2,"latency":6,"name":"123 456","volume":{"muted":false,"percent":48}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc#2","lastSeen":{"sec":1488025901,"usec":864472},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}"#;
+    let first: Client = serde_json::from_str(json).unwrap();
+    let mut second = first.clone();
+    second.last_seen.sec += 1;
+
+    assert_ne!(first, second);
+
+    let clients: HashSet<_> = [first.by_id(), second.by_id()].into_iter().collect();
+    assert_eq!(clients.len(), 1);
+  }
+}
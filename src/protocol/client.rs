@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // the snapclient
 /// A client connected to the Snapcast server
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Client {
   pub id: String,
   pub connected: bool,
@@ -13,16 +15,48 @@ pub struct Client {
   pub last_seen: LastSeen,
 }
 
+impl Client {
+  /// whether the client has not been seen for longer than `threshold`
+  ///
+  /// a client reported in the future is never considered stale
+  pub fn is_stale(&self, threshold: Duration) -> bool {
+    self.last_seen.elapsed().is_some_and(|elapsed| elapsed > threshold)
+  }
+
+  /// whether `other` differs from this client in anything but [Client::last_seen], which the server updates
+  /// constantly
+  ///
+  /// # example
+  /// ```no_run
+  /// if cached.differs_ignoring_last_seen(&updated) {
+  ///   redraw(&updated);
+  /// }
+  /// ```
+  pub fn differs_ignoring_last_seen(&self, other: &Client) -> bool {
+    Client {
+      last_seen: other.last_seen.clone(),
+      ..self.clone()
+    } != *other
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Host {
+  #[serde(default)]
   pub arch: String,
+  #[serde(default)]
   pub ip: String,
+  #[serde(default)]
   pub mac: String,
+  #[serde(default)]
   pub name: String,
+  #[serde(default)]
   pub os: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct ClientConfig {
   pub instance: usize,
   pub latency: usize,
@@ -30,13 +64,63 @@ pub struct ClientConfig {
   pub volume: ClientVolume,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct ClientVolume {
   pub muted: bool,
   pub percent: usize,
 }
 
+impl ClientVolume {
+  /// a volume of `percent`, clamped to 100
+  pub fn new(percent: u8, muted: bool) -> Self {
+    Self {
+      muted,
+      percent: usize::from(percent.min(100)),
+    }
+  }
+
+  /// an unmuted volume of `percent`, clamped to 100
+  pub fn unmuted(percent: u8) -> Self {
+    Self::new(percent, false)
+  }
+
+  /// a muted volume that restores to `percent` when unmuted, clamped to 100
+  pub fn muted(percent: u8) -> Self {
+    Self::new(percent, true)
+  }
+
+  /// this volume with `percent` clamped to 100
+  pub fn clamped(&self) -> Self {
+    Self {
+      muted: self.muted,
+      percent: self.percent.min(100),
+    }
+  }
+}
+
+/// an unmuted volume of `percent`, clamped to 100
+impl From<u8> for ClientVolume {
+  fn from(percent: u8) -> Self {
+    Self::unmuted(percent)
+  }
+}
+
+/// volumes are ordered by `percent`, then unmuted before muted
+impl Ord for ClientVolume {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    (self.percent, self.muted).cmp(&(other.percent, other.muted))
+  }
+}
+
+impl PartialOrd for ClientVolume {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Snapclient {
   pub name: String,
   #[serde(rename = "protocolVersion")]
@@ -45,80 +129,109 @@ pub struct Snapclient {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct LastSeen {
   pub sec: usize,
   pub usec: usize,
 }
 
+impl LastSeen {
+  /// the time the client was last seen
+  pub fn as_system_time(&self) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(self.sec as u64) + Duration::from_micros(self.usec as u64)
+  }
+
+  /// the time elapsed since the client was last seen, or [None] if it lies in the future
+  pub fn elapsed(&self) -> Option<Duration> {
+    SystemTime::now().duration_since(self.as_system_time()).ok()
+  }
+}
+
 // params and results
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetStatusParams {
   pub id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetStatusResult {
   pub client: Client,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetVolumeParams {
   pub id: String,
   pub volume: ClientVolume,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetVolumeResult {
   pub volume: ClientVolume,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetLatencyParams {
   pub id: String,
   pub latency: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetLatencyResult {
   pub latency: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetNameParams {
   pub id: String,
   pub name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetNameResult {
   pub name: String,
 }
 
 // notifications
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnConnectParams {
   pub id: String,
   pub client: Client,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnDisconnectParams {
   pub id: String,
+  /// the client as it was when it disconnected
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub client: Option<Client>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnVolumeChangedParams {
   pub id: String,
   pub volume: ClientVolume,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnLatencyChangedParams {
   pub id: String,
   pub latency: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnNameChangedParams {
   pub id: String,
   pub name: String,
@@ -154,6 +267,16 @@ mod tests {
     assert_eq!(host.mac, "00:21:6a:7d:74:fc");
   }
 
+  #[test]
+  fn deserialize_host_without_mac() {
+    let json = r#"{"arch":"x86_64","ip":"","name":"T400","os":"Linux Mint 17.3 Rosa"}"#;
+    let host: Host = serde_json::from_str(json).unwrap();
+
+    assert_eq!(host.mac, "");
+    assert_eq!(host.ip, "");
+    assert_eq!(host.name, "T400");
+  }
+
   #[test]
   fn serialize_client() {
     let client = Client {
@@ -192,6 +315,22 @@ mod tests {
     assert_eq!(json, expected);
   }
 
+  #[test]
+  fn stale_client() {
+    let json = r#"{"config":{"instance":2,"latency":6,"name":"123 456","volume":{"muted":false,"percent":48}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc#2","lastSeen":{"sec":1488025901,"usec":864472},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}"#;
+    let mut client: Client = serde_json::from_str(json).unwrap();
+
+    assert!(client.is_stale(Duration::from_secs(60)));
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    client.last_seen = LastSeen {
+      sec: now.as_secs() as usize,
+      usec: 0,
+    };
+
+    assert!(!client.is_stale(Duration::from_secs(60)));
+  }
+
   #[test]
   fn deserialize_client() {
     let json = r#"{"config":{"instance":2,"latency":6,"name":"123 456","volume":{"muted":false,"percent":48}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc#2","lastSeen":{"sec":1488025901,"usec":864472},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}"#;
@@ -199,4 +338,75 @@ mod tests {
 
     assert_eq!(client.id, "00:21:6a:7d:74:fc#2");
   }
+
+  #[test]
+  fn order_volumes() {
+    let mut volumes = [
+      ClientVolume {
+        muted: true,
+        percent: 50,
+      },
+      ClientVolume {
+        muted: false,
+        percent: 80,
+      },
+      ClientVolume {
+        muted: false,
+        percent: 50,
+      },
+      ClientVolume {
+        muted: true,
+        percent: 10,
+      },
+    ];
+    volumes.sort();
+
+    let sorted: Vec<_> = volumes.iter().map(|volume| (volume.percent, volume.muted)).collect();
+    assert_eq!(sorted, vec![(10, true), (50, false), (50, true), (80, false)]);
+  }
+
+  #[test]
+  fn deserialize_host_with_unknown_field() {
+    let json = r#"{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa","uptime":42}"#;
+    let host = serde_json::from_str::<Host>(json);
+
+    if cfg!(feature = "strict") {
+      assert!(host.unwrap_err().to_string().contains("unknown field `uptime`"));
+    } else {
+      assert_eq!(host.unwrap().name, "T400");
+    }
+  }
+
+  #[test]
+  fn construct_volumes() {
+    assert_eq!(
+      ClientVolume::new(150, true),
+      ClientVolume {
+        muted: true,
+        percent: 100
+      }
+    );
+    assert_eq!(ClientVolume::unmuted(40), ClientVolume::new(40, false));
+    assert_eq!(ClientVolume::muted(40), ClientVolume::new(40, true));
+    assert_eq!(ClientVolume::from(200), ClientVolume::unmuted(100));
+
+    let volume = ClientVolume {
+      muted: false,
+      percent: 250,
+    };
+    assert_eq!(volume.clamped(), ClientVolume::unmuted(100));
+  }
+
+  #[test]
+  fn differs_ignoring_last_seen() {
+    let client: Client = serde_json::from_str(r#"{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}"#).unwrap();
+
+    let mut seen = client.clone();
+    seen.last_seen.sec += 1;
+    assert!(!client.differs_ignoring_last_seen(&seen));
+
+    let mut renamed = seen.clone();
+    renamed.config.name = "Kitchen".to_string();
+    assert!(client.differs_ignoring_last_seen(&renamed));
+  }
 }
@@ -1,22 +1,246 @@
 use dashmap::DashMap;
 use serde::de::{DeserializeSeed, MapAccess, Visitor};
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use uuid::Uuid;
 
-use super::{notification::NotificationMethodConverter, request::RequestMethod, result::SnapcastResult};
+use super::{
+  notification::NotificationMethodConverter, request::RequestMethod, result::SnapcastResult, JsonRpcVersion,
+};
 use crate::Message;
 
 pub type SentRequests = DashMap<Uuid, RequestMethod>;
-pub struct SnapcastDeserializer<'a>(&'a SentRequests);
+/// requests whose caller stopped waiting for their result, with when they were forgotten
+pub type ForgottenRequests = DashMap<Uuid, (Instant, RequestMethod)>;
+
+/// A read-only view of the requests a connection has sent that are still waiting for a response, see
+/// [SnapcastConnection::sent_requests](crate::SnapcastConnection::sent_requests)
+///
+/// a request is added when it is encoded onto the socket, and removed when its result or error is decoded, when the
+/// [send_and_wait](crate::SnapcastConnection::send_and_wait) awaiting it is dropped, or when it is
+/// [re-sent](crate::SnapcastConnectionBuilder::resend_on_reconnect) under a new id. requests are kept when the
+/// connection is lost, as their responses may never arrive. \
+/// clones share the map of the connection, so a view handed to another task stays up to date.
+#[derive(Debug, Clone)]
+pub struct SentRequestsView(Arc<SentRequests>);
+
+impl SentRequestsView {
+  pub(crate) fn new(sent: Arc<SentRequests>) -> Self {
+    Self(sent)
+  }
+
+  /// the number of requests waiting for a response
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  /// whether no request is waiting for a response
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// the method of the request with id `id`, or [None] if it is not waiting for a response
+  pub fn method(&self, id: &Uuid) -> Option<RequestMethod> {
+    self.0.get(id).map(|entry| entry.value().clone())
+  }
+
+  /// an owned snapshot of the id and [RequestMethod] of each waiting request, in no particular order
+  pub fn snapshot(&self) -> Vec<(Uuid, RequestMethod)> {
+    self
+      .0
+      .iter()
+      .map(|entry| (*entry.key(), entry.value().clone()))
+      .collect()
+  }
+}
+/// deserializes messages, correlating results with the sent requests and, failing that, with the forgotten ones
+pub struct SnapcastDeserializer<'a>(&'a SentRequests, Option<&'a ForgottenRequests>, DeserializerOptions);
+
+/// options that change how messages are deserialized
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeserializerOptions {
+  /// drop the base64 `data` of any `artData`, keeping only the `extension`
+  pub ignore_art_data: bool,
+}
 
 impl<'a> SnapcastDeserializer<'a> {
   pub fn de(message: &str, state: &'a SentRequests) -> Result<Message, DeserializationError> {
+    Self::de_with_options(message, state, DeserializerOptions::default())
+  }
+
+  pub fn de_with_options(
+    message: &str,
+    state: &'a SentRequests,
+    options: DeserializerOptions,
+  ) -> Result<Message, DeserializationError> {
     let mut deserializer = serde_json::Deserializer::from_str(message);
 
-    Ok(SnapcastDeserializer(state).deserialize(&mut deserializer)?)
+    Ok(SnapcastDeserializer(state, None, options).deserialize(&mut deserializer)?)
+  }
+
+  /// like [SnapcastDeserializer::de_with_options], but results that are not in `state` are correlated with the
+  /// requests in `forgotten`, whose caller stopped waiting for them
+  pub(crate) fn de_with_forgotten(
+    message: &str,
+    state: &'a SentRequests,
+    forgotten: &'a ForgottenRequests,
+    options: DeserializerOptions,
+  ) -> Result<Message, DeserializationError> {
+    let mut deserializer = serde_json::Deserializer::from_str(message);
+
+    Ok(SnapcastDeserializer(state, Some(forgotten), options).deserialize(&mut deserializer)?)
+  }
+}
+
+/// deserialize `value`, naming the path to the failing field below `field` in the error, e.g.
+/// `params.volume.percent: invalid type: string "high", expected usize`
+pub(crate) fn deserialize_at<'de, T: serde::Deserialize<'de>>(
+  field: &str,
+  value: &'de serde_json::Value,
+) -> Result<T, serde_json::Error> {
+  use serde::de::Error;
+
+  serde_path_to_error::deserialize(value).map_err(|err| {
+    let path = err.path().to_string();
+    let location = match path.as_str() {
+      "." => field.to_string(),
+      path => format!("{}.{}", field, path),
+    };
+
+    serde_json::Error::custom(format!("{}: {}", location, err.inner()))
+  })
+}
+
+/// recursively calls `f` with every `artData` object in the value
+fn visit_art_data(value: &mut serde_json::Value, f: &impl Fn(&mut serde_json::Map<String, serde_json::Value>)) {
+  use serde_json::Value;
+
+  match value {
+    Value::Object(map) => {
+      if let Some(Value::Object(art_data)) = map.get_mut("artData") {
+        f(art_data);
+      }
+
+      map.values_mut().for_each(|value| visit_art_data(value, f));
+    }
+    Value::Array(values) => values.iter_mut().for_each(|value| visit_art_data(value, f)),
+    _ => {}
+  }
+}
+
+/// deserializes any json value like [serde_json::Value], but skips the `data` field of every `artData` object
+/// without allocating it
+///
+/// the flag is whether the value being deserialized is an `artData` object
+#[derive(Clone, Copy)]
+struct WithoutArtData(bool);
+
+impl<'de> DeserializeSeed<'de> for WithoutArtData {
+  type Value = serde_json::Value;
+
+  fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+  where
+    D: serde::de::Deserializer<'de>,
+  {
+    deserializer.deserialize_any(self)
+  }
+}
+
+impl<'de> Visitor<'de> for WithoutArtData {
+  type Value = serde_json::Value;
+
+  fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(formatter, "any valid json value")
+  }
+
+  fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_string<E>(self, value: String) -> Result<Self::Value, E> {
+    Ok(value.into())
+  }
+
+  fn visit_unit<E>(self) -> Result<Self::Value, E> {
+    Ok(serde_json::Value::Null)
+  }
+
+  fn visit_none<E>(self) -> Result<Self::Value, E> {
+    Ok(serde_json::Value::Null)
+  }
+
+  fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+  where
+    D: serde::de::Deserializer<'de>,
+  {
+    self.deserialize(deserializer)
+  }
+
+  fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+  where
+    A: serde::de::SeqAccess<'de>,
+  {
+    let mut values = Vec::new();
+    while let Some(value) = access.next_element_seed(WithoutArtData(false))? {
+      values.push(value);
+    }
+
+    Ok(values.into())
+  }
+
+  fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+  where
+    A: MapAccess<'de>,
+  {
+    let mut map = serde_json::Map::new();
+    while let Some(key) = access.next_key::<String>()? {
+      if self.0 && key == "data" {
+        access.next_value::<serde::de::IgnoredAny>()?;
+        continue;
+      }
+
+      let value = access.next_value_seed(WithoutArtData(key == "artData"))?;
+      map.insert(key, value);
+    }
+
+    Ok(map.into())
   }
 }
 
+/// replaces the `data` of every `artData` in a raw message with its length, for logging
+///
+/// returns the message unchanged if it is not valid json
+pub(crate) fn redact_art_data(message: &str) -> String {
+  use serde_json::Value;
+
+  let Ok(mut value) = serde_json::from_str::<Value>(message) else {
+    return message.to_string();
+  };
+
+  visit_art_data(&mut value, &|art_data| {
+    if let Some(Value::String(data)) = art_data.get_mut("data") {
+      *data = format!("<redacted {} bytes>", data.len());
+    }
+  });
+
+  value.to_string()
+}
+
 impl<'a> TryFrom<(&'a str, &'a SentRequests)> for Message {
   type Error = DeserializationError;
 
@@ -27,6 +251,18 @@ impl<'a> TryFrom<(&'a str, &'a SentRequests)> for Message {
   }
 }
 
+impl std::str::FromStr for Message {
+  type Err = DeserializationError;
+
+  /// parse a single message without request correlation
+  ///
+  /// results are matched by their shape alone, so results that carry the id of the entity they act on
+  /// (e.g. `Client.SetVolume`) cannot be parsed this way
+  fn from_str(message: &str) -> Result<Self, Self::Err> {
+    SnapcastDeserializer::de(message, &SentRequests::new())
+  }
+}
+
 impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
   type Value = Message;
 
@@ -34,7 +270,17 @@ impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
   where
     D: serde::de::Deserializer<'de>,
   {
-    struct SnapcastDeserializerVisitor<'a>(&'a SentRequests);
+    struct SnapcastDeserializerVisitor<'a>(&'a SentRequests, Option<&'a ForgottenRequests>, DeserializerOptions);
+
+    impl SnapcastDeserializerVisitor<'_> {
+      /// take the method of the request with id `id` out of the sent or the forgotten requests
+      fn correlate(&self, id: &Uuid) -> Option<RequestMethod> {
+        match self.0.remove(id) {
+          Some((_, method)) => Some(method),
+          None => self.1?.remove(id).map(|(_, (_, method))| method),
+        }
+      }
+    }
 
     impl<'de> Visitor<'de> for SnapcastDeserializerVisitor<'_> {
       type Value = Message;
@@ -52,30 +298,31 @@ impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
 
         let mut response: HashMap<String, Value> = HashMap::new();
 
-        while let Some((key, value)) = access.next_entry()? {
-          tracing::trace!("map key {:?} => {:?}", key, value);
+        while let Some(key) = access.next_key::<String>()? {
+          // skip art data while parsing, so the large base64 string is never allocated
+          let value = if self.2.ignore_art_data {
+            access.next_value_seed(WithoutArtData(false))?
+          } else {
+            access.next_value()?
+          };
+          // only the key, the value can hold kilobytes of art data and the whole message is traced already
+          tracing::trace!("map key {:?}", key);
           response.insert(key, value);
         }
 
-        let jsonrpc = response
-          .get("jsonrpc")
-          .unwrap_or(&Value::String("2.0".to_string()))
-          .as_str()
-          .unwrap_or("2.0")
-          .to_string();
+        let jsonrpc: JsonRpcVersion = match response.remove("jsonrpc") {
+          Some(version) => serde_json::from_value(version).map_err(Error::custom)?,
+          None if cfg!(feature = "lenient-jsonrpc") => JsonRpcVersion,
+          None => return Err(Error::missing_field("jsonrpc")),
+        };
+
+        if let Some(method) = response.remove("method") {
+          let method: String = serde_json::from_value(method).map_err(Error::custom)?;
+          let params = response.remove("params").ok_or(Error::custom("no response found??"))?;
 
-        if response.contains_key("method") {
           Ok(Message::Notification {
             jsonrpc,
-            method: Box::new(
-              NotificationMethodConverter(
-                serde_json::from_value(response.remove("method").expect("this should never fail"))
-                  .map_err(Error::custom)?,
-                response.remove("params").ok_or(Error::custom("no response found??"))?,
-              )
-              .try_into()
-              .map_err(Error::custom)?,
-            ),
+            method: Box::new(NotificationMethodConverter(method, params).into()),
           })
         } else if response.contains_key("result") {
           let id: Uuid = serde_json::from_value(
@@ -85,16 +332,19 @@ impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
           )
           .map_err(Error::custom)?;
           let result = response.remove("result").expect("this should never fail");
-          let result = if let Some(mapped_type) = self.0.remove(&id) {
-            SnapcastResult::try_from((mapped_type.1, result)).map_err(Error::custom)?
+          let (result, method) = if let Some(method) = self.correlate(&id) {
+            let result = SnapcastResult::try_from((method.clone(), result))
+              .map_err(|err| Error::custom(format_args!("invalid result of {:?} at {}", method, err)))?;
+            (result, Some(method))
           } else {
-            serde_json::from_value(result).map_err(Error::custom)?
+            (serde_json::from_value(result).map_err(Error::custom)?, None)
           };
 
           Ok(Message::Result {
             id,
             jsonrpc,
             result: Box::new(result),
+            method,
           })
         } else if response.contains_key("error") {
           let id: Uuid = serde_json::from_value(
@@ -103,11 +353,13 @@ impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
               .ok_or(Error::custom("could not associate result with request"))?,
           )
           .map_err(Error::custom)?;
+          let method = self.correlate(&id);
           Ok(Message::Error {
             id,
             jsonrpc,
             error: serde_json::from_value(response.remove("error").expect("this should never fail"))
               .map_err(Error::custom)?,
+            method,
           })
         } else {
           Err(Error::custom("invalid snapcast message"))
@@ -115,7 +367,7 @@ impl<'de, 'a> DeserializeSeed<'de> for SnapcastDeserializer<'a> {
       }
     }
 
-    d.deserialize_map(SnapcastDeserializerVisitor(self.0))
+    d.deserialize_map(SnapcastDeserializerVisitor(self.0, self.1, self.2))
   }
 }
 
@@ -147,18 +399,34 @@ mod tests {
       snapcast_message,
       Message::Error {
         id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-        jsonrpc: "2.0".to_string(),
-        error: serde_json::from_str(r#"{"code": -32603, "message": "Internal error"}"#).unwrap()
+        jsonrpc: JsonRpcVersion,
+        error: serde_json::from_str(r#"{"code": -32603, "message": "Internal error"}"#).unwrap(),
+        method: None,
       }
     );
   }
 
+  #[test]
+  fn deserialize_result_error_names_field() {
+    let map = DashMap::new();
+    map.insert(
+      "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
+      RequestMethod::ClientSetVolume("00:21:6a:7d:74:fc".to_string()),
+    );
+
+    let message = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","result":{"volume":{"muted":false,"percent":"high"}}}"#;
+    let error = SnapcastDeserializer::de(message, &map).unwrap_err().to_string();
+
+    assert!(error.contains("ClientSetVolume"), "{}", error);
+    assert!(error.contains("result.volume.percent: invalid type"), "{}", error);
+  }
+
   #[test]
   fn serialize_client_get_status() {
     let message = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","method":"Client.GetStatus","params":{"id":"00:21:6a:7d:74:fc"}}"#;
     let composed = Request {
       id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-      jsonrpc: "2.0".to_string(),
+      jsonrpc: JsonRpcVersion,
       method: Method::ClientGetStatus {
         params: client::GetStatusParams {
           id: "00:21:6a:7d:74:fc".to_string(),
@@ -183,7 +451,7 @@ mod tests {
       snapcast_message,
       Message::Result {
         id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-        jsonrpc: "2.0".to_string(),
+        jsonrpc: JsonRpcVersion,
         result: Box::new(SnapcastResult::ClientGetStatus(client::GetStatusResult {
           client: client::Client {
             id: "00:21:6a:7d:74:fc".to_string(),
@@ -214,7 +482,8 @@ mod tests {
               version: "0.10.0".to_string()
             }
           }
-        }))
+        })),
+        method: Some(RequestMethod::ClientGetStatus),
       }
     );
   }
@@ -224,7 +493,7 @@ mod tests {
     let message = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","method":"Group.GetStatus","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1"}}"#;
     let composed = Request {
       id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-      jsonrpc: "2.0".to_string(),
+      jsonrpc: JsonRpcVersion,
       method: Method::GroupGetStatus {
         params: group::GetStatusParams {
           id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
@@ -246,7 +515,7 @@ mod tests {
       snapcast_message,
       Message::Result {
         id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-        jsonrpc: "2.0".to_string(),
+        jsonrpc: JsonRpcVersion,
         result: Box::new(SnapcastResult::GroupGetStatus(group::GetStatusResult {
           group: group::Group {
             id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
@@ -314,7 +583,8 @@ mod tests {
               }
             ]
           }
-        }))
+        })),
+        method: None,
       }
     )
   }
@@ -324,7 +594,7 @@ mod tests {
     let message = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","method":"Server.GetStatus"}"#;
     let composed = Request {
       id: "00000000-0000-0000-0000-000000000000".try_into().unwrap(),
-      jsonrpc: "2.0".to_string(),
+      jsonrpc: JsonRpcVersion,
       method: Method::ServerGetStatus,
     };
 
@@ -341,6 +611,48 @@ mod tests {
     println!("{:?}", snapcast_message);
   }
 
+  #[test]
+  fn deserialize_ignoring_art_data() {
+    let map = DashMap::new();
+    let options = DeserializerOptions { ignore_art_data: true };
+
+    let message = r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"Spotify","properties":{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{"artData":{"data":"PD94bWwgdmVyc2lvbj0iMS4wIj8+","extension":"svg"},"title":"leave in five"}}}}"#;
+    let snapcast_message = SnapcastDeserializer::de_with_options(message, &map, options).unwrap();
+
+    let Message::Notification { method, .. } = snapcast_message else {
+      panic!("expected a notification");
+    };
+    let Notification::StreamOnProperties { params } = *method else {
+      panic!("expected a Stream.OnProperties notification");
+    };
+    let serialized = serde_json::to_value(params.properties.metadata.unwrap()).unwrap();
+
+    assert_eq!(serialized["artData"]["data"], "");
+    assert_eq!(serialized["artData"]["extension"], "svg");
+  }
+
+  #[test]
+  fn skip_art_data_while_parsing() {
+    let json = r#"{"data":"kept","streams":[{"artData":{"data":"PD94bWwgdmVyc2lvbj0iMS4wIj8+","extension":"svg"}}],"n":[1,-2,0.5,null,true]}"#;
+    let value = WithoutArtData(false)
+      .deserialize(&mut serde_json::Deserializer::from_str(json))
+      .unwrap();
+
+    assert_eq!(
+      value,
+      serde_json::json!({"data":"kept","streams":[{"artData":{"extension":"svg"}}],"n":[1,-2,0.5,null,true]})
+    );
+  }
+
+  #[test]
+  fn redact_art_data_for_logs() {
+    let message = r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"Spotify","properties":{"metadata":{"artData":{"data":"PD94bWwgdmVyc2lvbj0iMS4wIj8+","extension":"svg"}}}}}"#;
+    let redacted = redact_art_data(message);
+
+    assert!(redacted.contains(r#""data":"<redacted 28 bytes>""#));
+    assert!(redacted.contains(r#""extension":"svg""#));
+  }
+
   #[test]
   fn deserialize_notification() {
     let map = DashMap::new();
@@ -351,7 +663,7 @@ mod tests {
     assert_eq!(
       snapcast_message,
       Message::Notification {
-        jsonrpc: "2.0".to_string(),
+        jsonrpc: JsonRpcVersion,
         method: Box::new(Notification::ClientOnVolumeChanged {
           params: Box::new(client::OnVolumeChangedParams {
             id: "test".to_string(),
@@ -364,4 +676,56 @@ mod tests {
       }
     );
   }
+
+  #[test]
+  fn deserialize_malformed_notification() {
+    let map = DashMap::new();
+
+    let message = r#"{"jsonrpc":"2.0","method":"Client.OnVolumeChanged","params":{"id":"test","volume":{"muted":false,"percent":50},"extra":true}}"#;
+    let snapcast_message = SnapcastDeserializer::de(message, &map).unwrap();
+    assert!(matches!(
+      snapcast_message,
+      Message::Notification { method, .. } if matches!(*method, Notification::ClientOnVolumeChanged { .. }) != cfg!(feature = "strict")
+    ));
+
+    let message = r#"{"jsonrpc":"2.0","method":"Client.OnVolumeChanged","params":{"id":"test"}}"#;
+    let snapcast_message = SnapcastDeserializer::de(message, &map).unwrap();
+    assert_eq!(
+      snapcast_message,
+      Message::Notification {
+        jsonrpc: JsonRpcVersion,
+        method: Box::new(Notification::Unknown {
+          method: "Client.OnVolumeChanged".to_string(),
+          params: serde_json::json!({ "id": "test" }),
+        })
+      }
+    );
+
+    let Message::Notification { method, .. } = snapcast_message else {
+      unreachable!();
+    };
+    assert_eq!(
+      serde_json::to_string(&method).unwrap(),
+      r#"{"method":"Client.OnVolumeChanged","params":{"id":"test"}}"#
+    );
+  }
+
+  #[test]
+  fn deserialize_unknown_notification() {
+    let map = DashMap::new();
+
+    let message = r#"{"jsonrpc":"2.0","method":"Group.OnVolumeChanged","params":{"id":"test","volume":50}}"#;
+    let snapcast_message = SnapcastDeserializer::de(message, &map).unwrap();
+
+    assert_eq!(
+      snapcast_message,
+      Message::Notification {
+        jsonrpc: JsonRpcVersion,
+        method: Box::new(Notification::Unknown {
+          method: "Group.OnVolumeChanged".to_string(),
+          params: serde_json::json!({ "id": "test", "volume": 50 }),
+        })
+      }
+    );
+  }
 }
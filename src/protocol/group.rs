@@ -1,9 +1,11 @@
 use super::{client::Client, server::Server};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 // the group
 /// A group of clients maintained by the Snapcast server
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Group {
   pub id: String,
   pub name: String,
@@ -12,46 +14,77 @@ pub struct Group {
   pub clients: Vec<Client>,
 }
 
+impl Group {
+  /// whether `other` differs from this group in anything but the [last_seen](Client::last_seen) of its clients, see
+  /// [Client::differs_ignoring_last_seen]. clients are matched by id, so their order does not matter.
+  pub fn differs_ignoring_last_seen(&self, other: &Group) -> bool {
+    let (clients, others) = (self.clients_by_id(), other.clients_by_id());
+
+    self.id != other.id
+      || self.name != other.name
+      || self.stream_id != other.stream_id
+      || self.muted != other.muted
+      || self.clients.len() != other.clients.len()
+      || clients.len() != others.len()
+      || clients.iter().any(|(id, client)| match others.get(id) {
+        Some(other) => client.differs_ignoring_last_seen(other),
+        None => true,
+      })
+  }
+
+  fn clients_by_id(&self) -> HashMap<&str, &Client> {
+    self.clients.iter().map(|client| (client.id.as_str(), client)).collect()
+  }
+}
+
 // params and results
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetStatusParams {
   pub id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetStatusResult {
   pub group: Group,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetMuteParams {
   pub id: String,
   pub mute: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetMuteResult {
   pub mute: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnMuteParams {
   pub id: String,
   pub mute: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetStreamParams {
   pub id: String,
   pub stream_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetStreamResult {
   pub stream_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetClientsParams {
   pub id: String,
   /// vec of client ids
@@ -59,29 +92,34 @@ pub struct SetClientsParams {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetClientsResult {
   pub server: Server,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetNameParams {
   pub id: String,
   pub name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct SetNameResult {
   pub name: String,
 }
 
 // notifications
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnStreamChangedParams {
   pub id: String,
   pub stream_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnNameChangedParams {
   pub id: String,
   pub name: String,
@@ -172,4 +210,22 @@ mod tests {
 
     assert_eq!(group.id, "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1");
   }
+
+  #[test]
+  fn differs_ignoring_client_order() {
+    let json = r#"{"clients":[{"config":{"instance":2,"latency":6,"name":"123 456","volume":{"muted":false,"percent":48}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc#2","lastSeen":{"sec":1488025901,"usec":864472},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}},{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":false,"name":"","stream_id":"stream 2"}"#;
+    let group: Group = serde_json::from_str(json).unwrap();
+
+    let mut reordered = group.clone();
+    reordered.clients.reverse();
+    reordered.clients[0].last_seen.sec += 1;
+    assert!(!group.differs_ignoring_last_seen(&reordered));
+
+    reordered.clients[1].config.volume.percent = 50;
+    assert!(group.differs_ignoring_last_seen(&reordered));
+
+    let mut replaced = group.clone();
+    replaced.clients[1].id = "00:21:6a:7d:74:fd".to_string();
+    assert!(group.differs_ignoring_last_seen(&replaced));
+  }
 }
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The jsonrpc version of a message, which is always `2.0`
+///
+/// deserializing any other version is an error unless the `lenient-jsonrpc` feature is enabled,
+/// in which case the version sent by the server is accepted and discarded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct JsonRpcVersion;
+
+impl JsonRpcVersion {
+  /// the version as sent over the wire
+  pub const VERSION: &'static str = "2.0";
+}
+
+impl std::fmt::Display for JsonRpcVersion {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(Self::VERSION)
+  }
+}
+
+impl Serialize for JsonRpcVersion {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(Self::VERSION)
+  }
+}
+
+impl<'de> Deserialize<'de> for JsonRpcVersion {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let version = String::deserialize(deserializer)?;
+
+    if version == Self::VERSION || cfg!(feature = "lenient-jsonrpc") {
+      Ok(JsonRpcVersion)
+    } else {
+      Err(serde::de::Error::invalid_value(
+        serde::de::Unexpected::Str(&version),
+        &"jsonrpc version 2.0",
+      ))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn serialize_jsonrpc_version() {
+    assert_eq!(serde_json::to_string(&JsonRpcVersion).unwrap(), r#""2.0""#);
+    assert_eq!(
+      serde_json::from_str::<JsonRpcVersion>(r#""2.0""#).unwrap(),
+      JsonRpcVersion
+    );
+  }
+
+  #[cfg(not(feature = "lenient-jsonrpc"))]
+  #[test]
+  fn reject_other_jsonrpc_versions() {
+    assert!(serde_json::from_str::<JsonRpcVersion>(r#""1.0""#).is_err());
+  }
+}
@@ -28,17 +28,21 @@ pub mod stream;
 /// module for all error types that can be returned from the server
 pub mod errors;
 
+mod by_id;
 mod de;
+mod jsonrpc;
 mod notification;
 mod request;
 mod result;
 
-pub use de::DeserializationError;
-pub(super) use de::SentRequests;
-pub(super) use request::{Request, RequestMethod};
+pub use by_id::{ById, Identifiable};
+pub(super) use de::{redact_art_data, DeserializerOptions, ForgottenRequests, SentRequests, SnapcastDeserializer};
+pub use de::{DeserializationError, SentRequestsView};
+pub use jsonrpc::JsonRpcVersion;
+pub(super) use request::Request;
 
-pub use notification::Notification;
-pub use request::Method;
+pub use notification::{Notification, NotificationType};
+pub use request::{Method, RequestMethod};
 pub use result::SnapcastResult;
 
 /// A message received from the Snapcast server
@@ -50,23 +54,29 @@ pub enum Message {
     /// The id of the request
     id: uuid::Uuid,
     /// The jsonrpc version (2.0)
-    jsonrpc: String,
+    jsonrpc: JsonRpcVersion,
     /// The result of the request
     result: Box<SnapcastResult>,
+    /// The method of the request that produced this result, if it could be correlated
+    #[serde(skip)]
+    method: Option<RequestMethod>,
   },
   /// An error from the server
   Error {
     /// The id of the request
     id: uuid::Uuid,
     /// The jsonrpc version (2.0)
-    jsonrpc: String,
+    jsonrpc: JsonRpcVersion,
     /// The error
     error: errors::SnapcastError,
+    /// The method of the request that failed, if it could be correlated
+    #[serde(skip)]
+    method: Option<RequestMethod>,
   },
   /// A notification from the server
   Notification {
     /// The jsonrpc version (2.0)
-    jsonrpc: String,
+    jsonrpc: JsonRpcVersion,
     /// The notification data itself as a tagged enum
     #[serde(flatten)]
     method: Box<Notification>,
@@ -82,28 +92,82 @@ pub enum ValidMessage {
     /// The id of the request
     id: uuid::Uuid,
     /// The jsonrpc version (2.0)
-    jsonrpc: String,
+    jsonrpc: JsonRpcVersion,
     /// The result of the request
     result: Box<SnapcastResult>,
+    /// The method of the request that produced this result, if it could be correlated
+    #[serde(skip)]
+    method: Option<RequestMethod>,
   },
   /// A notification from the server
   Notification {
     /// The jsonrpc version (2.0)
-    jsonrpc: String,
+    jsonrpc: JsonRpcVersion,
     /// The notification data itself as a tagged enum
     #[serde(flatten)]
     method: Box<Notification>,
+    /// Whether the notification mirrors a command this client sent recently
+    #[serde(skip)]
+    origin: Origin,
   },
 }
 
+/// where the change carried by a [ValidMessage] originated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+  /// the result of a request this client sent
+  Local,
+  /// a notification about a change made by someone else
+  Remote,
+  /// a notification that mirrors a command this client sent recently, e.g. a `Client.OnVolumeChanged` with the
+  /// same volume that was just set
+  Echo,
+}
+
+impl ValidMessage {
+  /// where the change carried by this message originated
+  ///
+  /// useful to avoid feedback loops when mirroring state elsewhere: [Origin::Local] and [Origin::Echo] messages
+  /// only reflect commands sent by this client
+  ///
+  /// # example
+  /// ```no_run
+  /// if let Some(Ok(message)) = client.recv().await {
+  ///   if message.origin() == Origin::Remote {
+  ///     println!("someone else changed something: {:?}", message);
+  ///   }
+  /// }
+  /// ```
+  pub fn origin(&self) -> Origin {
+    match self {
+      ValidMessage::Result { .. } => Origin::Local,
+      ValidMessage::Notification { origin, .. } => *origin,
+    }
+  }
+}
+
 impl TryFrom<Message> for ValidMessage {
   type Error = errors::SnapcastError;
 
   fn try_from(value: Message) -> Result<Self, Self::Error> {
     match value {
-      Message::Result { id, jsonrpc, result } => Ok(ValidMessage::Result { id, jsonrpc, result }),
+      Message::Result {
+        id,
+        jsonrpc,
+        result,
+        method,
+      } => Ok(ValidMessage::Result {
+        id,
+        jsonrpc,
+        result,
+        method,
+      }),
       Message::Error { error, .. } => Err(error),
-      Message::Notification { jsonrpc, method } => Ok(ValidMessage::Notification { jsonrpc, method }),
+      Message::Notification { jsonrpc, method } => Ok(ValidMessage::Notification {
+        jsonrpc,
+        method,
+        origin: Origin::Remote,
+      }),
     }
   }
 }
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{client, group, server, stream};
+use super::de::deserialize_at;
+use crate::{client, group, server, stream, Method};
 
 /// A notification from the Snapcast server
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -37,9 +38,15 @@ pub enum Notification {
   StreamOnUpdate { params: Box<stream::OnUpdateParams> },
   #[serde(rename = "Stream.OnProperties")]
   StreamOnProperties { params: Box<stream::OnPropertiesParams> },
+
+  /// a notification with a method this crate does not know, or whose params did not match the method, kept raw so
+  /// newer servers do not break the connection
+  #[serde(untagged)]
+  Unknown { method: String, params: serde_json::Value },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// the kind of a [Notification], without its params
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum NotificationType {
   // client
   #[serde(rename = "Client.OnConnect")]
@@ -72,55 +79,232 @@ pub enum NotificationType {
   StreamOnProperties,
 }
 
-pub(crate) struct NotificationMethodConverter(pub NotificationType, pub serde_json::Value);
+impl NotificationType {
+  /// the names of every notification this crate recognizes, as they appear in the `method` field of a notification
+  ///
+  /// # example
+  /// ```no_run
+  /// for name in NotificationType::all_names() {
+  ///   println!("{}", name);
+  /// }
+  /// ```
+  pub fn all_names() -> &'static [&'static str] {
+    &[
+      "Client.OnConnect",
+      "Client.OnDisconnect",
+      "Client.OnVolumeChanged",
+      "Client.OnLatencyChanged",
+      "Client.OnNameChanged",
+      "Group.OnMute",
+      "Group.OnStreamChanged",
+      "Group.OnNameChanged",
+      "Server.OnUpdate",
+      "Stream.OnUpdate",
+      "Stream.OnProperties",
+    ]
+  }
 
-impl TryFrom<NotificationMethodConverter> for Notification {
-  type Error = serde_json::Error;
+  /// the name of this kind of notification, as it appears in the `method` field of the notification
+  pub fn name(&self) -> &'static str {
+    match self {
+      NotificationType::ClientOnConnect => "Client.OnConnect",
+      NotificationType::ClientOnDisconnect => "Client.OnDisconnect",
+      NotificationType::ClientOnVolumeChanged => "Client.OnVolumeChanged",
+      NotificationType::ClientOnLatencyChanged => "Client.OnLatencyChanged",
+      NotificationType::ClientOnNameChanged => "Client.OnNameChanged",
+      NotificationType::GroupOnMute => "Group.OnMute",
+      NotificationType::GroupOnStreamChanged => "Group.OnStreamChanged",
+      NotificationType::GroupOnNameChanged => "Group.OnNameChanged",
+      NotificationType::ServerOnUpdate => "Server.OnUpdate",
+      NotificationType::StreamOnUpdate => "Stream.OnUpdate",
+      NotificationType::StreamOnProperties => "Stream.OnProperties",
+    }
+  }
+}
 
-  fn try_from(value: NotificationMethodConverter) -> Result<Self, Self::Error> {
+pub(crate) struct NotificationMethodConverter(pub String, pub serde_json::Value);
+
+impl From<NotificationMethodConverter> for Notification {
+  /// unrecognized methods and notifications with malformed params are kept as [Notification::Unknown]
+  fn from(value: NotificationMethodConverter) -> Self {
     let NotificationMethodConverter(method, params) = value;
 
-    match method {
+    let Ok(kind) = serde_json::from_value(serde_json::Value::String(method.clone())) else {
+      tracing::debug!("received unknown notification {}", method);
+      return Notification::Unknown { method, params };
+    };
+
+    match Notification::parse(&kind, &params) {
+      Ok(notification) => notification,
+      Err(err) => {
+        tracing::warn!(
+          "could not parse params of {} notification ({}): {}",
+          method,
+          err,
+          params
+        );
+        Notification::Unknown { method, params }
+      }
+    }
+  }
+}
+
+impl Notification {
+  /// build a notification from its method and params, the same way notifications read from the server are parsed
+  ///
+  /// # args
+  /// `method`: the name of the notification, e.g. `Group.OnMute`
+  /// `params`: the params of the notification
+  ///
+  /// # returns
+  /// the [Notification], or [Notification::Unknown] if the method is not recognized or the params do not match it
+  pub fn from_parts(method: impl Into<String>, params: serde_json::Value) -> Self {
+    NotificationMethodConverter(method.into(), params).into()
+  }
+
+  /// the name of this notification, as it appears in the `method` field
+  ///
+  /// # example
+  /// ```no_run
+  /// let topic = format!("snapcast/{}", notification.method_name());
+  /// ```
+  pub fn method_name(&self) -> &str {
+    match self {
+      Notification::Unknown { method, .. } => method,
+      notification => notification.kind().map(|kind| kind.name()).unwrap_or_default(),
+    }
+  }
+
+  /// the kind of this notification
+  ///
+  /// # returns
+  /// the [NotificationType], or [None] for [Notification::Unknown]
+  pub fn kind(&self) -> Option<NotificationType> {
+    Some(match self {
+      Notification::ClientOnConnect { .. } => NotificationType::ClientOnConnect,
+      Notification::ClientOnDisconnect { .. } => NotificationType::ClientOnDisconnect,
+      Notification::ClientOnVolumeChanged { .. } => NotificationType::ClientOnVolumeChanged,
+      Notification::ClientOnLatencyChanged { .. } => NotificationType::ClientOnLatencyChanged,
+      Notification::ClientOnNameChanged { .. } => NotificationType::ClientOnNameChanged,
+      Notification::GroupOnMute { .. } => NotificationType::GroupOnMute,
+      Notification::GroupOnStreamChanged { .. } => NotificationType::GroupOnStreamChanged,
+      Notification::GroupOnNameChanged { .. } => NotificationType::GroupOnNameChanged,
+      Notification::ServerOnUpdate { .. } => NotificationType::ServerOnUpdate,
+      Notification::StreamOnUpdate { .. } => NotificationType::StreamOnUpdate,
+      Notification::StreamOnProperties { .. } => NotificationType::StreamOnProperties,
+      Notification::Unknown { .. } => return None,
+    })
+  }
+
+  /// whether this notification reports exactly the change requested by `command`
+  ///
+  /// only set-commands with a matching notification are considered, everything else never mirrors
+  pub fn mirrors(&self, command: &Method) -> bool {
+    match (self, command) {
+      (Notification::ClientOnVolumeChanged { params: n }, Method::ClientSetVolume { params: c }) => {
+        n.id == c.id && n.volume == c.volume
+      }
+      (Notification::ClientOnLatencyChanged { params: n }, Method::ClientSetLatency { params: c }) => {
+        n.id == c.id && n.latency == c.latency
+      }
+      (Notification::ClientOnNameChanged { params: n }, Method::ClientSetName { params: c }) => {
+        n.id == c.id && n.name == c.name
+      }
+      (Notification::GroupOnMute { params: n }, Method::GroupSetMute { params: c }) => n.id == c.id && n.mute == c.mute,
+      (Notification::GroupOnStreamChanged { params: n }, Method::GroupSetStream { params: c }) => {
+        n.id == c.id && n.stream_id == c.stream_id
+      }
+      (Notification::GroupOnNameChanged { params: n }, Method::GroupSetName { params: c }) => {
+        n.id == c.id && n.name == c.name
+      }
+      _ => false,
+    }
+  }
+
+  fn parse(kind: &NotificationType, params: &serde_json::Value) -> Result<Self, serde_json::Error> {
+    match kind {
       // client
       NotificationType::ClientOnConnect => Ok(Notification::ClientOnConnect {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::ClientOnDisconnect => Ok(Notification::ClientOnDisconnect {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::ClientOnVolumeChanged => Ok(Notification::ClientOnVolumeChanged {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::ClientOnLatencyChanged => Ok(Notification::ClientOnLatencyChanged {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::ClientOnNameChanged => Ok(Notification::ClientOnNameChanged {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
 
       // group
       NotificationType::GroupOnMute => Ok(Notification::GroupOnMute {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::GroupOnStreamChanged => Ok(Notification::GroupOnStreamChanged {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::GroupOnNameChanged => Ok(Notification::GroupOnNameChanged {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
 
       // server
       NotificationType::ServerOnUpdate => Ok(Notification::ServerOnUpdate {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
 
       // stream
       NotificationType::StreamOnUpdate => Ok(Notification::StreamOnUpdate {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
       NotificationType::StreamOnProperties => Ok(Notification::StreamOnProperties {
-        params: serde_json::from_value(params)?,
+        params: deserialize_at("params", params)?,
       }),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_error_names_field() {
+    let params = serde_json::json!({"id": "00:21:6a:7d:74:fc", "volume": {"muted": false, "percent": "high"}});
+    let error = Notification::parse(&NotificationType::ClientOnVolumeChanged, &params).unwrap_err();
+
+    assert!(
+      error.to_string().starts_with("params.volume.percent: invalid type"),
+      "{}",
+      error
+    );
+  }
+
+  #[test]
+  fn all_notification_names_deserialize() {
+    let kinds: std::collections::HashSet<NotificationType> = NotificationType::all_names()
+      .iter()
+      .map(|name| serde_json::from_value(serde_json::Value::String(name.to_string())).unwrap())
+      .collect();
+
+    assert_eq!(kinds.len(), NotificationType::all_names().len());
+  }
+
+  #[test]
+  fn method_names_round_trip() {
+    for name in NotificationType::all_names() {
+      let kind: NotificationType = serde_json::from_value(serde_json::Value::String(name.to_string())).unwrap();
+      assert_eq!(kind.name(), *name);
+    }
+
+    let notification = Notification::from_parts("Group.OnMute", serde_json::json!({"id": "group", "mute": true}));
+    assert_eq!(notification.kind(), Some(NotificationType::GroupOnMute));
+    assert_eq!(notification.method_name(), "Group.OnMute");
+
+    let notification = Notification::from_parts("Group.OnExplode", serde_json::json!({}));
+    assert_eq!(notification.method_name(), "Group.OnExplode");
+  }
+}
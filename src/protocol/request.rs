@@ -47,10 +47,100 @@ pub enum Method {
   StreamSetProperty { params: stream::SetPropertyParams },
 }
 
+impl Method {
+  /// the names of every method this crate can send, as they appear in the `method` field of a request
+  ///
+  /// # example
+  /// ```no_run
+  /// for name in Method::all_names() {
+  ///   println!("{}", name);
+  /// }
+  /// ```
+  pub fn all_names() -> &'static [&'static str] {
+    &[
+      "Client.GetStatus",
+      "Client.SetVolume",
+      "Client.SetLatency",
+      "Client.SetName",
+      "Group.GetStatus",
+      "Group.SetMute",
+      "Group.SetStream",
+      "Group.SetClients",
+      "Group.SetName",
+      "Server.GetRPCVersion",
+      "Server.GetStatus",
+      "Server.DeleteClient",
+      "Stream.AddStream",
+      "Stream.RemoveStream",
+      "Stream.Control",
+      "Stream.SetProperty",
+    ]
+  }
+
+  /// the name of this method, as it appears in the `method` field of the request
+  ///
+  /// # example
+  /// ```no_run
+  /// assert_eq!(Method::ServerGetStatus.name(), "Server.GetStatus");
+  /// ```
+  pub fn name(&self) -> &'static str {
+    match self {
+      // client
+      Method::ClientGetStatus { .. } => "Client.GetStatus",
+      Method::ClientSetVolume { .. } => "Client.SetVolume",
+      Method::ClientSetLatency { .. } => "Client.SetLatency",
+      Method::ClientSetName { .. } => "Client.SetName",
+
+      // group
+      Method::GroupGetStatus { .. } => "Group.GetStatus",
+      Method::GroupSetMute { .. } => "Group.SetMute",
+      Method::GroupSetStream { .. } => "Group.SetStream",
+      Method::GroupSetClients { .. } => "Group.SetClients",
+      Method::GroupSetName { .. } => "Group.SetName",
+
+      // server
+      Method::ServerGetRPCVersion => "Server.GetRPCVersion",
+      Method::ServerGetStatus => "Server.GetStatus",
+      Method::ServerDeleteClient { .. } => "Server.DeleteClient",
+
+      // stream
+      Method::StreamAddStream { .. } => "Stream.AddStream",
+      Method::StreamRemoveStream { .. } => "Stream.RemoveStream",
+      Method::StreamControl { .. } => "Stream.Control",
+      Method::StreamSetProperty { .. } => "Stream.SetProperty",
+    }
+  }
+
+  /// build a method from its name and its params, e.g. for forwarding commands that arrive from another protocol
+  ///
+  /// # args
+  /// `name`: the name of the method, one of [Method::all_names]
+  /// `params`: the params of the method, ignored for methods without params
+  ///
+  /// # returns
+  /// the [Method], or a [serde_json::Error] if the name is unknown or the params do not match it
+  ///
+  /// # example
+  /// ```no_run
+  /// let method = Method::from_parts("Group.SetMute", serde_json::json!({ "id": "group_id", "mute": true }))
+  ///   .expect("invalid command");
+  /// client.send(method).await.expect("could not send command");
+  /// ```
+  pub fn from_parts(name: &str, params: serde_json::Value) -> Result<Self, serde_json::Error> {
+    let mut request = serde_json::Map::new();
+    request.insert("method".to_string(), serde_json::Value::String(name.to_string()));
+    if !params.is_null() {
+      request.insert("params".to_string(), params);
+    }
+
+    serde_json::from_value(serde_json::Value::Object(request))
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
   pub id: uuid::Uuid,
-  pub jsonrpc: String,
+  pub jsonrpc: super::JsonRpcVersion,
   #[serde(flatten)]
   pub method: Method,
 }
@@ -63,7 +153,10 @@ impl TryInto<String> for Request {
   }
 }
 
-#[derive(Clone, Debug)]
+/// The method of a request that has been sent to the server, used to correlate results with requests
+///
+/// variants that act on a single entity carry the id of that entity, as the server does not echo it back
+#[derive(Clone, Debug, PartialEq)]
 pub enum RequestMethod {
   // client
   ClientGetStatus,
@@ -119,3 +212,90 @@ impl From<&Method> for RequestMethod {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn every_method_name_matches_serde() {
+    let params = [
+      ("Client.GetStatus", json!({ "id": "client" })),
+      (
+        "Client.SetVolume",
+        json!({ "id": "client", "volume": { "muted": false, "percent": 50 } }),
+      ),
+      ("Client.SetLatency", json!({ "id": "client", "latency": 20 })),
+      ("Client.SetName", json!({ "id": "client", "name": "Kitchen" })),
+      ("Group.GetStatus", json!({ "id": "group" })),
+      ("Group.SetMute", json!({ "id": "group", "mute": true })),
+      ("Group.SetStream", json!({ "id": "group", "stream_id": "stream" })),
+      ("Group.SetClients", json!({ "id": "group", "clients": ["client"] })),
+      ("Group.SetName", json!({ "id": "group", "name": "Kitchen" })),
+      ("Server.GetRPCVersion", serde_json::Value::Null),
+      ("Server.GetStatus", serde_json::Value::Null),
+      ("Server.DeleteClient", json!({ "id": "client" })),
+      (
+        "Stream.AddStream",
+        json!({ "streamUri": "pipe:///tmp/snapfifo?name=stream" }),
+      ),
+      ("Stream.RemoveStream", json!({ "id": "stream" })),
+      ("Stream.Control", json!({ "id": "stream", "command": "play" })),
+      (
+        "Stream.SetProperty",
+        json!({ "id": "stream", "property": "shuffle", "value": true }),
+      ),
+    ];
+    assert_eq!(
+      params.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+      Method::all_names()
+    );
+
+    for (name, params) in params {
+      let method = Method::from_parts(name, params).unwrap();
+      assert_eq!(method.name(), name);
+      assert_eq!(serde_json::to_value(&method).unwrap()["method"], json!(name));
+
+      // a new variant fails to compile here until it is added to the names above
+      match method {
+        Method::ClientGetStatus { .. }
+        | Method::ClientSetVolume { .. }
+        | Method::ClientSetLatency { .. }
+        | Method::ClientSetName { .. }
+        | Method::GroupGetStatus { .. }
+        | Method::GroupSetMute { .. }
+        | Method::GroupSetStream { .. }
+        | Method::GroupSetClients { .. }
+        | Method::GroupSetName { .. }
+        | Method::ServerGetRPCVersion
+        | Method::ServerGetStatus
+        | Method::ServerDeleteClient { .. }
+        | Method::StreamAddStream { .. }
+        | Method::StreamRemoveStream { .. }
+        | Method::StreamControl { .. }
+        | Method::StreamSetProperty { .. } => {}
+      }
+    }
+  }
+
+  #[test]
+  fn method_from_parts() {
+    let method = Method::from_parts("Group.SetMute", serde_json::json!({ "id": "group", "mute": true })).unwrap();
+    assert_eq!(method.name(), "Group.SetMute");
+    assert_eq!(
+      serde_json::to_value(&method).unwrap()["method"],
+      serde_json::json!(method.name())
+    );
+
+    let method = Method::from_parts("Server.GetStatus", serde_json::Value::Null).unwrap();
+    assert_eq!(method, Method::ServerGetStatus);
+    assert_eq!(
+      Method::from_parts("Server.GetStatus", serde_json::json!({})).unwrap(),
+      Method::ServerGetStatus
+    );
+
+    assert!(Method::from_parts("Group.SetMute", serde_json::json!({ "id": "group" })).is_err());
+    assert!(Method::from_parts("Group.Explode", serde_json::Value::Null).is_err());
+  }
+}
@@ -1,10 +1,14 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-use super::request::RequestMethod;
+use super::{de::deserialize_at, request::RequestMethod};
 use crate::{client, group, server, stream};
 
 /// The result of a Snapcast request
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// results are normally matched to the request that produced them. without that correlation, the variant is chosen
+/// by the keys of the result, see the [Deserialize] impl.
+#[derive(Debug, Clone, Serialize, PartialEq)]
 #[serde(untagged)]
 pub enum SnapcastResult {
   // client
@@ -54,28 +58,173 @@ impl TryFrom<(RequestMethod, serde_json::Value)> for SnapcastResult {
   fn try_from((method, value): (RequestMethod, serde_json::Value)) -> Result<Self, Self::Error> {
     match method {
       // client
-      RequestMethod::ClientGetStatus => Ok(SnapcastResult::ClientGetStatus(serde_json::from_value(value)?)),
-      RequestMethod::ClientSetVolume(id) => Ok(SnapcastResult::ClientSetVolume(id, serde_json::from_value(value)?)),
-      RequestMethod::ClientSetLatency(id) => Ok(SnapcastResult::ClientSetLatency(id, serde_json::from_value(value)?)),
-      RequestMethod::ClientSetName(id) => Ok(SnapcastResult::ClientSetName(id, serde_json::from_value(value)?)),
+      RequestMethod::ClientGetStatus => Ok(SnapcastResult::ClientGetStatus(deserialize_at("result", &value)?)),
+      RequestMethod::ClientSetVolume(id) => Ok(SnapcastResult::ClientSetVolume(id, deserialize_at("result", &value)?)),
+      RequestMethod::ClientSetLatency(id) => {
+        Ok(SnapcastResult::ClientSetLatency(id, deserialize_at("result", &value)?))
+      }
+      RequestMethod::ClientSetName(id) => Ok(SnapcastResult::ClientSetName(id, deserialize_at("result", &value)?)),
 
       // group
-      RequestMethod::GroupGetStatus => Ok(SnapcastResult::GroupGetStatus(serde_json::from_value(value)?)),
-      RequestMethod::GroupSetMute(id) => Ok(SnapcastResult::GroupSetMute(id, serde_json::from_value(value)?)),
-      RequestMethod::GroupSetStream(id) => Ok(SnapcastResult::GroupSetStream(id, serde_json::from_value(value)?)),
-      RequestMethod::GroupSetClients => Ok(SnapcastResult::GroupSetClients(serde_json::from_value(value)?)),
-      RequestMethod::GroupSetName(id) => Ok(SnapcastResult::GroupSetName(id, serde_json::from_value(value)?)),
+      RequestMethod::GroupGetStatus => Ok(SnapcastResult::GroupGetStatus(deserialize_at("result", &value)?)),
+      RequestMethod::GroupSetMute(id) => Ok(SnapcastResult::GroupSetMute(id, deserialize_at("result", &value)?)),
+      RequestMethod::GroupSetStream(id) => Ok(SnapcastResult::GroupSetStream(id, deserialize_at("result", &value)?)),
+      RequestMethod::GroupSetClients => Ok(SnapcastResult::GroupSetClients(deserialize_at("result", &value)?)),
+      RequestMethod::GroupSetName(id) => Ok(SnapcastResult::GroupSetName(id, deserialize_at("result", &value)?)),
 
       // server
-      RequestMethod::ServerGetRPCVersion => Ok(SnapcastResult::ServerGetRPCVersion(serde_json::from_value(value)?)),
-      RequestMethod::ServerGetStatus => Ok(SnapcastResult::ServerGetStatus(serde_json::from_value(value)?)),
-      RequestMethod::ServerDeleteClient => Ok(SnapcastResult::ServerDeleteClient(serde_json::from_value(value)?)),
+      RequestMethod::ServerGetRPCVersion => Ok(SnapcastResult::ServerGetRPCVersion(deserialize_at("result", &value)?)),
+      RequestMethod::ServerGetStatus => Ok(SnapcastResult::ServerGetStatus(deserialize_at("result", &value)?)),
+      RequestMethod::ServerDeleteClient => Ok(SnapcastResult::ServerDeleteClient(deserialize_at("result", &value)?)),
 
       // stream
-      RequestMethod::StreamAddStream => Ok(SnapcastResult::StreamAddStream(serde_json::from_value(value)?)),
-      RequestMethod::StreamRemoveStream => Ok(SnapcastResult::StreamRemoveStream(serde_json::from_value(value)?)),
-      RequestMethod::StreamControl => Ok(SnapcastResult::StreamControl(serde_json::from_value(value)?)),
-      RequestMethod::StreamSetProperty => Ok(SnapcastResult::StreamSetProperty(serde_json::from_value(value)?)),
+      RequestMethod::StreamAddStream => Ok(SnapcastResult::StreamAddStream(deserialize_at("result", &value)?)),
+      RequestMethod::StreamRemoveStream => Ok(SnapcastResult::StreamRemoveStream(deserialize_at("result", &value)?)),
+      RequestMethod::StreamControl => Ok(SnapcastResult::StreamControl(deserialize_at("result", &value)?)),
+      RequestMethod::StreamSetProperty => Ok(SnapcastResult::StreamSetProperty(deserialize_at("result", &value)?)),
     }
   }
 }
+
+/// picks the variant of an uncorrelated result by its discriminating keys
+///
+/// results that share a shape resolve to a single variant: `server` to [SnapcastResult::ServerGetStatus], `id` to
+/// [SnapcastResult::StreamAddStream], and a string to [SnapcastResult::StreamControl]. results that act on an
+/// entity need its id, so they are only accepted in their serialized `[id, result]` form.
+impl<'de> Deserialize<'de> for SnapcastResult {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let value = Value::deserialize(deserializer)?;
+
+    match value {
+      Value::String(result) => Ok(SnapcastResult::StreamControl(result)),
+      Value::Object(ref map) => {
+        let result = if map.contains_key("client") {
+          SnapcastResult::ClientGetStatus(serde_json::from_value(value).map_err(D::Error::custom)?)
+        } else if map.contains_key("group") {
+          SnapcastResult::GroupGetStatus(serde_json::from_value(value).map_err(D::Error::custom)?)
+        } else if map.contains_key("server") {
+          SnapcastResult::ServerGetStatus(serde_json::from_value(value).map_err(D::Error::custom)?)
+        } else if map.contains_key("major") {
+          SnapcastResult::ServerGetRPCVersion(serde_json::from_value(value).map_err(D::Error::custom)?)
+        } else if map.contains_key("id") {
+          SnapcastResult::StreamAddStream(serde_json::from_value(value).map_err(D::Error::custom)?)
+        } else {
+          return Err(D::Error::custom(
+            "result cannot be identified without the request that produced it",
+          ));
+        };
+
+        Ok(result)
+      }
+      Value::Array(mut pair) if pair.len() == 2 => {
+        let result = pair.pop().expect("this should never fail");
+        let id: String =
+          serde_json::from_value(pair.pop().expect("this should never fail")).map_err(D::Error::custom)?;
+
+        let Value::Object(ref map) = result else {
+          return Err(D::Error::custom("expected a result object after the id"));
+        };
+
+        if map.contains_key("volume") {
+          Ok(SnapcastResult::ClientSetVolume(
+            id,
+            serde_json::from_value(result).map_err(D::Error::custom)?,
+          ))
+        } else if map.contains_key("latency") {
+          Ok(SnapcastResult::ClientSetLatency(
+            id,
+            serde_json::from_value(result).map_err(D::Error::custom)?,
+          ))
+        } else if map.contains_key("mute") {
+          Ok(SnapcastResult::GroupSetMute(
+            id,
+            serde_json::from_value(result).map_err(D::Error::custom)?,
+          ))
+        } else if map.contains_key("stream_id") {
+          Ok(SnapcastResult::GroupSetStream(
+            id,
+            serde_json::from_value(result).map_err(D::Error::custom)?,
+          ))
+        } else if map.contains_key("name") {
+          Err(D::Error::custom("a name result could be for a client or a group"))
+        } else {
+          Err(D::Error::custom("unknown result for id"))
+        }
+      }
+      _ => Err(D::Error::custom("invalid snapcast result")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CLIENT: &str = r#"{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":74}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488026416,"usec":135973},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}"#;
+
+  fn de(json: &str) -> SnapcastResult {
+    serde_json::from_str(json).unwrap()
+  }
+
+  #[test]
+  fn deserialize_uncorrelated_results() {
+    assert!(matches!(
+      de(&format!(r#"{{"client":{}}}"#, CLIENT)),
+      SnapcastResult::ClientGetStatus(_)
+    ));
+    assert!(matches!(
+      de(&format!(
+        r#"{{"group":{{"clients":[{}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":false,"name":"","stream_id":"stream 1"}}}}"#,
+        CLIENT
+      )),
+      SnapcastResult::GroupGetStatus(_)
+    ));
+    assert!(matches!(
+      de(
+        r#"{"server":{"groups":[],"server":{"host":{"arch":"x86_64","ip":"","mac":"","name":"T400","os":"Linux Mint 17.3 Rosa"},"snapserver":{"controlProtocolVersion":1,"name":"Snapserver","protocolVersion":1,"version":"0.10.0"}},"streams":[]}}"#
+      ),
+      SnapcastResult::ServerGetStatus(_)
+    ));
+    assert_eq!(
+      de(r#"{"major":2,"minor":0,"patch":0}"#),
+      SnapcastResult::ServerGetRPCVersion(server::TESTED_RPC_VERSION)
+    );
+    assert!(matches!(de(r#"{"id":"stream 1"}"#), SnapcastResult::StreamAddStream(_)));
+    assert_eq!(de(r#""ok""#), SnapcastResult::StreamControl("ok".to_string()));
+  }
+
+  #[test]
+  fn deserialize_results_with_id() {
+    assert!(matches!(
+      de(r#"["00:21:6a:7d:74:fc",{"volume":{"muted":false,"percent":36}}]"#),
+      SnapcastResult::ClientSetVolume(id, _) if id == "00:21:6a:7d:74:fc"
+    ));
+    assert!(matches!(
+      de(r#"["00:21:6a:7d:74:fc",{"latency":10}]"#),
+      SnapcastResult::ClientSetLatency(_, result) if result.latency == 10
+    ));
+    assert!(matches!(
+      de(r#"["4dcc4e3b-c699-a04b-7f0c-8260d23c43e1",{"mute":true}]"#),
+      SnapcastResult::GroupSetMute(_, result) if result.mute
+    ));
+    assert!(matches!(
+      de(r#"["4dcc4e3b-c699-a04b-7f0c-8260d23c43e1",{"stream_id":"stream 1"}]"#),
+      SnapcastResult::GroupSetStream(..)
+    ));
+
+    let result = SnapcastResult::GroupSetMute(
+      "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+      group::SetMuteResult { mute: true },
+    );
+    assert_eq!(de(&serde_json::to_string(&result).unwrap()), result);
+  }
+
+  #[test]
+  fn reject_ambiguous_results() {
+    assert!(serde_json::from_str::<SnapcastResult>(r#"{"volume":{"muted":false,"percent":36}}"#).is_err());
+    assert!(serde_json::from_str::<SnapcastResult>(r#"["00:21:6a:7d:74:fc",{"name":"Kitchen"}]"#).is_err());
+  }
+}
@@ -6,6 +6,7 @@ use super::{group::Group, stream::Stream};
 // the server
 /// The struct representing the full state of the Snapcast server
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Server {
   pub server: ServerDetails,
   pub groups: Vec<Group>,
@@ -13,12 +14,14 @@ pub struct Server {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct ServerDetails {
   pub host: Host,
   pub snapserver: Snapserver,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Snapserver {
   pub name: String,
   #[serde(rename = "protocolVersion")]
@@ -28,31 +31,58 @@ pub struct Snapserver {
   pub version: String,
 }
 
+/// the rpc version of the Snapcast server this crate was tested against
+pub const TESTED_RPC_VERSION: GetRpcVersionResult = GetRpcVersionResult {
+  major: 2,
+  minor: 0,
+  patch: 0,
+};
+
 // params and results
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// the rpc version of the Snapcast server, ordered by major, minor, then patch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetRpcVersionResult {
   pub major: usize,
   pub minor: usize,
   pub patch: usize,
 }
 
+impl GetRpcVersionResult {
+  /// the version as `(major, minor, patch)`, for comparing against a version literal
+  ///
+  /// # example
+  /// ```no_run
+  /// if version.as_tuple() >= (2, 0, 0) {
+  ///   println!("server speaks rpc 2");
+  /// }
+  /// ```
+  pub fn as_tuple(&self) -> (usize, usize, usize) {
+    (self.major, self.minor, self.patch)
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct GetStatusResult {
   pub server: Server,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct DeleteClientParams {
   pub id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct DeleteClientResult {
   pub server: Server,
 }
 
 // notifications
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnUpdateParams {
   pub server: Server,
 }
@@ -165,6 +195,8 @@ mod tests {
             raw: "pipe:///tmp/snapfifo?name=stream 1".to_string(),
             scheme: "pipe".to_string(),
           },
+          codec: None,
+          sample_format: None,
         },
         Stream {
           id: "stream 2".to_string(),
@@ -178,6 +210,8 @@ mod tests {
             raw: "pipe:///tmp/snapfifo?name=stream 2".to_string(),
             scheme: "pipe".to_string(),
           },
+          codec: None,
+          sample_format: None,
         },
       ],
     };
@@ -192,4 +226,13 @@ mod tests {
 
     assert_eq!(server.server.host.name, "T400");
   }
+
+  #[test]
+  fn rpc_version_as_tuple() {
+    let version: GetRpcVersionResult = serde_json::from_str(r#"{"major":2,"minor":1,"patch":3}"#).unwrap();
+
+    assert_eq!(version.as_tuple(), (2, 1, 3));
+    assert!(version.as_tuple() >= TESTED_RPC_VERSION.as_tuple());
+    assert!(version.as_tuple() < (2, 2, 0));
+  }
 }
@@ -1,24 +1,107 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
 
 // the stream
 /// A stream of audio maintained by the Snapcast server
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct Stream {
   pub id: String,
   pub properties: Option<StreamProperties>,
   pub status: StreamStatus,
   pub uri: StreamUri,
+  /// the codec, if the server reports it next to the uri, see [Stream::codec]
+  pub codec: Option<String>,
+  /// the sample format, if the server reports it next to the uri, see [Stream::sample_format]
+  #[serde(rename = "sampleformat")]
+  pub sample_format: Option<String>,
 }
 
+impl Stream {
+  /// the "artist - title" line shown by now-playing widgets
+  ///
+  /// # returns
+  /// the artists and title joined with " - ", only the title if there are no artists,
+  /// or [None] if the stream has no metadata or no title
+  pub fn now_playing(&self) -> Option<String> {
+    let metadata = self.properties.as_ref()?.metadata.as_ref()?;
+    let title = metadata.display_title()?;
+
+    Some(match metadata.display_artist() {
+      Some(artist) => format!("{} - {}", artist, title),
+      None => title.to_string(),
+    })
+  }
+
+  /// the codec the stream is encoded with, e.g. `flac`
+  ///
+  /// # returns
+  /// the top-level `codec` of the stream, else the `codec` parameter of the stream uri, or [None] if the server did
+  /// not report one
+  pub fn codec(&self) -> Option<&str> {
+    self
+      .codec
+      .as_deref()
+      .or_else(|| self.uri.query.get("codec").map(String::as_str))
+  }
+
+  /// the sample format of the stream as `rate:bits:channels`, e.g. `48000:16:2`
+  ///
+  /// # returns
+  /// the top-level `sampleformat` of the stream, else the `sampleformat` parameter of the stream uri, or [None] if
+  /// the server did not report one
+  pub fn sample_format(&self) -> Option<&str> {
+    self
+      .sample_format
+      .as_deref()
+      .or_else(|| self.uri.query.get("sampleformat").map(String::as_str))
+  }
+
+  /// whether `other` differs from this stream in anything but the playback [position](StreamProperties::position),
+  /// which moves constantly while the stream plays
+  pub fn differs_ignoring_position(&self, other: &Stream) -> bool {
+    let without_position = |stream: &Stream| {
+      stream.properties.as_ref().map(|properties| StreamProperties {
+        position: None,
+        ..properties.clone()
+      })
+    };
+
+    self.id != other.id
+      || self.status != other.status
+      || self.uri != other.uri
+      || self.codec != other.codec
+      || self.sample_format != other.sample_format
+      || without_position(self) != without_position(other)
+  }
+}
+
+/// the status of a stream
+///
+/// statuses not modeled by this crate are kept as [StreamStatus::Unknown] with the raw value
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "camelCase")]
+#[serde(from = "String", into = "String")]
 pub enum StreamStatus {
   Idle,
   Playing,
   Disabled,
-  Unknown,
+  Unknown(String),
+}
+
+impl StreamStatus {
+  /// the status as sent by the server
+  pub fn as_str(&self) -> &str {
+    match self {
+      StreamStatus::Idle => "idle",
+      StreamStatus::Playing => "playing",
+      StreamStatus::Disabled => "disabled",
+      StreamStatus::Unknown(status) => status,
+    }
+  }
 }
 
 impl From<&str> for StreamStatus {
@@ -27,12 +110,33 @@ impl From<&str> for StreamStatus {
       "idle" => StreamStatus::Idle,
       "playing" => StreamStatus::Playing,
       "disabled" => StreamStatus::Disabled,
-      _ => StreamStatus::Unknown,
+      _ => StreamStatus::Unknown(s.to_string()),
+    }
+  }
+}
+
+impl From<String> for StreamStatus {
+  fn from(s: String) -> Self {
+    match s.as_str() {
+      "idle" => StreamStatus::Idle,
+      "playing" => StreamStatus::Playing,
+      "disabled" => StreamStatus::Disabled,
+      _ => StreamStatus::Unknown(s),
+    }
+  }
+}
+
+impl From<StreamStatus> for String {
+  fn from(status: StreamStatus) -> Self {
+    match status {
+      StreamStatus::Unknown(status) => status,
+      status => status.as_str().to_string(),
     }
   }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct StreamUri {
   pub fragment: String,
   pub host: String,
@@ -61,6 +165,7 @@ pub enum StreamLoopStatus {
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct StreamProperties {
   pub playback_status: Option<StreamPlaybackStatus>,
   pub loop_status: Option<StreamLoopStatus>,
@@ -78,12 +183,108 @@ pub struct StreamProperties {
   pub metadata: Option<StreamMetadata>,
 }
 
+impl StreamProperties {
+  /// whether the capability flags allow `command`
+  ///
+  /// follows mpris: nothing is allowed without `can_control`, `PlayPause` needs `can_pause`, `Stop` needs only
+  /// `can_control`, and `Seek` and `SetPosition` need `can_seek`
+  pub fn supports(&self, command: &ControlCommand) -> bool {
+    self.can_control
+      && match command {
+        ControlCommand::Play => self.can_play,
+        ControlCommand::Pause | ControlCommand::PlayPause => self.can_pause,
+        ControlCommand::Stop => true,
+        ControlCommand::Next => self.can_go_next,
+        ControlCommand::Previous => self.can_go_previous,
+        ControlCommand::Seek { .. } | ControlCommand::SetPosition { .. } => self.can_seek,
+      }
+  }
+}
+
+/// the playback position of a stream, advancing on its own between `Stream.OnProperties` notifications
+///
+/// snapserver only reports the position when the properties change, so a progress bar built on it would jump. \
+/// this captures the position when the properties were received and extrapolates from there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamProgress {
+  position: Duration,
+  rate: f64,
+  playing: bool,
+  duration: Option<Duration>,
+  received_at: Instant,
+}
+
+impl StreamProgress {
+  /// capture the progress of `properties` received at `received_at`
+  pub fn received_at(properties: &StreamProperties, received_at: Instant) -> Self {
+    let seconds = |seconds: f64| Duration::try_from_secs_f64(seconds).unwrap_or_default();
+
+    Self {
+      position: seconds(properties.position.unwrap_or_default()),
+      rate: properties.rate.unwrap_or(1.0),
+      playing: properties.playback_status == Some(StreamPlaybackStatus::Playing),
+      duration: properties.metadata.as_ref().and_then(StreamMetadata::duration),
+      received_at,
+    }
+  }
+
+  /// the position of the stream at `now`
+  ///
+  /// advances with the playback rate only while playing, and never goes past the duration of the track if known
+  pub fn elapsed(&self, now: Instant) -> Duration {
+    let mut elapsed = self.position;
+    if self.playing {
+      let played = now.saturating_duration_since(self.received_at).as_secs_f64() * self.rate;
+      elapsed += Duration::try_from_secs_f64(played).unwrap_or_default();
+    }
+
+    match self.duration {
+      Some(duration) => elapsed.min(duration),
+      None => elapsed,
+    }
+  }
+
+  /// the duration of the current track, if known
+  pub fn duration(&self) -> Option<Duration> {
+    self.duration
+  }
+}
+
+/// seconds sent as a number, or as a string containing one by some stream plugins
+///
+/// a string that is not a number is dropped with a warning instead of failing the whole message
+fn lenient_seconds<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Seconds {
+    Number(f64),
+    Text(String),
+  }
+
+  match Option::<Seconds>::deserialize(deserializer)? {
+    Some(Seconds::Number(seconds)) => Ok(Some(seconds)),
+    Some(Seconds::Text(seconds)) => match seconds.trim().parse() {
+      Ok(seconds) => Ok(Some(seconds)),
+      Err(_) => {
+        tracing::warn!("ignoring non-numeric seconds {:?}", seconds);
+        Ok(None)
+      }
+    },
+    None => Ok(None),
+  }
+}
+
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct StreamMetadata {
   track_id: Option<String>,
   file: Option<String>,
+  #[serde(default, deserialize_with = "lenient_seconds")]
   duration: Option<f64>,
   artist: Option<Vec<String>>,
   artist_sort: Option<Vec<String>>,
@@ -126,30 +327,457 @@ pub struct StreamMetadata {
   spotify_track_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+impl StreamMetadata {
+  /// the duration of the current track, if known
+  ///
+  /// # returns
+  /// the duration with sub-second precision, or [None] if it is unknown, negative, or not finite
+  pub fn duration(&self) -> Option<Duration> {
+    self
+      .duration
+      .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+  }
+
+  /// the duration of the current track in seconds as sent by the server, if known
+  pub fn duration_secs(&self) -> Option<f64> {
+    self.duration
+  }
+
+  /// the title of the current track, falling back to the `name` of the track if there is no title
+  pub fn display_title(&self) -> Option<&str> {
+    self.title.as_deref().or(self.name.as_deref())
+  }
+
+  /// the artists of the current track joined with ", ", or [None] if there are none
+  pub fn display_artist(&self) -> Option<String> {
+    self
+      .artist
+      .as_ref()
+      .filter(|artists| !artists.is_empty())
+      .map(|artists| artists.join(", "))
+  }
+
+  /// the album of the current track
+  pub fn display_album(&self) -> Option<&str> {
+    self.album.as_deref()
+  }
+
+  /// the cover art of the current track as raw image bytes
+  ///
+  /// inline `artData` is decoded if present, otherwise `artUrl` is fetched over http(s). snapserver reports an
+  /// absolute url for its `__image_cache`, use [StreamMetadata::fetch_art_relative_to] if the url is relative.
+  ///
+  /// # returns
+  /// the image bytes, or an [ArtError] if there is no art or it could not be retrieved
+  ///
+  /// # example
+  /// ```no_run
+  /// let art = metadata.fetch_art().await.expect("could not fetch art");
+  /// ```
+  #[cfg(feature = "http")]
+  pub async fn fetch_art(&self) -> Result<Vec<u8>, ArtError> {
+    self.fetch_art_from(None).await
+  }
+
+  /// like [StreamMetadata::fetch_art], but a relative `artUrl` is resolved against `base`
+  ///
+  /// # args
+  /// `base`: the http address of the snapserver, e.g. `http://127.0.0.1:1780`
+  ///
+  /// # returns
+  /// the image bytes, or an [ArtError] if there is no art or it could not be retrieved
+  ///
+  /// # example
+  /// ```no_run
+  /// let art = metadata.fetch_art_relative_to("http://127.0.0.1:1780").await.expect("could not fetch art");
+  /// ```
+  #[cfg(feature = "http")]
+  pub async fn fetch_art_relative_to(&self, base: &str) -> Result<Vec<u8>, ArtError> {
+    self.fetch_art_from(Some(base)).await
+  }
+
+  #[cfg(feature = "http")]
+  async fn fetch_art_from(&self, base: Option<&str>) -> Result<Vec<u8>, ArtError> {
+    use base64::Engine;
+
+    if let Some(art_data) = self.art_data.as_ref().filter(|art_data| !art_data.data.is_empty()) {
+      return Ok(base64::engine::general_purpose::STANDARD.decode(&art_data.data)?);
+    }
+
+    let url = self.art_url.as_deref().ok_or(ArtError::NoArt)?;
+    crate::http::get(&crate::http::resolve(base, url)?).await
+  }
+}
+
+/// An error retrieving cover art with [StreamMetadata::fetch_art]
+#[cfg(feature = "http")]
+#[derive(Debug, thiserror::Error)]
+pub enum ArtError {
+  /// the metadata has neither inline art data nor an art url
+  #[error("no cover art available")]
+  NoArt,
+  /// the art url is relative and no base url was given to resolve it
+  #[error("relative art url: {0}")]
+  RelativeUrl(String),
+  /// the server responded with a status other than 200
+  #[error("http status {0}")]
+  Status(u16),
+  /// the art is larger than the limit for a single image
+  #[error("cover art is too large")]
+  TooLarge,
+  /// an error fetching the art url, including timeouts
+  #[error("http error: {0}")]
+  Http(#[from] reqwest::Error),
+  /// the inline art data is not valid base64
+  #[error("invalid art data: {0}")]
+  Base64(#[from] base64::DecodeError),
+}
+
+/// cover art sent inline with stream metadata
+///
+/// the [Debug] output only shows the length of `data` to keep logs readable
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct ArtData {
+  /// base64 encoded image, empty when art data is ignored by the connection
+  #[serde(default)]
   pub data: String,
   pub extension: String,
 }
 
+impl std::fmt::Debug for ArtData {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ArtData")
+      .field("data", &format_args!("<{} bytes>", self.data.len()))
+      .field("extension", &self.extension)
+      .finish()
+  }
+}
+
+// stream uris
+/// An error building a stream uri with [StreamUriBuilder]
+#[derive(Debug, Clone, thiserror::Error, PartialEq)]
+pub enum StreamUriError {
+  /// snapserver requires every stream to have a name
+  #[error("stream uri is missing a name")]
+  MissingName,
+  /// a meta stream needs at least one source stream
+  #[error("meta stream uri has no sources")]
+  MissingSources,
+  /// a query parameter has a value of the wrong type
+  #[error("invalid value {value:?} for stream uri parameter {key}")]
+  InvalidParam {
+    /// the key of the parameter
+    key: String,
+    /// the value that could not be parsed
+    value: String,
+  },
+}
+
+/// builder for the `streamUri` of a [Stream.AddStream](super::Method::StreamAddStream) request
+///
+/// the presets know which parameters each source type needs; everything else can be set with
+/// [param](StreamUriBuilder::param). values are percent encoded when the uri is built.
+///
+/// # example
+/// ```no_run
+/// let uri = StreamUriBuilder::tcp("0.0.0.0", 4953)
+///   .name("Turntable")
+///   .mode("server")
+///   .sampleformat("48000:16:2")
+///   .build()
+///   .expect("invalid stream uri");
+/// client.stream_add_stream(uri).await.expect("could not add stream");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamUriBuilder {
+  scheme: String,
+  host: String,
+  path: Vec<String>,
+  name: Option<String>,
+  query: Vec<(String, String)>,
+}
+
+impl StreamUriBuilder {
+  /// start a uri for any source type
+  ///
+  /// # args
+  /// `scheme`: the source type, e.g. `pipe`
+  /// `path`: the path of the source, e.g. `/tmp/snapfifo`
+  pub fn new(scheme: impl Into<String>, path: impl Into<String>) -> Self {
+    let path: String = path.into();
+
+    Self {
+      scheme: scheme.into(),
+      host: String::new(),
+      path: path.trim_start_matches('/').split('/').map(str::to_string).collect(),
+      name: None,
+      query: Vec::new(),
+    }
+  }
+
+  /// a named pipe that snapserver reads pcm audio from
+  pub fn pipe(path: impl Into<String>) -> Self {
+    Self::new("pipe", path)
+  }
+
+  /// a tcp socket that snapserver reads pcm audio from, see [mode](StreamUriBuilder::mode)
+  pub fn tcp(host: impl Into<String>, port: u16) -> Self {
+    Self {
+      host: format!("{}:{}", host.into(), port),
+      path: Vec::new(),
+      ..Self::new("tcp", "")
+    }
+  }
+
+  /// a process whose stdout snapserver reads pcm audio from, see [params](StreamUriBuilder::params)
+  pub fn process(path: impl Into<String>) -> Self {
+    Self::new("process", path)
+  }
+
+  /// a pcm file that snapserver plays in a loop
+  pub fn file(path: impl Into<String>) -> Self {
+    Self::new("file", path)
+  }
+
+  /// a shairport-sync process that snapserver starts
+  pub fn airplay(path: impl Into<String>) -> Self {
+    Self::new("airplay", path)
+  }
+
+  /// a librespot process that snapserver starts
+  pub fn librespot(path: impl Into<String>) -> Self {
+    Self::new("librespot", path)
+  }
+
+  /// a stream that plays the first active of the given streams, by name in order of priority
+  pub fn meta<I, S>(sources: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    Self {
+      path: sources.into_iter().map(Into::into).collect(),
+      ..Self::new("meta", "")
+    }
+  }
+
+  /// set the name of the stream, required for every source type
+  pub fn name(mut self, name: impl Into<String>) -> Self {
+    self.name = Some(name.into());
+    self
+  }
+
+  /// set the sample format, e.g. `48000:16:2`
+  pub fn sampleformat(self, sampleformat: impl Into<String>) -> Self {
+    self.param("sampleformat", sampleformat)
+  }
+
+  /// set the codec, e.g. `flac`
+  pub fn codec(self, codec: impl Into<String>) -> Self {
+    self.param("codec", codec)
+  }
+
+  /// set the size of the read chunks in milliseconds
+  pub fn chunk_ms(self, chunk_ms: usize) -> Self {
+    self.param("chunk_ms", chunk_ms.to_string())
+  }
+
+  /// set whether snapserver listens (`server`) or connects (`client`), for pipe and tcp sources
+  pub fn mode(self, mode: impl Into<String>) -> Self {
+    self.param("mode", mode)
+  }
+
+  /// set the command line arguments of a process source
+  pub fn params(self, params: impl Into<String>) -> Self {
+    self.param("params", params)
+  }
+
+  /// set any query parameter, replacing an earlier value for the same key
+  pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+    let key = key.into();
+    let value = value.into();
+
+    match self.query.iter_mut().find(|(k, _)| *k == key) {
+      Some((_, v)) => *v = value,
+      None => self.query.push((key, value)),
+    }
+
+    self
+  }
+
+  /// set every parameter of a librespot stream, in order of their keys
+  ///
+  /// a `name` in [LibrespotParams::other] sets the [name](StreamUriBuilder::name) of the stream
+  pub fn librespot_params(mut self, params: &LibrespotParams) -> Self {
+    let mut query: Vec<(String, String)> = params.to_query().into_iter().collect();
+    query.sort();
+
+    for (key, value) in query {
+      self = match key.as_str() {
+        "name" => self.name(value),
+        _ => self.param(key, value),
+      };
+    }
+
+    self
+  }
+
+  /// build the uri
+  ///
+  /// # returns
+  /// the uri to pass to [stream_add_stream](crate::SnapcastConnection::stream_add_stream)
+  pub fn build(self) -> Result<String, StreamUriError> {
+    let name = self.name.ok_or(StreamUriError::MissingName)?;
+    if self.scheme == "meta" && self.path.is_empty() {
+      return Err(StreamUriError::MissingSources);
+    }
+
+    let path: Vec<String> = self.path.iter().map(|segment| encode_uri_component(segment)).collect();
+    let query: Vec<String> = std::iter::once(("name".to_string(), name))
+      .chain(self.query)
+      .map(|(key, value)| format!("{}={}", encode_uri_component(&key), encode_uri_component(&value)))
+      .collect();
+
+    Ok(format!(
+      "{}://{}/{}?{}",
+      self.scheme,
+      self.host,
+      path.join("/"),
+      query.join("&")
+    ))
+  }
+}
+
+/// the query parameters of a librespot stream uri
+///
+/// parameters that are missing from the query are [None] and left out of [to_query](LibrespotParams::to_query).
+/// every other parameter, including the name, is kept as-is in `other`.
+///
+/// # example
+/// ```no_run
+/// let stream = client.state.stream("Spotify").expect("stream not found");
+/// let mut params = LibrespotParams::from_query(&stream.uri.query).expect("invalid librespot parameters");
+/// params.bitrate = Some(160);
+///
+/// let uri = StreamUriBuilder::librespot(stream.uri.path)
+///   .librespot_params(&params)
+///   .build()
+///   .expect("invalid stream uri");
+/// client.stream_remove_stream(stream.id).await.expect("could not remove stream");
+/// client.stream_add_stream(uri).await.expect("could not add stream");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LibrespotParams {
+  /// bitrate in kbit/s, one of `96`, `160`, or `320`
+  pub bitrate: Option<u16>,
+  /// codec the stream is encoded with, e.g. `flac`
+  pub codec: Option<String>,
+  /// size of the read chunks in milliseconds
+  pub chunk_ms: Option<usize>,
+  /// name of the device shown in spotify
+  pub devicename: Option<String>,
+  /// sample format, e.g. `44100:16:2`
+  pub sampleformat: Option<String>,
+  /// initial volume in percent
+  pub volume: Option<u8>,
+  /// whether to autoplay similar songs when the queue ends
+  pub autoplay: Option<bool>,
+  /// every other parameter
+  pub other: HashMap<String, String>,
+}
+
+impl LibrespotParams {
+  /// read the parameters from the query of a [StreamUri]
+  ///
+  /// # args
+  /// `query`: the query, usually [StreamUri::query]
+  ///
+  /// # returns
+  /// the parameters, or [StreamUriError::InvalidParam] if a typed parameter could not be parsed
+  pub fn from_query(query: &HashMap<String, String>) -> Result<Self, StreamUriError> {
+    let mut params = Self::default();
+
+    for (key, value) in query {
+      match key.as_str() {
+        "bitrate" => params.bitrate = Some(parse_param(key, value)?),
+        "codec" => params.codec = Some(value.clone()),
+        "chunk_ms" => params.chunk_ms = Some(parse_param(key, value)?),
+        "devicename" => params.devicename = Some(value.clone()),
+        "sampleformat" => params.sampleformat = Some(value.clone()),
+        "volume" => params.volume = Some(parse_param(key, value)?),
+        "autoplay" => params.autoplay = Some(parse_param(key, value)?),
+        _ => {
+          params.other.insert(key.clone(), value.clone());
+        }
+      }
+    }
+
+    Ok(params)
+  }
+
+  /// write the parameters back into the shape of [StreamUri::query]
+  pub fn to_query(&self) -> HashMap<String, String> {
+    let mut query = self.other.clone();
+
+    let typed = [
+      ("bitrate", self.bitrate.map(|bitrate| bitrate.to_string())),
+      ("codec", self.codec.clone()),
+      ("chunk_ms", self.chunk_ms.map(|chunk_ms| chunk_ms.to_string())),
+      ("devicename", self.devicename.clone()),
+      ("sampleformat", self.sampleformat.clone()),
+      ("volume", self.volume.map(|volume| volume.to_string())),
+      ("autoplay", self.autoplay.map(|autoplay| autoplay.to_string())),
+    ];
+    for (key, value) in typed {
+      if let Some(value) = value {
+        query.insert(key.to_string(), value);
+      }
+    }
+
+    query
+  }
+}
+
+fn parse_param<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, StreamUriError> {
+  value.parse().map_err(|_| StreamUriError::InvalidParam {
+    key: key.to_string(),
+    value: value.to_string(),
+  })
+}
+
+fn encode_uri_component(value: &str) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' => (byte as char).to_string(),
+      _ => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
 // params and results
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct AddStreamParams {
   #[serde(rename = "streamUri")]
   pub stream_uri: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct AddStreamResult {
   pub id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct RemoveStreamParams {
   pub id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct RemoveStreamResult {
   pub id: String,
 }
@@ -174,6 +802,10 @@ pub enum ControlCommand {
   SetPosition { position: f64 },
 }
 
+/// the result of a `Stream.Control` request
+///
+/// the server always answers `"ok"` and reports failures as errors instead, so the value carries no information. the
+/// new position of a seek arrives with the next `Stream.OnProperties` notification.
 pub type ControlResult = String;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -193,16 +825,22 @@ pub enum SetPropertyProperties {
   Rate(f64),
 }
 
+/// the result of a `Stream.SetProperty` request
+///
+/// the server always answers `"ok"` and reports failures as errors instead, so the value carries no information. the
+/// new value arrives with the next `Stream.OnProperties` notification.
 pub type SetPropertiesResult = String;
 
 // notifications
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnUpdateParams {
   pub id: String,
   pub stream: Stream,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "strict", serde(deny_unknown_fields))]
 pub struct OnPropertiesParams {
   pub id: String,
   pub properties: StreamProperties,
@@ -228,6 +866,8 @@ mod tests {
         raw: "pipe:///tmp/snapfifo?name=stream 1".to_string(),
         scheme: "pipe".to_string(),
       },
+      codec: None,
+      sample_format: None,
     };
 
     let json = serde_json::to_string(&stream).unwrap();
@@ -243,4 +883,299 @@ mod tests {
 
     assert_eq!(stream.id, "stream 1");
   }
+
+  #[test]
+  fn codec_and_sample_format() {
+    let json = r#"{"id":"stream 1","status":"idle","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{"chunk_ms":"20","codec":"flac","name":"stream 1","sampleformat":"48000:16:2"},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}"#;
+    let pipe: Stream = serde_json::from_str(json).unwrap();
+    assert_eq!(pipe.codec(), Some("flac"));
+    assert_eq!(pipe.sample_format(), Some("48000:16:2"));
+
+    let json = r#"{"id":"Spotify","status":"idle","uri":{"fragment":"","host":"","path":"/usr/bin/librespot","query":{"bitrate":"320","codec":"opus","name":"Spotify"},"raw":"librespot:////usr/bin/librespot?bitrate=320&codec=opus&name=Spotify","scheme":"librespot"}}"#;
+    let librespot: Stream = serde_json::from_str(json).unwrap();
+    assert_eq!(librespot.codec(), Some("opus"));
+    assert_eq!(librespot.sample_format(), None);
+
+    let json = r#"{"id":"Spotify","status":"idle","codec":"flac","sampleformat":"44100:16:2","uri":{"fragment":"","host":"","path":"/usr/bin/librespot","query":{"codec":"opus","name":"Spotify"},"raw":"librespot:////usr/bin/librespot?codec=opus&name=Spotify","scheme":"librespot"}}"#;
+    let top_level: Stream = serde_json::from_str(json).unwrap();
+    assert_eq!(top_level.codec(), Some("flac"));
+    assert_eq!(top_level.sample_format(), Some("44100:16:2"));
+    assert_eq!(
+      serde_json::from_str::<Stream>(&serde_json::to_string(&top_level).unwrap()).unwrap(),
+      top_level
+    );
+
+    let json = r#"{"id":"stream 1","status":"idle","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{},"raw":"pipe:///tmp/snapfifo","scheme":"pipe"}}"#;
+    let bare: Stream = serde_json::from_str(json).unwrap();
+    assert_eq!(bare.codec(), None);
+    assert_eq!(bare.sample_format(), None);
+  }
+
+  #[test]
+  fn deserialize_unknown_stream_status() {
+    let json = r#"{"id":"stream 1","status":"starting","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}"#;
+    let stream: Stream = serde_json::from_str(json).unwrap();
+
+    assert_eq!(stream.status, StreamStatus::Unknown("starting".to_string()));
+    assert_eq!(serde_json::to_string(&stream).unwrap(), json);
+  }
+
+  #[test]
+  fn build_stream_uris() {
+    let pipe = StreamUriBuilder::pipe("/tmp/snapfifo")
+      .name("stream 1")
+      .sampleformat("48000:16:2")
+      .mode("create");
+    assert_eq!(
+      pipe.build().unwrap(),
+      "pipe:///tmp/snapfifo?name=stream%201&sampleformat=48000:16:2&mode=create"
+    );
+
+    let tcp = StreamUriBuilder::tcp("0.0.0.0", 4953).name("tcp").mode("server");
+    assert_eq!(tcp.build().unwrap(), "tcp://0.0.0.0:4953/?name=tcp&mode=server");
+
+    let process = StreamUriBuilder::process("/usr/bin/mpv")
+      .name("radio")
+      .params("--no-video --audio-display=no http://example.com/stream?a=b&c");
+    assert_eq!(
+      process.build().unwrap(),
+      "process:///usr/bin/mpv?name=radio&params=--no-video%20--audio-display%3Dno%20http:%2F%2Fexample.com%2Fstream%3Fa%3Db%26c"
+    );
+
+    let meta = StreamUriBuilder::meta(["Spotify", "Line In"]).name("mix");
+    assert_eq!(meta.build().unwrap(), "meta:///Spotify/Line%20In?name=mix");
+  }
+
+  #[test]
+  fn stream_uri_requires_name_and_sources() {
+    assert_eq!(
+      StreamUriBuilder::file("/tmp/audio.pcm").build(),
+      Err(StreamUriError::MissingName)
+    );
+    assert_eq!(
+      StreamUriBuilder::meta(Vec::<String>::new()).name("mix").build(),
+      Err(StreamUriError::MissingSources)
+    );
+  }
+
+  #[test]
+  fn librespot_params() {
+    let json = r#"{"id":"Porches Spotify","status":"idle","uri":{"fragment":"","host":"","path":"/usr/bin/librespot","query":{"autoplay":"true","bitrate":"320","chunk_ms":"20","codec":"flac","devicename":"Porches","name":"Porches Spotify","sampleformat":"44100:16:2","volume":"50"},"raw":"librespot:////usr/bin/librespot?autoplay=true&bitrate=320&chunk_ms=20&codec=flac&devicename=Porches&name=Porches Spotify&sampleformat=44100:16:2&volume=50","scheme":"librespot"}}"#;
+    let stream: Stream = serde_json::from_str(json).unwrap();
+
+    let mut params = LibrespotParams::from_query(&stream.uri.query).unwrap();
+    assert_eq!(
+      params,
+      LibrespotParams {
+        bitrate: Some(320),
+        codec: Some("flac".to_string()),
+        chunk_ms: Some(20),
+        devicename: Some("Porches".to_string()),
+        sampleformat: Some("44100:16:2".to_string()),
+        volume: Some(50),
+        autoplay: Some(true),
+        other: HashMap::from([("name".to_string(), "Porches Spotify".to_string())]),
+      }
+    );
+    assert_eq!(params.to_query(), stream.uri.query);
+
+    params.bitrate = Some(160);
+    params.autoplay = None;
+    let uri = StreamUriBuilder::librespot(stream.uri.path)
+      .librespot_params(&params)
+      .build()
+      .unwrap();
+    assert_eq!(
+      uri,
+      "librespot:///usr/bin/librespot?name=Porches%20Spotify&bitrate=160&chunk_ms=20&codec=flac&devicename=Porches&sampleformat=44100:16:2&volume=50"
+    );
+
+    let query = HashMap::from([("volume".to_string(), "loud".to_string())]);
+    assert_eq!(
+      LibrespotParams::from_query(&query),
+      Err(StreamUriError::InvalidParam {
+        key: "volume".to_string(),
+        value: "loud".to_string(),
+      })
+    );
+  }
+
+  #[test]
+  fn now_playing() {
+    let mut stream = Stream {
+      id: "stream 1".to_string(),
+      status: "playing".into(),
+      properties: None,
+      uri: StreamUri {
+        fragment: "".to_string(),
+        host: "".to_string(),
+        path: "/tmp/snapfifo".to_string(),
+        query: HashMap::new(),
+        raw: "pipe:///tmp/snapfifo?name=stream 1".to_string(),
+        scheme: "pipe".to_string(),
+      },
+      codec: None,
+      sample_format: None,
+    };
+    assert_eq!(stream.now_playing(), None);
+
+    let properties = r#"{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{"album":"Discovery","artist":["Daft Punk","Romanthony"],"title":"One More Time"}}"#;
+    stream.properties = Some(serde_json::from_str(properties).unwrap());
+
+    let metadata = stream.properties.as_ref().unwrap().metadata.as_ref().unwrap();
+    assert_eq!(metadata.display_title(), Some("One More Time"));
+    assert_eq!(metadata.display_artist().as_deref(), Some("Daft Punk, Romanthony"));
+    assert_eq!(metadata.display_album(), Some("Discovery"));
+    assert_eq!(
+      stream.now_playing().as_deref(),
+      Some("Daft Punk, Romanthony - One More Time")
+    );
+
+    let properties = r#"{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{"artist":[],"title":"Radio"}}"#;
+    stream.properties = Some(serde_json::from_str(properties).unwrap());
+    assert_eq!(stream.now_playing().as_deref(), Some("Radio"));
+  }
+
+  #[cfg(feature = "http")]
+  #[tokio::test]
+  async fn fetch_art() {
+    use tokio::{
+      io::{AsyncReadExt, AsyncWriteExt},
+      net::TcpListener,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+      loop {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![0; 1024];
+        let read = socket.read(&mut request).await.unwrap();
+        let request = String::from_utf8_lossy(&request[..read]).to_string();
+        let response: &[u8] = if request.starts_with("GET /huge ") {
+          b"HTTP/1.1 200 OK\r\nContent-Length: 1000000000\r\n\r\n"
+        } else {
+          b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 4\r\n\r\nart!"
+        };
+        socket.write_all(response).await.unwrap();
+        requests.send(request).unwrap();
+      }
+    });
+
+    let metadata: StreamMetadata = serde_json::from_str(r#"{"artUrl":"/__image_cache?name=cover.png"}"#).unwrap();
+    assert!(matches!(metadata.fetch_art().await, Err(ArtError::RelativeUrl(_))));
+    assert_eq!(metadata.fetch_art_relative_to(&base).await.unwrap(), b"art!");
+    assert!(received
+      .recv()
+      .await
+      .unwrap()
+      .starts_with("GET /__image_cache?name=cover.png HTTP/1.1\r\n"));
+
+    let metadata = StreamMetadata {
+      art_url: Some(format!("{}/__image_cache?name=cover.png", base)),
+      ..serde_json::from_str("{}").unwrap()
+    };
+    assert_eq!(metadata.fetch_art().await.unwrap(), b"art!");
+
+    let metadata = StreamMetadata {
+      art_url: Some(format!("{}/huge", base)),
+      ..serde_json::from_str("{}").unwrap()
+    };
+    assert!(matches!(metadata.fetch_art().await, Err(ArtError::TooLarge)));
+
+    let metadata: StreamMetadata =
+      serde_json::from_str(r#"{"artData":{"data":"YXJ0IQ==","extension":"png"},"artUrl":"/unused"}"#).unwrap();
+    assert_eq!(metadata.fetch_art().await.unwrap(), b"art!");
+
+    let metadata: StreamMetadata = serde_json::from_str("{}").unwrap();
+    assert!(matches!(metadata.fetch_art().await, Err(ArtError::NoArt)));
+  }
+
+  #[test]
+  fn deserialize_lenient_duration() {
+    let duration = |json: &str| serde_json::from_str::<StreamMetadata>(json).unwrap().duration();
+
+    assert_eq!(
+      duration(r#"{"duration":217.945}"#),
+      Some(Duration::from_millis(217_945))
+    );
+    assert_eq!(duration(r#"{"duration":218}"#), Some(Duration::from_secs(218)));
+    assert_eq!(
+      duration(r#"{"duration":"217.5"}"#),
+      Some(Duration::from_millis(217_500))
+    );
+    assert_eq!(duration(r#"{"duration":null}"#), None);
+    assert_eq!(duration(r#"{"duration":-1}"#), None);
+    assert_eq!(duration("{}"), None);
+    assert_eq!(duration(r#"{"duration":"unknown"}"#), None);
+  }
+
+  #[test]
+  fn progress_advances_while_playing() {
+    let properties = r#"{"canControl":true,"canGoNext":false,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":true,"playbackStatus":"playing","position":30.0,"metadata":{"duration":40.0}}"#;
+    let mut properties: StreamProperties = serde_json::from_str(properties).unwrap();
+    let received_at = Instant::now();
+
+    let progress = StreamProgress::received_at(&properties, received_at);
+    assert_eq!(progress.elapsed(received_at), Duration::from_secs(30));
+    assert_eq!(
+      progress.elapsed(received_at + Duration::from_secs(5)),
+      Duration::from_secs(35)
+    );
+    assert_eq!(
+      progress.elapsed(received_at + Duration::from_secs(60)),
+      Duration::from_secs(40)
+    );
+
+    properties.rate = Some(2.0);
+    let progress = StreamProgress::received_at(&properties, received_at);
+    assert_eq!(
+      progress.elapsed(received_at + Duration::from_secs(2)),
+      Duration::from_secs(34)
+    );
+
+    properties.playback_status = Some(StreamPlaybackStatus::Paused);
+    let progress = StreamProgress::received_at(&properties, received_at);
+    assert_eq!(
+      progress.elapsed(received_at + Duration::from_secs(5)),
+      Duration::from_secs(30)
+    );
+  }
+
+  #[test]
+  fn differs_ignoring_position() {
+    let stream: Stream = serde_json::from_str(r#"{"id":"stream 1","status":"playing","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{"name":"stream 1"},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"},"properties":{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"position":10.5}}"#).unwrap();
+
+    let mut moved = stream.clone();
+    moved.properties.as_mut().unwrap().position = Some(12.0);
+    assert!(!stream.differs_ignoring_position(&moved));
+
+    moved.status = StreamStatus::Idle;
+    assert!(stream.differs_ignoring_position(&moved));
+  }
+
+  #[test]
+  fn supports_follows_capabilities() {
+    let properties: StreamProperties = serde_json::from_str(
+      r#"{"canControl":true,"canGoNext":true,"canGoPrevious":false,"canPause":false,"canPlay":true,"canSeek":true}"#,
+    )
+    .unwrap();
+
+    assert!(properties.supports(&ControlCommand::Play));
+    assert!(!properties.supports(&ControlCommand::Pause));
+    assert!(!properties.supports(&ControlCommand::PlayPause));
+    assert!(properties.supports(&ControlCommand::Stop));
+    assert!(properties.supports(&ControlCommand::Next));
+    assert!(!properties.supports(&ControlCommand::Previous));
+    assert!(properties.supports(&ControlCommand::Seek { offset: 10.0 }));
+    assert!(properties.supports(&ControlCommand::SetPosition { position: 0.0 }));
+
+    let locked = StreamProperties {
+      can_control: false,
+      ..properties
+    };
+    assert!(!locked.supports(&ControlCommand::Play));
+    assert!(!locked.supports(&ControlCommand::Stop));
+  }
 }
@@ -1,22 +1,32 @@
 use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
 use std::{
-  cell::OnceCell,
   collections::HashSet,
-  sync::{Arc, RwLock},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, OnceLock, RwLock,
+  },
+  time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
 
-use crate::protocol::{
-  client::{Client, ClientVolume},
-  group::Group,
-  server::{Server, ServerDetails},
-  stream::{Stream, StreamProperties},
-  Notification, SnapcastResult,
+use crate::{
+  clock::ClockHook,
+  protocol::{
+    client::{Client, ClientVolume},
+    group::Group,
+    server::{GetRpcVersionResult, Server, ServerDetails},
+    stream::{ControlCommand, Stream, StreamProgress, StreamProperties, StreamStatus},
+    Identifiable, Message, Notification, SnapcastResult,
+  },
 };
 
 /// group details as stored in the state object
 ///
-/// this contains a [HashSet] of client ids instead of a vec of client structs
-#[derive(Clone, Debug)]
+/// this contains a [HashSet] of client ids instead of a vec of client structs. \
+/// serializes to `{"id", "name", "stream_id", "muted", "clients"}`, the field names of [Group], with `clients` as an
+/// array of ids sorted ascending, so the json is stable and can be cached or compared.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct StateGroup {
   /// group id
   pub id: String,
@@ -27,17 +37,221 @@ pub struct StateGroup {
   /// group muted status
   pub muted: bool,
   /// set of client ids in group
+  ///
+  /// iteration order is unspecified and changes between updates, use [State::group_client_ids_ordered] for a
+  /// stable order
+  #[serde(serialize_with = "sorted_ids")]
   pub clients: HashSet<String>,
 }
 
+/// serialize a set of ids as a sorted array, so the output does not depend on the iteration order of the set
+fn sorted_ids<S>(ids: &HashSet<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer,
+{
+  let mut ids: Vec<&String> = ids.iter().collect();
+  ids.sort();
+  serializer.collect_seq(ids)
+}
+
+impl StateGroup {
+  /// inflate the client ids back into the [Group] shape the server reports
+  ///
+  /// # args
+  /// `clients`: the clients to look the ids up in, usually [State::clients]
+  ///
+  /// # returns
+  /// the [Group] with its clients sorted by id. ids missing from `clients` are left out.
+  pub fn to_group(&self, clients: &DashMap<String, Client>) -> Group {
+    let mut group_clients: Vec<Client> = self
+      .clients
+      .iter()
+      .filter_map(|id| clients.get(id).map(|client| client.clone()))
+      .collect();
+    group_clients.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Group {
+      id: self.id.clone(),
+      name: self.name.clone(),
+      stream_id: self.stream_id.clone(),
+      muted: self.muted,
+      clients: group_clients,
+    }
+  }
+}
+
+impl From<&Group> for StateGroup {
+  fn from(group: &Group) -> Self {
+    Self {
+      id: group.id.clone(),
+      name: group.name.clone(),
+      stream_id: group.stream_id.clone(),
+      muted: group.muted,
+      clients: group.clients.iter().map(|client| client.id.clone()).collect(),
+    }
+  }
+}
+
+impl Identifiable for StateGroup {
+  fn id(&self) -> &str {
+    &self.id
+  }
+}
+
+/// A change made to the [State], broadcast to every subscriber
+///
+/// [StateChange::ClientChanged] and [StateChange::GroupChanged] carry the full entry after the change was applied.
+/// the per-field events like [StateChange::ClientVolumeChanged] or [StateChange::GroupMuteChanged] carry the id of the
+/// entry and the `old` and `new` value of the field, and [StateChange::StreamChanged] carries the stream, which is
+/// [None] while its properties are being fetched.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateChange {
+  /// the host and snapserver information changed
+  ServerChanged(ServerDetails),
+  /// a group was added
+  GroupAdded(StateGroup),
+  /// a group changed
+  GroupChanged(StateGroup),
+  /// a group was removed, with its id
+  GroupRemoved(String),
+  /// a client was added
+  ClientAdded(Client),
+  /// a client changed, not sent when only its [last_seen](Client::last_seen) changed
+  ClientChanged(Client),
+  /// a client was removed, with its id
+  ClientRemoved(String),
+  /// the volume of a client changed, sent after its [StateChange::ClientChanged]
+  ClientVolumeChanged {
+    /// the id of the client
+    id: String,
+    /// the volume before the change
+    old: ClientVolume,
+    /// the volume after the change
+    new: ClientVolume,
+  },
+  /// the latency of a client changed, sent after its [StateChange::ClientChanged]
+  ClientLatencyChanged {
+    /// the id of the client
+    id: String,
+    /// the latency in milliseconds before the change
+    old: usize,
+    /// the latency in milliseconds after the change
+    new: usize,
+  },
+  /// the name of a client changed, sent after its [StateChange::ClientChanged]
+  ClientNameChanged {
+    /// the id of the client
+    id: String,
+    /// the name before the change
+    old: String,
+    /// the name after the change
+    new: String,
+  },
+  /// the name of a group changed, sent after its [StateChange::GroupChanged]
+  GroupNameChanged {
+    /// the id of the group
+    id: String,
+    /// the name before the change
+    old: String,
+    /// the name after the change
+    new: String,
+  },
+  /// a group was muted or unmuted, sent after its [StateChange::GroupChanged]
+  GroupMuteChanged {
+    /// the id of the group
+    id: String,
+    /// the mute state before the change
+    old: bool,
+    /// the mute state after the change
+    new: bool,
+  },
+  /// a group switched to another stream, sent after its [StateChange::GroupChanged]
+  GroupStreamChanged {
+    /// the id of the group
+    id: String,
+    /// the stream id before the change
+    old: String,
+    /// the stream id after the change
+    new: String,
+  },
+  /// a partial update for a client, group, or stream was dropped because the entity is not in the state, e.g. when
+  /// a result raced a reconnect. the state may be out of date until the next full server status.
+  StaleUpdateIgnored { id: String },
+  /// a client became a member of a group, sent after the [StateChange::GroupChanged] or [StateChange::GroupAdded] of
+  /// the group. this includes the clients of every group added by the first server status, before
+  /// [StateChange::Ready].
+  ClientJoinedGroup { client: String, group: String },
+  /// a client is no longer a member of a group, sent after the [StateChange::GroupChanged] or
+  /// [StateChange::GroupRemoved] of the group
+  ClientLeftGroup { client: String, group: String },
+  /// a stream was added, the stream is [None] until its properties have been fetched
+  StreamAdded { id: String, stream: Option<Stream> },
+  /// a stream changed
+  StreamChanged { id: String, stream: Option<Stream> },
+  /// a stream was removed, with its id
+  StreamRemoved(String),
+  /// a stream became a placeholder whose details are still loading, sent after the [StateChange::StreamAdded] or
+  /// [StateChange::StreamChanged] that stored it as [None], see [State::is_stream_loaded]
+  StreamPending { id: String },
+  /// the first full server status since connecting was applied, sent once per connection
+  Ready,
+}
+
+/// aggregate counts of the entries in the [State], see [State::counts]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateCounts {
+  /// number of groups
+  pub groups: usize,
+  /// number of clients
+  pub clients: usize,
+  /// number of clients the server lists as connected
+  pub connected_clients: usize,
+  /// number of streams
+  pub streams: usize,
+  /// number of streams that are playing
+  pub playing_streams: usize,
+}
+
+/// the volume of a group's clients combined into one value, see [State::group_volume]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupVolumeSummary {
+  /// mean volume percent of the clients, rounded to the nearest integer
+  pub average: u8,
+  /// highest volume percent of the clients
+  pub max: u8,
+  /// lowest volume percent of the clients
+  pub min: u8,
+  /// whether every client is muted
+  pub all_muted: bool,
+}
+
+/// a group joined with the stream it plays, see [State::overview]
+///
+/// serializes to `{"group", "stream", "now_playing"}`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GroupOverview {
+  /// the group
+  pub group: StateGroup,
+  /// the stream of the group, or [None] if it is not in the state or its details have not been loaded
+  pub stream: Option<Stream>,
+  /// the now-playing line of the stream, see [Stream::now_playing]
+  pub now_playing: Option<String>,
+}
+
 /// A wrapped state that can be shared between threads
 pub type WrappedState = Arc<State>;
 
 /// The state of the Snapcast server, automatically kept up to date by the client
-#[derive(Debug, Default)]
+///
+/// the maps are [DashMap]s: a reference returned by `get` or held while iterating locks part of the map until it is
+/// dropped, and the connection cannot apply updates to that part meanwhile. never hold one while awaiting, or while
+/// accessing the same map again on the same thread, as that can deadlock. \
+/// the accessors like [State::group], [State::clients_snapshot], and [State::loaded_streams] return owned copies
+/// and are the recommended way to read the state.
+#[derive(Debug)]
 pub struct State {
   /// host and snapserver information
-  pub server: OnceCell<RwLock<ServerDetails>>,
+  pub server: OnceLock<RwLock<ServerDetails>>,
   /// group information keyed by group id
   pub groups: DashMap<String, StateGroup>,
   /// client information keyed by client id
@@ -45,231 +259,1962 @@ pub struct State {
   /// stream information keyed by stream id \
   /// None indicates that the stream was recently added and properties have not been fetched
   pub streams: DashMap<String, Option<Stream>>,
-}
 
-enum ClientPartialUpdate {
-  Volume(ClientVolume),
-  Latency(usize),
-  Name(String),
+  // internal
+  rpc_version: RwLock<Option<GetRpcVersionResult>>,
+  changes: broadcast::Sender<StateChange>,
+  update_lock: Mutex<()>,
+  ready: Arc<AtomicBool>,
+  unconfirmed: Mutex<HashSet<String>>,
+  /// when the properties of each stream were received, for [State::stream_progress]. only ever locked after
+  /// `streams`, never before
+  properties_received: DashMap<String, Instant>,
+  /// the clock of the connection, for stamping received properties
+  clock: ClockHook,
+  /// how many times a client entry was locked for writing
+  #[cfg(test)]
+  client_writes: std::sync::atomic::AtomicUsize,
 }
 
-enum GroupPartialUpdate {
-  Name(String),
-  StreamId(String),
-  Muted(bool),
+impl Default for State {
+  fn default() -> Self {
+    Self {
+      server: OnceLock::new(),
+      groups: DashMap::new(),
+      clients: DashMap::new(),
+      streams: DashMap::new(),
+      rpc_version: RwLock::new(None),
+      changes: broadcast::channel(CHANGE_CAPACITY).0,
+      update_lock: Mutex::new(()),
+      ready: Arc::new(AtomicBool::new(false)),
+      unconfirmed: Mutex::default(),
+      properties_received: DashMap::new(),
+      clock: ClockHook::default(),
+      #[cfg(test)]
+      client_writes: Default::default(),
+    }
+  }
 }
 
-enum StreamPartialUpdate {
-  Properties(StreamProperties),
+/// how many changes a subscriber may fall behind before it starts missing them
+const CHANGE_CAPACITY: usize = 256;
+
+/// An update to apply to the [State] with [State::apply]
+///
+/// every message received from the server is translated into these, so applying them by hand behaves exactly like
+/// receiving the equivalent messages
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateUpdate {
+  /// replace everything with a full server status
+  Server(Server),
+  /// store the rpc version of the server
+  RpcVersion(GetRpcVersionResult),
+  /// add or replace a client
+  Client(Client),
+  /// remove a client
+  ClientRemoved(String),
+  /// set the volume of a client
+  ClientVolume { id: String, volume: ClientVolume },
+  /// set the latency of a client
+  ClientLatency { id: String, latency: usize },
+  /// set the name of a client
+  ClientName { id: String, name: String },
+  /// add or replace a group, without touching its clients
+  Group(Group),
+  /// set the mute status of a group
+  GroupMuted { id: String, muted: bool },
+  /// set the stream of a group
+  GroupStream { id: String, stream_id: String },
+  /// set the name of a group
+  GroupName { id: String, name: String },
+  /// add or replace a stream, [None] if its details have not been loaded
+  Stream { id: String, stream: Option<Box<Stream>> },
+  /// remove a stream
+  StreamRemoved(String),
+  /// set the properties of a stream
+  StreamProperties {
+    id: String,
+    properties: Box<StreamProperties>,
+  },
 }
 
 impl State {
-  pub(crate) fn handle_result(&self, data: SnapcastResult) {
-    match data {
-      // client
-      SnapcastResult::ClientGetStatus(result) => self.client_upsert(result.client),
-      SnapcastResult::ClientSetVolume(id, result) => {
-        self.client_partial_update(id, ClientPartialUpdate::Volume(result.volume))
-      }
-      SnapcastResult::ClientSetLatency(id, result) => {
-        self.client_partial_update(id, ClientPartialUpdate::Latency(result.latency))
-      }
-      SnapcastResult::ClientSetName(id, result) => {
-        self.client_partial_update(id, ClientPartialUpdate::Name(result.name))
+  /// an empty state that takes the time from `clock`
+  pub(crate) fn with_clock(clock: ClockHook) -> Self {
+    Self {
+      clock,
+      ..Self::default()
+    }
+  }
+
+  /// the protocol versions reported by the snapserver
+  ///
+  /// # returns
+  /// a tuple of `(protocol_version, control_protocol_version)`, or [None] if the server status has not been loaded
+  pub fn server_protocol_version(&self) -> Option<(usize, usize)> {
+    let server = self.server.get()?.read().expect("rwlock poisoned");
+
+    Some((
+      server.snapserver.protocol_version,
+      server.snapserver.control_protocol_version,
+    ))
+  }
+
+  /// the snapserver version, e.g. `0.26.0`
+  ///
+  /// # returns
+  /// the version string, or [None] if the server status has not been loaded
+  pub fn snapserver_version(&self) -> Option<String> {
+    let server = self.server.get()?.read().expect("rwlock poisoned");
+
+    Some(server.snapserver.version.clone())
+  }
+
+  /// the rpc version reported by the last `Server.GetRPCVersion` result
+  ///
+  /// # returns
+  /// the [GetRpcVersionResult], or [None] if the rpc version has not been requested
+  pub fn rpc_version(&self) -> Option<GetRpcVersionResult> {
+    self.rpc_version.read().expect("rwlock poisoned").clone()
+  }
+
+  /// count the groups, clients, and streams in the state
+  ///
+  /// # returns
+  /// an owned [StateCounts], so no locks are held once it is returned
+  pub fn counts(&self) -> StateCounts {
+    let connected_clients = self.clients.iter().filter(|client| client.connected).count();
+    let playing_streams = self.streams.iter().filter(|stream| is_playing(stream.value())).count();
+
+    StateCounts {
+      groups: self.groups.len(),
+      clients: self.clients.len(),
+      connected_clients,
+      streams: self.streams.len(),
+      playing_streams,
+    }
+  }
+
+  /// whether any stream is playing
+  pub fn any_playing(&self) -> bool {
+    self.streams.iter().any(|stream| is_playing(stream.value()))
+  }
+
+  /// a copy of a single group
+  ///
+  /// # args
+  /// `id`: the id of the group
+  pub fn group(&self, id: &str) -> Option<StateGroup> {
+    self.groups.get(id).map(|group| group.clone())
+  }
+
+  /// a copy of a single client
+  ///
+  /// # args
+  /// `id`: the id of the client
+  pub fn client(&self, id: &str) -> Option<Client> {
+    self.clients.get(id).map(|client| client.clone())
+  }
+
+  /// a copy of a single stream, [None] if it does not exist or has not been loaded
+  ///
+  /// # args
+  /// `id`: the id of the stream
+  pub fn stream(&self, id: &str) -> Option<Stream> {
+    self.streams.get(id).and_then(|stream| stream.clone())
+  }
+
+  /// whether a stream accepts a control command, to grey out unsupported controls instead of sending them
+  ///
+  /// # args
+  /// `stream_id`: the id of the stream
+  /// `command`: the command to check, see [StreamProperties::supports]
+  ///
+  /// # returns
+  /// whether the capability flags of the stream allow the command, or [None] if the stream does not exist or its
+  /// properties have not been loaded
+  pub fn can_control(&self, stream_id: &str, command: &ControlCommand) -> Option<bool> {
+    let stream = self.streams.get(stream_id)?;
+    let properties = stream.as_ref()?.properties.as_ref()?;
+
+    Some(properties.supports(command))
+  }
+
+  /// an owned snapshot of every group, no locks are held once it is returned
+  pub fn groups_snapshot(&self) -> Vec<StateGroup> {
+    self.groups.iter().map(|group| group.clone()).collect()
+  }
+
+  /// an owned snapshot of every client, no locks are held once it is returned
+  pub fn clients_snapshot(&self) -> Vec<Client> {
+    self.clients.iter().map(|client| client.clone()).collect()
+  }
+
+  /// what every group is playing, e.g. for a summary screen
+  ///
+  /// no update can be applied while the overview is assembled, so every group is joined with the stream it points to
+  /// at the same moment
+  ///
+  /// # returns
+  /// an owned [GroupOverview] for every group, sorted by group id
+  pub fn overview(&self) -> Vec<GroupOverview> {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+
+    let mut overview: Vec<GroupOverview> = self
+      .groups
+      .iter()
+      .map(|group| {
+        let stream = self.stream(&group.stream_id);
+        GroupOverview {
+          group: group.clone(),
+          now_playing: stream.as_ref().and_then(Stream::now_playing),
+          stream,
+        }
+      })
+      .collect();
+    overview.sort_by(|a, b| a.group.id.cmp(&b.group.id));
+
+    overview
+  }
+
+  /// rebuild the [Server] the snapserver would report from the tracked state
+  ///
+  /// group client ids are inflated back into full [Client]s, and streams whose details have not been loaded are
+  /// left out. groups, their clients, and streams are sorted by id, as the state does not keep the server's order.
+  ///
+  /// # returns
+  /// the [Server], or [None] if no server status has been applied yet
+  pub fn to_server(&self) -> Option<Server> {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+    let server = self.server.get()?.read().expect("rwlock poisoned").clone();
+
+    let mut groups: Vec<Group> = self.groups.iter().map(|group| group.to_group(&self.clients)).collect();
+    groups.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut streams = self.loaded_streams();
+    streams.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Some(Server {
+      server,
+      groups,
+      streams,
+    })
+  }
+
+  /// the progress of a stream's current track, extrapolated from its properties since they were received
+  ///
+  /// the progress only changes when new properties arrive, so it can be built again on every frame of a progress bar
+  ///
+  /// # args
+  /// `stream_id`: the id of the stream
+  ///
+  /// # returns
+  /// the [StreamProgress], or [None] if the stream or its properties have not been loaded
+  pub fn stream_progress(&self, stream_id: &str) -> Option<StreamProgress> {
+    let stream = self.streams.get(stream_id)?;
+    let properties = stream.as_ref()?.properties.as_ref()?;
+    let received_at = *self.properties_received.get(stream_id)?;
+
+    Some(StreamProgress::received_at(properties, received_at))
+  }
+
+  /// the streams whose details have been loaded
+  ///
+  /// # returns
+  /// an owned snapshot of every stream that is not [None], no locks are held once it is returned
+  pub fn loaded_streams(&self) -> Vec<Stream> {
+    self
+      .streams
+      .iter()
+      .filter_map(|stream| stream.value().clone())
+      .collect()
+  }
+
+  /// whether the details of a stream have been loaded, to tell a stream that is still loading from one that does not
+  /// exist
+  ///
+  /// # args
+  /// `id`: the id of the stream
+  ///
+  /// # returns
+  /// [None] if the stream is not in the state, `Some(false)` if it is a placeholder waiting for its details, see
+  /// [StateChange::StreamPending], or `Some(true)` if it is loaded
+  pub fn is_stream_loaded(&self, id: &str) -> Option<bool> {
+    self.streams.get(id).map(|stream| stream.is_some())
+  }
+
+  /// the ids of streams that were added but whose details have not been loaded yet
+  ///
+  /// # returns
+  /// an owned snapshot of the ids of every stream that is [None]
+  pub fn pending_stream_ids(&self) -> Vec<String> {
+    self
+      .streams
+      .iter()
+      .filter(|stream| stream.value().is_none())
+      .map(|stream| stream.key().clone())
+      .collect()
+  }
+
+  /// whether every group is muted, false if there are no groups
+  pub fn all_groups_muted(&self) -> bool {
+    !self.groups.is_empty() && self.groups.iter().all(|group| group.muted)
+  }
+
+  /// whether at least one group is muted
+  pub fn any_group_muted(&self) -> bool {
+    self.groups.iter().any(|group| group.muted)
+  }
+
+  /// the combined volume of the clients in a group, for showing a single group volume
+  ///
+  /// # args
+  /// `group_id`: the id of the group
+  ///
+  /// # returns
+  /// the [GroupVolumeSummary] of the clients in the group, or [None] if the group does not exist or none of its
+  /// clients are in the state
+  pub fn group_volume(&self, group_id: &str) -> Option<GroupVolumeSummary> {
+    let client_ids = self.groups.get(group_id)?.clients.clone();
+    let volumes: Vec<_> = client_ids
+      .iter()
+      .filter_map(|id| self.clients.get(id).map(|client| client.config.volume.clone()))
+      .collect();
+    if volumes.is_empty() {
+      return None;
+    }
+
+    let percents = volumes.iter().map(|volume| volume.percent.min(100) as u8);
+    let total: usize = percents.clone().map(usize::from).sum();
+
+    Some(GroupVolumeSummary {
+      average: ((total as f64) / (volumes.len() as f64)).round() as u8,
+      max: percents.clone().max().unwrap_or_default(),
+      min: percents.min().unwrap_or_default(),
+      all_muted: volumes.iter().all(|volume| volume.muted),
+    })
+  }
+
+  /// the volumes to give the clients of a group so their mean becomes `average`, see
+  /// [SnapcastConnection::group_set_volume](crate::SnapcastConnection::group_set_volume)
+  ///
+  /// every client keeps its share of the group volume, so the loudest client stays the loudest. the shares are
+  /// rounded so they add up to exactly `average` times the number of clients, without rounding clients further
+  /// apart, and the result only depends on the current volumes. repeated small adjustments therefore do not drift
+  /// the clients apart. volumes are clamped to 100, which lowers the mean when a client would exceed it.
+  ///
+  /// # args
+  /// `group_id`: the id of the group
+  /// `average`: the mean volume percent the group should have
+  ///
+  /// # returns
+  /// the ids of the clients and their new volume with the mute status unchanged, sorted by id, or [None] if the
+  /// group does not exist or none of its clients are in the state
+  pub fn group_volume_targets(&self, group_id: &str, average: u8) -> Option<Vec<(String, ClientVolume)>> {
+    let mut client_ids: Vec<_> = self.groups.get(group_id)?.clients.iter().cloned().collect();
+    client_ids.sort();
+    let clients: Vec<_> = client_ids
+      .into_iter()
+      .filter_map(|id| self.clients.get(&id).map(|client| (id, client.config.volume.clone())))
+      .collect();
+    if clients.is_empty() {
+      return None;
+    }
+
+    let current: Vec<_> = clients.iter().map(|(_, volume)| volume.percent).collect();
+    let targets = distribute_volume(&current, average);
+
+    Some(
+      clients
+        .into_iter()
+        .zip(targets)
+        .map(|((id, volume), percent)| {
+          (
+            id,
+            ClientVolume {
+              muted: volume.muted,
+              percent,
+            },
+          )
+        })
+        .collect(),
+    )
+  }
+
+  /// the ids of the clients in a group in a stable order, e.g. for listing them in a ui
+  ///
+  /// clients are sorted by their configured name, or their host name if none is configured, then by id. the order
+  /// only changes when a client is renamed or joins or leaves the group.
+  ///
+  /// # args
+  /// `group_id`: the id of the group
+  ///
+  /// # returns
+  /// the ordered client ids, with ids missing from [State::clients] last, or [None] if the group does not exist
+  pub fn group_client_ids_ordered(&self, group_id: &str) -> Option<Vec<String>> {
+    let client_ids = self.groups.get(group_id)?.clients.clone();
+    let mut named: Vec<(Option<String>, String)> = client_ids
+      .into_iter()
+      .map(|id| {
+        let name = self.clients.get(&id).map(|client| {
+          if client.config.name.is_empty() {
+            client.host.name.clone()
+          } else {
+            client.config.name.clone()
+          }
+        });
+        (name, id)
+      })
+      .collect();
+    named.sort_by(|(a_name, a_id), (b_name, b_id)| match (a_name, b_name) {
+      (Some(a), Some(b)) => a.cmp(b).then_with(|| a_id.cmp(b_id)),
+      (Some(_), None) => std::cmp::Ordering::Less,
+      (None, Some(_)) => std::cmp::Ordering::Greater,
+      (None, None) => a_id.cmp(b_id),
+    });
+
+    Some(named.into_iter().map(|(_, id)| id).collect())
+  }
+
+  /// clients that have not been seen for longer than `threshold`, even if the server lists them as connected
+  ///
+  /// # args
+  /// `threshold`: how long a client may go unseen before it is considered stale
+  ///
+  /// # returns
+  /// an owned snapshot of the stale clients
+  pub fn stale_clients(&self, threshold: Duration) -> Vec<Client> {
+    self
+      .clients
+      .iter()
+      .filter(|client| client.is_stale(threshold))
+      .map(|client| client.clone())
+      .collect()
+  }
+
+  /// the groups currently playing a stream, e.g. for pushing now-playing information to every room on a source
+  ///
+  /// # args
+  /// `stream_id`: the id of the stream
+  ///
+  /// # returns
+  /// an owned snapshot of the groups whose stream is `stream_id`, sorted by id
+  pub fn groups_using_stream(&self, stream_id: &str) -> Vec<StateGroup> {
+    let mut groups: Vec<StateGroup> = self
+      .groups
+      .iter()
+      .filter(|group| group.stream_id == stream_id)
+      .map(|group| group.clone())
+      .collect();
+    groups.sort_by(|a, b| a.id.cmp(&b.id));
+
+    groups
+  }
+
+  /// whether a full server status has been applied since the connection was last established
+  pub fn is_ready(&self) -> bool {
+    self.ready.load(Ordering::Acquire)
+  }
+
+  /// whether the group, client, or stream with id `id` was seeded from
+  /// [SnapcastConnectionBuilder::with_initial_state](crate::SnapcastConnectionBuilder::with_initial_state) and no
+  /// update from the server has touched it yet
+  ///
+  /// a full server status confirms every entry at once, entries it does not contain are removed
+  pub fn is_unconfirmed(&self, id: &str) -> bool {
+    self.unconfirmed.lock().expect("mutex poisoned").contains(id)
+  }
+
+  /// fill the state from a cached server status, marking every entry unconfirmed, without becoming ready
+  pub(crate) fn seed(&self, server: Server) {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+    self.apply_update(StateUpdate::Server(server));
+
+    let mut unconfirmed = self.unconfirmed.lock().expect("mutex poisoned");
+    unconfirmed.extend(self.groups.iter().map(|group| group.key().clone()));
+    unconfirmed.extend(self.clients.iter().map(|client| client.key().clone()));
+    unconfirmed.extend(self.streams.iter().map(|stream| stream.key().clone()));
+  }
+
+  /// the flag behind [State::is_ready], cleared by the transport when the connection is lost
+  pub(crate) fn ready_flag(&self) -> Arc<AtomicBool> {
+    self.ready.clone()
+  }
+
+  /// subscribe to every change made to the state from now on
+  ///
+  /// # returns
+  /// a [broadcast::Receiver] of [StateChange]s. a subscriber that falls too far behind receives a
+  /// [broadcast::error::RecvError::Lagged] and misses the oldest changes.
+  pub fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+    self.changes.subscribe()
+  }
+
+  /// subscribe to the state, starting with a snapshot of its current contents
+  ///
+  /// the stream first yields a [StateChange::ServerChanged] and a `*Added` event for every stream, client, and
+  /// group currently in the state, followed by [StateChange::Ready] if the state is ready, then every live change.
+  /// no update can be applied between the snapshot and the subscription, so the snapshot followed by the deltas
+  /// always reflects the state. \
+  /// changes missed because the subscriber lagged behind are logged and skipped.
+  ///
+  /// # returns
+  /// a stream of [StateChange]s that ends when the state is dropped
+  ///
+  /// # example
+  /// ```no_run
+  /// let mut changes = Box::pin(client.state.subscribe_with_snapshot());
+  /// while let Some(change) = changes.next().await {
+  ///   println!("{:?}", change);
+  /// }
+  /// ```
+  pub fn subscribe_with_snapshot(&self) -> impl futures::Stream<Item = StateChange> + Send + 'static {
+    use futures::StreamExt;
+
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+    let receiver = self.changes.subscribe();
+    let snapshot = self.snapshot();
+
+    futures::stream::iter(snapshot).chain(futures::stream::unfold(receiver, |mut receiver| async move {
+      loop {
+        match receiver.recv().await {
+          Ok(change) => return Some((change, receiver)),
+          Err(broadcast::error::RecvError::Lagged(skipped)) => {
+            tracing::warn!("state subscriber lagged behind, skipped {} changes", skipped)
+          }
+          Err(broadcast::error::RecvError::Closed) => return None,
+        }
       }
+    }))
+  }
+
+  fn snapshot(&self) -> Vec<StateChange> {
+    let server = self
+      .server
+      .get()
+      .map(|server| StateChange::ServerChanged(server.read().expect("rwlock poisoned").clone()));
+    let streams = self.streams.iter().flat_map(|stream| {
+      let id = stream.key().clone();
+      let pending = stream.is_none().then(|| StateChange::StreamPending { id: id.clone() });
+      [Some(StateChange::StreamAdded {
+        id,
+        stream: stream.value().clone(),
+      })]
+      .into_iter()
+      .chain([pending])
+      .flatten()
+    });
+    let clients = self
+      .clients
+      .iter()
+      .map(|client| StateChange::ClientAdded(client.clone()));
+    let groups = self.groups.iter().map(|group| StateChange::GroupAdded(group.clone()));
+
+    let ready = self.is_ready().then_some(StateChange::Ready);
+
+    server
+      .into_iter()
+      .chain(streams)
+      .chain(clients)
+      .chain(groups)
+      .chain(ready)
+      .collect()
+  }
+
+  fn emit(&self, change: impl FnOnce() -> StateChange) {
+    if self.changes.receiver_count() > 0 {
+      let _ = self.changes.send(change());
+    }
+  }
+
+  /// build a state from a full server status, without any connection
+  ///
+  /// # args
+  /// `server`: the [Server] as returned by `Server.GetStatus`
+  ///
+  /// # returns
+  /// a new [State] containing the server's groups, clients, and streams
+  pub fn from_status(server: &Server) -> Self {
+    let state = Self::default();
+    state.apply(StateUpdate::Server(server.clone()));
+
+    state
+  }
+
+  /// apply a message received from the server to the state
+  ///
+  /// this is what the connection does with every message it receives, exposed for use without an async runtime. \
+  /// errors are ignored as they carry no state.
+  ///
+  /// # args
+  /// `message`: the [Message] to apply, e.g. parsed with [str::parse]
+  ///
+  /// # example
+  /// ```no_run
+  /// let message: Message = line.parse().expect("could not parse message");
+  /// state.apply_message(&message);
+  /// ```
+  pub fn apply_message(&self, message: &Message) {
+    match message {
+      Message::Result { result, .. } => self.handle_result(result),
+      Message::Notification { method, .. } => self.handle_notification(method),
+      Message::Error { .. } => {}
+    }
+  }
+
+  /// apply an update to the state, notifying subscribers of any changes
+  ///
+  /// # args
+  /// `update`: the [StateUpdate] to apply
+  ///
+  /// # example
+  /// ```no_run
+  /// state.apply(StateUpdate::GroupMuted { id: "group_id".to_string(), muted: true });
+  /// ```
+  pub fn apply(&self, update: StateUpdate) {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+    self.apply_update(update);
+  }
+
+  pub(crate) fn handle_result(&self, data: &SnapcastResult) {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+
+    let update = match data {
+      // client
+      SnapcastResult::ClientGetStatus(result) => StateUpdate::Client(result.client.clone()),
+      SnapcastResult::ClientSetVolume(id, result) => StateUpdate::ClientVolume {
+        id: id.clone(),
+        volume: result.volume.clone(),
+      },
+      SnapcastResult::ClientSetLatency(id, result) => StateUpdate::ClientLatency {
+        id: id.clone(),
+        latency: result.latency,
+      },
+      SnapcastResult::ClientSetName(id, result) => StateUpdate::ClientName {
+        id: id.clone(),
+        name: result.name.clone(),
+      },
 
       // group
-      SnapcastResult::GroupGetStatus(result) => {
-        let clients = result.group.clients.iter().map(|c| c.id.clone()).collect();
-        self.group_upsert(result.group, clients);
-      }
-      SnapcastResult::GroupSetMute(id, result) => self.group_partial_update(id, GroupPartialUpdate::Muted(result.mute)),
-      SnapcastResult::GroupSetStream(id, result) => {
-        self.group_partial_update(id, GroupPartialUpdate::StreamId(result.stream_id))
-      }
-      SnapcastResult::GroupSetName(id, result) => self.group_partial_update(id, GroupPartialUpdate::Name(result.name)),
-      SnapcastResult::GroupSetClients(result) => self.full_server_upsert(result.server),
+      SnapcastResult::GroupGetStatus(result) => StateUpdate::Group(result.group.clone()),
+      SnapcastResult::GroupSetMute(id, result) => StateUpdate::GroupMuted {
+        id: id.clone(),
+        muted: result.mute,
+      },
+      SnapcastResult::GroupSetStream(id, result) => StateUpdate::GroupStream {
+        id: id.clone(),
+        stream_id: result.stream_id.clone(),
+      },
+      SnapcastResult::GroupSetName(id, result) => StateUpdate::GroupName {
+        id: id.clone(),
+        name: result.name.clone(),
+      },
+      SnapcastResult::GroupSetClients(result) => return self.server_upsert(&result.server),
 
       // server
-      SnapcastResult::ServerGetRPCVersion(_) => {}
-      SnapcastResult::ServerGetStatus(result) => self.full_server_upsert(result.server),
-      SnapcastResult::ServerDeleteClient(result) => self.full_server_upsert(result.server),
+      SnapcastResult::ServerGetRPCVersion(result) => StateUpdate::RpcVersion(result.clone()),
+      SnapcastResult::ServerGetStatus(result) => {
+        self.server_upsert(&result.server);
+        if !self.ready.swap(true, Ordering::AcqRel) {
+          self.emit(|| StateChange::Ready);
+        }
+        return;
+      }
+      SnapcastResult::ServerDeleteClient(result) => return self.server_upsert(&result.server),
 
       // stream
-      SnapcastResult::StreamAddStream(result) => self.stream_upsert(result.id, None),
-      SnapcastResult::StreamRemoveStream(result) => {
-        self.streams.remove(&result.id);
-      }
-      SnapcastResult::StreamControl(_) => {}
-      SnapcastResult::StreamSetProperty(_) => {}
+      SnapcastResult::StreamAddStream(result) => StateUpdate::Stream {
+        id: result.id.clone(),
+        stream: None,
+      },
+      SnapcastResult::StreamRemoveStream(result) => StateUpdate::StreamRemoved(result.id.clone()),
+      SnapcastResult::StreamControl(_) => return,
+      SnapcastResult::StreamSetProperty(_) => return,
     };
+
+    self.apply_update(update);
   }
 
-  pub(crate) fn handle_notification(&self, data: Notification) {
-    match data {
+  pub(crate) fn handle_notification(&self, data: &Notification) {
+    let _guard = self.update_lock.lock().expect("mutex poisoned");
+
+    let update = match data {
       // client
-      Notification::ClientOnConnect { params } => self.client_upsert(params.client),
-      Notification::ClientOnDisconnect { params } => self.client_remove(params.id),
-      Notification::ClientOnVolumeChanged { params } => {
-        self.client_partial_update(params.id, ClientPartialUpdate::Volume(params.volume))
-      }
-      Notification::ClientOnLatencyChanged { params } => {
-        self.client_partial_update(params.id, ClientPartialUpdate::Latency(params.latency))
-      }
-      Notification::ClientOnNameChanged { params } => {
-        self.client_partial_update(params.id, ClientPartialUpdate::Name(params.name))
-      }
+      Notification::ClientOnConnect { params } => StateUpdate::Client(params.client.clone()),
+      Notification::ClientOnDisconnect { params } => StateUpdate::ClientRemoved(params.id.clone()),
+      Notification::ClientOnVolumeChanged { params } => StateUpdate::ClientVolume {
+        id: params.id.clone(),
+        volume: params.volume.clone(),
+      },
+      Notification::ClientOnLatencyChanged { params } => StateUpdate::ClientLatency {
+        id: params.id.clone(),
+        latency: params.latency,
+      },
+      Notification::ClientOnNameChanged { params } => StateUpdate::ClientName {
+        id: params.id.clone(),
+        name: params.name.clone(),
+      },
 
       // group
-      Notification::GroupOnMute { params } => {
-        self.group_partial_update(params.id, GroupPartialUpdate::Muted(params.mute))
-      }
-      Notification::GroupOnStreamChanged { params } => {
-        self.group_partial_update(params.id, GroupPartialUpdate::StreamId(params.stream_id))
-      }
-      Notification::GroupOnNameChanged { params } => {
-        self.group_partial_update(params.id, GroupPartialUpdate::Name(params.name))
-      }
+      Notification::GroupOnMute { params } => StateUpdate::GroupMuted {
+        id: params.id.clone(),
+        muted: params.mute,
+      },
+      Notification::GroupOnStreamChanged { params } => StateUpdate::GroupStream {
+        id: params.id.clone(),
+        stream_id: params.stream_id.clone(),
+      },
+      Notification::GroupOnNameChanged { params } => StateUpdate::GroupName {
+        id: params.id.clone(),
+        name: params.name.clone(),
+      },
 
       // server
-      Notification::ServerOnUpdate { params } => self.full_server_upsert(params.server),
+      Notification::ServerOnUpdate { params } => return self.server_upsert(&params.server),
 
       // stream
-      Notification::StreamOnUpdate { params } => self.stream_upsert(params.stream.id.clone(), Some(params.stream)),
-      Notification::StreamOnProperties { params } => {
-        self.stream_partial_update(params.id, StreamPartialUpdate::Properties(params.properties))
-      }
+      Notification::StreamOnUpdate { params } => StateUpdate::Stream {
+        id: params.stream.id.clone(),
+        stream: Some(Box::new(params.stream.clone())),
+      },
+      Notification::StreamOnProperties { params } => StateUpdate::StreamProperties {
+        id: params.id.clone(),
+        properties: Box::new(params.properties.clone()),
+      },
+
+      Notification::Unknown { .. } => return,
     };
+
+    self.apply_update(update);
   }
 
-  fn full_server_upsert(&self, data: Server) {
-    self.server_details_upsert(data.server);
+  /// the single code path for every update but full server statuses, callers must hold the update lock
+  fn apply_update(&self, update: StateUpdate) {
+    self.confirm(&update);
 
-    let group_keys: HashSet<&str> = data.groups.iter().map(|g| &*g.id).collect();
-    self.groups.retain(|k, _| group_keys.contains(k.as_str()));
+    match update {
+      StateUpdate::Server(server) => self.full_server_upsert(&server),
+      StateUpdate::RpcVersion(version) => *self.rpc_version.write().expect("rwlock poisoned") = Some(version),
 
-    let client_keys: HashSet<&str> = data
-      .groups
-      .iter()
-      .flat_map(|g| g.clients.iter().map(|c| &*c.id))
-      .collect();
-    self.clients.retain(|k, _| client_keys.contains(k.as_str()));
+      // client
+      StateUpdate::Client(client) => self.client_upsert(&client),
+      StateUpdate::ClientRemoved(id) => self.client_remove(&id),
+      StateUpdate::ClientVolume { id, volume } => self.client_modify(&id, |client| {
+        let old = std::mem::replace(&mut client.config.volume, volume.clone());
+        (old != volume).then(|| StateChange::ClientVolumeChanged {
+          id: id.clone(),
+          old,
+          new: volume,
+        })
+      }),
+      StateUpdate::ClientLatency { id, latency } => self.client_modify(&id, |client| {
+        let old = std::mem::replace(&mut client.config.latency, latency);
+        (old != latency).then(|| StateChange::ClientLatencyChanged {
+          id: id.clone(),
+          old,
+          new: latency,
+        })
+      }),
+      StateUpdate::ClientName { id, name } => self.client_modify(&id, |client| {
+        let old = std::mem::replace(&mut client.config.name, name.clone());
+        (old != name).then(|| StateChange::ClientNameChanged {
+          id: id.clone(),
+          old,
+          new: name,
+        })
+      }),
+
+      // group
+      StateUpdate::Group(group) => self.group_upsert(&group),
+      StateUpdate::GroupMuted { id, muted } => self.group_modify(&id, |group| {
+        let old = std::mem::replace(&mut group.muted, muted);
+        (old != muted).then(|| StateChange::GroupMuteChanged {
+          id: id.clone(),
+          old,
+          new: muted,
+        })
+      }),
+      StateUpdate::GroupStream { id, stream_id } => self.group_modify(&id, |group| {
+        let old = std::mem::replace(&mut group.stream_id, stream_id.clone());
+        (old != stream_id).then(|| StateChange::GroupStreamChanged {
+          id: id.clone(),
+          old,
+          new: stream_id,
+        })
+      }),
+      StateUpdate::GroupName { id, name } => self.group_modify(&id, |group| {
+        let old = std::mem::replace(&mut group.name, name.clone());
+        (old != name).then(|| StateChange::GroupNameChanged {
+          id: id.clone(),
+          old,
+          new: name,
+        })
+      }),
+
+      // stream
+      StateUpdate::Stream { id, stream } => self.stream_upsert(&id, stream.as_deref()),
+      StateUpdate::StreamRemoved(id) => self.stream_remove(&id),
+      StateUpdate::StreamProperties { id, properties } => self.stream_modify(&id, |stream| match stream {
+        Some(stream) if stream.properties.as_ref() != Some(&properties) => {
+          stream.properties = Some(*properties);
+          true
+        }
+        _ => false,
+      }),
+    }
+  }
 
-    for mut group in data.groups {
-      let clients: HashSet<String> = group.clients.iter().map(|c| c.id.clone()).collect();
+  /// clear the unconfirmed mark of every entry `update` touches, see [State::is_unconfirmed]
+  fn confirm(&self, update: &StateUpdate) {
+    let mut unconfirmed = self.unconfirmed.lock().expect("mutex poisoned");
+    if unconfirmed.is_empty() {
+      return;
+    }
 
-      for client in group.clients.drain(..) {
+    match update {
+      StateUpdate::Server(_) => unconfirmed.clear(),
+      StateUpdate::RpcVersion(_) => {}
+      StateUpdate::Client(client) => {
+        unconfirmed.remove(&client.id);
+      }
+      StateUpdate::Group(group) => {
+        unconfirmed.remove(&group.id);
+        for client in &group.clients {
+          unconfirmed.remove(&client.id);
+        }
+      }
+      StateUpdate::ClientRemoved(id)
+      | StateUpdate::ClientVolume { id, .. }
+      | StateUpdate::ClientLatency { id, .. }
+      | StateUpdate::ClientName { id, .. }
+      | StateUpdate::GroupMuted { id, .. }
+      | StateUpdate::GroupStream { id, .. }
+      | StateUpdate::GroupName { id, .. }
+      | StateUpdate::Stream { id, .. }
+      | StateUpdate::StreamRemoved(id)
+      | StateUpdate::StreamProperties { id, .. } => {
+        unconfirmed.remove(id);
+      }
+    }
+  }
+
+  /// apply a full server status without cloning it, the path of [StateUpdate::Server] for received messages.
+  /// callers must hold the update lock
+  fn server_upsert(&self, server: &Server) {
+    self.unconfirmed.lock().expect("mutex poisoned").clear();
+    self.full_server_upsert(server);
+  }
+
+  /// reconcile the state with a full server status, only entries that differ are cloned into the state and emit
+  /// changes. the maps are locked one at a time, except that `properties_received` is locked while a `streams` entry
+  /// is held: by the `streams.retain` below, [State::stamp_properties], and [State::stream_modify].
+  /// [State::stream_progress] locks them in the same order, and `properties_received` is never held while locking
+  /// `streams`, so updates cannot deadlock
+  fn full_server_upsert(&self, data: &Server) {
+    let Server {
+      server,
+      groups,
+      streams,
+    } = data;
+    self.server_details_upsert(server);
+
+    let group_keys: HashSet<&str> = groups.iter().map(|g| &*g.id).collect();
+    self.groups.retain(|k, v| {
+      let keep = group_keys.contains(k.as_str());
+      if !keep {
+        self.emit(|| StateChange::GroupRemoved(k.clone()));
+        self.emit_membership(k, &v.clients, &HashSet::new());
+      }
+      keep
+    });
+
+    let client_keys: HashSet<&str> = groups.iter().flat_map(|g| g.clients.iter().map(|c| &*c.id)).collect();
+    self.clients.retain(|k, _| {
+      let keep = client_keys.contains(k.as_str());
+      if !keep {
+        self.emit(|| StateChange::ClientRemoved(k.clone()));
+      }
+      keep
+    });
+
+    for group in groups {
+      for client in &group.clients {
         self.client_upsert(client);
       }
 
-      self.group_upsert(group, clients);
+      self.group_upsert(group);
     }
 
-    let stream_keys: HashSet<&str> = data.streams.iter().map(|s| &*s.id).collect();
-    self.streams.retain(|k, _| stream_keys.contains(k.as_str()));
+    let stream_keys: HashSet<&str> = streams.iter().map(|s| &*s.id).collect();
+    self.streams.retain(|k, _| {
+      let keep = stream_keys.contains(k.as_str());
+      if !keep {
+        self.properties_received.remove(k);
+        self.emit(|| StateChange::StreamRemoved(k.clone()));
+      }
+      keep
+    });
 
-    for stream in data.streams {
-      self.stream_upsert(stream.id.clone(), Some(stream));
+    for stream in streams {
+      self.stream_upsert(&stream.id, Some(stream));
     }
   }
 
   // client
-  fn client_upsert(&self, client: Client) {
+  fn client_upsert(&self, client: &Client) {
+    // compare under a read lock first, most clients are unchanged by a full server update
+    if self.clients.get(&client.id).is_some_and(|entry| *entry == *client) {
+      return;
+    }
+
     let entry = self.clients.entry(client.id.clone());
+    #[cfg(test)]
+    self.client_writes.fetch_add(1, Ordering::Relaxed);
     if let Entry::Occupied(mut entry) = entry {
       let entry = entry.get_mut();
-      *entry = client;
+      if !entry.differs_ignoring_last_seen(client) {
+        // the server refreshes the last seen time constantly, keep it without emitting a change
+        entry.last_seen = client.last_seen.clone();
+      } else {
+        let old = std::mem::replace(entry, client.clone());
+        self.emit(|| StateChange::ClientChanged(entry.clone()));
+        self.emit_client_fields(&old, entry);
+      }
     } else {
-      entry.insert(client);
+      let entry = entry.insert(client.clone());
+      self.emit(|| StateChange::ClientAdded(entry.clone()));
     }
   }
 
-  fn client_remove(&self, id: String) {
-    self.clients.remove(&id);
+  /// emit the [StateChange::ClientVolumeChanged], [StateChange::ClientLatencyChanged], and
+  /// [StateChange::ClientNameChanged] for the fields that differ between `old` and `new`
+  fn emit_client_fields(&self, old: &Client, new: &Client) {
+    if old.config.volume != new.config.volume {
+      self.emit(|| StateChange::ClientVolumeChanged {
+        id: new.id.clone(),
+        old: old.config.volume.clone(),
+        new: new.config.volume.clone(),
+      });
+    }
+    if old.config.latency != new.config.latency {
+      self.emit(|| StateChange::ClientLatencyChanged {
+        id: new.id.clone(),
+        old: old.config.latency,
+        new: new.config.latency,
+      });
+    }
+    if old.config.name != new.config.name {
+      self.emit(|| StateChange::ClientNameChanged {
+        id: new.id.clone(),
+        old: old.config.name.clone(),
+        new: new.config.name.clone(),
+      });
+    }
   }
 
-  fn client_partial_update(&self, id: String, update: ClientPartialUpdate) {
-    let entry = self.clients.entry(id);
-    if let Entry::Occupied(mut entry) = entry {
-      let entry = entry.get_mut();
+  fn client_remove(&self, id: &str) {
+    if self.clients.remove(id).is_some() {
+      self.emit(|| StateChange::ClientRemoved(id.to_string()));
+    }
+  }
 
-      match update {
-        ClientPartialUpdate::Volume(volume) => entry.config.volume = volume,
-        ClientPartialUpdate::Latency(latency) => entry.config.latency = latency,
-        ClientPartialUpdate::Name(name) => entry.config.name = name,
+  /// apply `modify` to a client and, if it returns a change, emit [StateChange::ClientChanged] followed by that change
+  fn client_modify(&self, id: &str, modify: impl FnOnce(&mut Client) -> Option<StateChange>) {
+    if let Some(mut entry) = self.clients.get_mut(id) {
+      let entry = entry.value_mut();
+      if let Some(change) = modify(entry) {
+        self.emit(|| StateChange::ClientChanged(entry.clone()));
+        self.emit(|| change);
       }
+    } else {
+      self.ignore_stale("client", id);
     }
   }
 
   // group
-  fn group_upsert(&self, group: Group, clients: HashSet<String>) {
+  fn group_upsert(&self, group: &Group) {
+    let unchanged = |entry: &StateGroup| {
+      entry.name == group.name
+        && entry.stream_id == group.stream_id
+        && entry.muted == group.muted
+        && entry.clients.len() == group.clients.len()
+        && group.clients.iter().all(|client| entry.clients.contains(&client.id))
+    };
+    // compare under a read lock first, most groups are unchanged by a full server update
+    if self.groups.get(&group.id).is_some_and(|entry| unchanged(&entry)) {
+      return;
+    }
+
+    let clients = group.clients.iter().map(|c| c.id.clone()).collect();
     let entry = self.groups.entry(group.id.clone());
     if let Entry::Occupied(mut entry) = entry {
       let entry = entry.get_mut();
+      if unchanged(entry) {
+        return;
+      }
 
-      entry.name = group.name;
-      entry.stream_id = group.stream_id;
-      entry.muted = group.muted;
-      entry.clients = clients;
+      let name = std::mem::replace(&mut entry.name, group.name.clone());
+      let stream_id = std::mem::replace(&mut entry.stream_id, group.stream_id.clone());
+      let muted = std::mem::replace(&mut entry.muted, group.muted);
+      let previous = std::mem::replace(&mut entry.clients, clients);
+      self.emit(|| StateChange::GroupChanged(entry.clone()));
+      if name != entry.name {
+        self.emit(|| StateChange::GroupNameChanged {
+          id: entry.id.clone(),
+          old: name,
+          new: entry.name.clone(),
+        });
+      }
+      if muted != entry.muted {
+        self.emit(|| StateChange::GroupMuteChanged {
+          id: entry.id.clone(),
+          old: muted,
+          new: entry.muted,
+        });
+      }
+      if stream_id != entry.stream_id {
+        self.emit(|| StateChange::GroupStreamChanged {
+          id: entry.id.clone(),
+          old: stream_id,
+          new: entry.stream_id.clone(),
+        });
+      }
+      self.emit_membership(&entry.id, &previous, &entry.clients);
     } else {
-      entry.insert(StateGroup {
+      let entry = entry.insert(StateGroup {
         id: group.id.clone(),
-        name: group.name,
+        name: group.name.clone(),
         stream_id: group.stream_id.clone(),
         muted: group.muted,
         clients,
       });
+      self.emit(|| StateChange::GroupAdded(entry.clone()));
+      self.emit_membership(&entry.id, &HashSet::new(), &entry.clients);
     }
   }
 
-  fn group_partial_update(&self, id: String, update: GroupPartialUpdate) {
-    let entry = self.groups.entry(id.clone());
-    if let Entry::Occupied(mut entry) = entry {
-      let entry = entry.get_mut();
+  /// emit a [StateChange::ClientLeftGroup] or [StateChange::ClientJoinedGroup] for every client that is only in
+  /// `previous` or `current`, ordered by client id
+  fn emit_membership(&self, group: &str, previous: &HashSet<String>, current: &HashSet<String>) {
+    let mut left: Vec<&String> = previous.difference(current).collect();
+    left.sort();
+    for client in left {
+      self.emit(|| StateChange::ClientLeftGroup {
+        client: client.clone(),
+        group: group.to_string(),
+      });
+    }
 
-      match update {
-        GroupPartialUpdate::Name(name) => entry.name = name,
-        GroupPartialUpdate::Muted(muted) => entry.muted = muted,
-        GroupPartialUpdate::StreamId(stream_id) => {
-          entry.stream_id = stream_id;
-        }
+    let mut joined: Vec<&String> = current.difference(previous).collect();
+    joined.sort();
+    for client in joined {
+      self.emit(|| StateChange::ClientJoinedGroup {
+        client: client.clone(),
+        group: group.to_string(),
+      });
+    }
+  }
+
+  /// apply `modify` to a group and, if it returns a change, emit [StateChange::GroupChanged] followed by that change
+  fn group_modify(&self, id: &str, modify: impl FnOnce(&mut StateGroup) -> Option<StateChange>) {
+    if let Some(mut entry) = self.groups.get_mut(id) {
+      let entry = entry.value_mut();
+      if let Some(change) = modify(entry) {
+        self.emit(|| StateChange::GroupChanged(entry.clone()));
+        self.emit(|| change);
       }
+    } else {
+      self.ignore_stale("group", id);
     }
   }
 
   // server
-  fn server_details_upsert(&self, server: ServerDetails) {
+  fn server_details_upsert(&self, server: &ServerDetails) {
     if self.server.get().is_none() {
-      self.server.set(RwLock::new(server)).expect("this should never fail");
+      self.emit(|| StateChange::ServerChanged(server.clone()));
+      self
+        .server
+        .set(RwLock::new(server.clone()))
+        .expect("this should never fail");
     } else {
       let mut entry = self.server.get().unwrap().write().expect("rwlock poisoned");
-      *entry = server;
+      if *entry != *server {
+        *entry = server.clone();
+        self.emit(|| StateChange::ServerChanged(entry.clone()));
+      }
     }
   }
 
   // stream
-  fn stream_upsert(&self, id: String, stream: Option<Stream>) {
-    let entry = self.streams.entry(id);
+  fn stream_upsert(&self, id: &str, stream: Option<&Stream>) {
+    // compare under a read lock first, most streams are unchanged by a full server update
+    if self.streams.get(id).is_some_and(|entry| entry.as_ref() == stream) {
+      return;
+    }
+
+    let pending = stream.is_none();
+    let entry = self.streams.entry(id.to_string());
     if let Entry::Occupied(mut entry) = entry {
-      let entry = entry.get_mut();
-      *entry = stream;
+      if entry.get().as_ref() != stream {
+        let previous = entry.insert(stream.cloned());
+        self.stamp_properties(entry.key(), &previous, entry.get());
+        self.emit(|| StateChange::StreamChanged {
+          id: entry.key().clone(),
+          stream: entry.get().clone(),
+        });
+      } else {
+        return;
+      }
     } else {
-      entry.insert(stream);
+      let entry = entry.insert(stream.cloned());
+      self.stamp_properties(entry.key(), &None, entry.value());
+      self.emit(|| StateChange::StreamAdded {
+        id: entry.key().clone(),
+        stream: entry.value().clone(),
+      });
+    }
+
+    if pending {
+      self.emit(|| StateChange::StreamPending { id: id.to_string() });
     }
   }
 
-  fn stream_partial_update(&self, id: String, update: StreamPartialUpdate) {
-    let entry = self.streams.entry(id);
-    if let Entry::Occupied(mut entry) = entry {
-      let entry = entry.get_mut();
+  /// remember when the properties of a stream were received if they changed, see [State::stream_progress]
+  fn stamp_properties(&self, id: &str, previous: &Option<Stream>, current: &Option<Stream>) {
+    fn properties(stream: &Option<Stream>) -> Option<&StreamProperties> {
+      stream.as_ref()?.properties.as_ref()
+    }
+
+    match properties(current) {
+      Some(current) if properties(previous) != Some(current) => {
+        self.properties_received.insert(id.to_string(), self.clock.now());
+      }
+      Some(_) => {}
+      None => {
+        self.properties_received.remove(id);
+      }
+    }
+  }
+
+  fn stream_remove(&self, id: &str) {
+    self.properties_received.remove(id);
+    if self.streams.remove(id).is_some() {
+      self.emit(|| StateChange::StreamRemoved(id.to_string()));
+    }
+  }
+
+  /// apply `modify` to a stream and emit [StateChange::StreamChanged] if it returns that the stream changed
+  fn stream_modify(&self, id: &str, modify: impl FnOnce(&mut Option<Stream>) -> bool) {
+    if let Some(mut entry) = self.streams.get_mut(id) {
+      let entry = entry.value_mut();
+      if modify(entry) {
+        self.properties_received.insert(id.to_string(), self.clock.now());
+        self.emit(|| StateChange::StreamChanged {
+          id: id.to_string(),
+          stream: entry.clone(),
+        });
+      }
+    } else {
+      self.ignore_stale("stream", id);
+    }
+  }
+
+  fn ignore_stale(&self, kind: &str, id: &str) {
+    tracing::warn!("ignoring update for unknown {} {}", kind, id);
+    self.emit(|| StateChange::StaleUpdateIgnored { id: id.to_string() });
+  }
+}
+
+fn is_playing(stream: &Option<Stream>) -> bool {
+  stream
+    .as_ref()
+    .is_some_and(|stream| stream.status == StreamStatus::Playing)
+}
+
+/// split `average` times the number of clients between them in proportion to their `current` volume, see
+/// [State::group_volume_targets]
+///
+/// a proportional share is the quietest client's share plus the scaled distance of the client to it. the distances
+/// are rounded down and the rest is split evenly, with leftover percent going to the quietest clients first, so
+/// rounding never pushes clients further apart than the scaling itself. integer arithmetic keeps the rounding
+/// deterministic. clients that are all silent get `average` each.
+fn distribute_volume(current: &[usize], average: u8) -> Vec<usize> {
+  let current: Vec<_> = current.iter().map(|percent| (*percent).min(100)).collect();
+  let average = usize::from(average.min(100));
+  let sum: usize = current.iter().sum();
+  if sum == 0 {
+    return vec![average; current.len()];
+  }
+
+  let total = average * current.len();
+  let quietest = current.iter().copied().min().unwrap_or_default();
+  let distances: Vec<_> = current
+    .iter()
+    .map(|percent| (percent - quietest) * total / sum)
+    .collect();
+
+  let rest = total - distances.iter().sum::<usize>();
+  let base = rest / current.len();
+  let mut order: Vec<_> = (0..current.len()).collect();
+  order.sort_by_key(|index| (distances[*index], *index));
+
+  let mut targets: Vec<_> = distances.iter().map(|distance| base + distance).collect();
+  for index in order.into_iter().take(rest % current.len()) {
+    targets[index] += 1;
+  }
+
+  targets.into_iter().map(|percent| percent.min(100)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SERVER_STATUS: &str = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","result":{"server":{"groups":[{"clients":[{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":false,"name":"","stream_id":"stream 1"}],"server":{"host":{"arch":"x86_64","ip":"","mac":"","name":"T400","os":"Linux Mint 17.3 Rosa"},"snapserver":{"controlProtocolVersion":1,"name":"Snapserver","protocolVersion":1,"version":"0.10.0"}},"streams":[{"id":"stream 1","status":"idle","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{"name":"stream 1"},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}]}}}"#;
+
+  #[test]
+  fn apply_parsed_messages() {
+    let state = State::default();
+
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    state.apply_message(
+      &r#"{"jsonrpc":"2.0","method":"Group.OnNameChanged","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","name":"Kitchen"}}"#
+        .parse()
+        .unwrap(),
+    );
+
+    assert!(state.clients.contains_key("00:21:6a:7d:74:fc"));
+    assert!(state.streams.contains_key("stream 1"));
+    assert_eq!(
+      state.groups.get("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap().name,
+      "Kitchen"
+    );
+  }
+
+  #[test]
+  fn state_from_status() {
+    let json: serde_json::Value = serde_json::from_str(SERVER_STATUS).unwrap();
+    let server: Server = serde_json::from_value(json["result"]["server"].clone()).unwrap();
+    let state = State::from_status(&server);
+
+    assert_eq!(state.server_protocol_version(), Some((1, 1)));
+    assert_eq!(state.groups.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn subscribe_with_snapshot_then_changes() {
+    use futures::StreamExt;
+
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut changes = Box::pin(state.subscribe_with_snapshot());
+    state.apply_message(
+      &r#"{"jsonrpc":"2.0","method":"Client.OnDisconnect","params":{"id":"00:21:6a:7d:74:fc","client":{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":false,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}}}"#
+        .parse()
+        .unwrap(),
+    );
+
+    assert!(matches!(changes.next().await, Some(StateChange::ServerChanged(_))));
+    assert!(matches!(changes.next().await, Some(StateChange::StreamAdded { id, .. }) if id == "stream 1"));
+    assert!(matches!(changes.next().await, Some(StateChange::ClientAdded(client)) if client.id == "00:21:6a:7d:74:fc"));
+    assert!(
+      matches!(changes.next().await, Some(StateChange::GroupAdded(group)) if group.id == "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1")
+    );
+    assert_eq!(changes.next().await, Some(StateChange::Ready));
+    assert_eq!(
+      changes.next().await,
+      Some(StateChange::ClientRemoved("00:21:6a:7d:74:fc".to_string()))
+    );
+  }
+
+  #[test]
+  fn ready_once_per_connection() {
+    let json: serde_json::Value = serde_json::from_str(SERVER_STATUS).unwrap();
+    let result = SnapcastResult::ServerGetStatus(serde_json::from_value(json["result"].clone()).unwrap());
+
+    let state = State::default();
+    let mut changes = state.subscribe();
+    assert!(!state.is_ready());
+
+    state.handle_result(&result);
+    state.handle_result(&result);
+    assert!(state.is_ready());
+
+    state.ready_flag().store(false, Ordering::Release);
+    state.handle_result(&result);
+
+    let ready = std::iter::from_fn(|| changes.try_recv().ok())
+      .filter(|change| *change == StateChange::Ready)
+      .count();
+    assert_eq!(ready, 2);
+  }
+
+  #[test]
+  fn count_state() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    assert!(!state.any_playing());
+
+    state.apply_message(
+      &r#"{"jsonrpc":"2.0","method":"Stream.OnUpdate","params":{"id":"stream 2","stream":{"id":"stream 2","status":"playing","uri":{"fragment":"","host":"","path":"/tmp/snapfifo2","query":{"name":"stream 2"},"raw":"pipe:///tmp/snapfifo2?name=stream 2","scheme":"pipe"}}}}"#
+        .parse()
+        .unwrap(),
+    );
+
+    assert!(state.any_playing());
+    assert_eq!(
+      state.counts(),
+      StateCounts {
+        groups: 1,
+        clients: 1,
+        connected_clients: 1,
+        streams: 2,
+        playing_streams: 1,
+      }
+    );
+  }
+
+  #[test]
+  fn loaded_and_pending_streams() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    state.handle_result(&SnapcastResult::StreamAddStream(crate::stream::AddStreamResult {
+      id: "stream 2".to_string(),
+    }));
+
+    let loaded = state.loaded_streams();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[0].id, "stream 1");
+    assert_eq!(state.pending_stream_ids(), vec!["stream 2".to_string()]);
+  }
+
+  #[test]
+  fn can_control_uses_capabilities() {
+    let state = State::default();
+    assert_eq!(state.can_control("stream 1", &ControlCommand::Play), None);
+
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    state.handle_result(&SnapcastResult::StreamAddStream(crate::stream::AddStreamResult {
+      id: "stream 2".to_string(),
+    }));
+    assert_eq!(state.can_control("stream 2", &ControlCommand::Play), None);
+
+    state.apply_message(&r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"stream 1","properties":{"canControl":true,"canGoNext":true,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":false}}}"#.parse().unwrap());
+    assert_eq!(state.can_control("stream 1", &ControlCommand::Next), Some(true));
+    assert_eq!(state.can_control("stream 1", &ControlCommand::Previous), Some(false));
+    assert_eq!(state.can_control("stream 1", &ControlCommand::PlayPause), Some(true));
+    assert_eq!(
+      state.can_control("stream 1", &ControlCommand::Seek { offset: 5.0 }),
+      Some(false)
+    );
+  }
+
+  #[test]
+  fn stream_placeholders_are_observable() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+
+    state.handle_result(&SnapcastResult::StreamAddStream(crate::stream::AddStreamResult {
+      id: "stream 2".to_string(),
+    }));
+    assert_eq!(
+      changes.try_recv().unwrap(),
+      StateChange::StreamAdded {
+        id: "stream 2".to_string(),
+        stream: None,
+      }
+    );
+    assert_eq!(
+      changes.try_recv().unwrap(),
+      StateChange::StreamPending {
+        id: "stream 2".to_string()
+      }
+    );
+
+    // adding the same placeholder again changes nothing
+    state.handle_result(&SnapcastResult::StreamAddStream(crate::stream::AddStreamResult {
+      id: "stream 2".to_string(),
+    }));
+    assert!(changes.try_recv().is_err());
 
-      match update {
-        StreamPartialUpdate::Properties(properties) => {
-          if let Some(entry) = entry {
-            entry.properties = Some(properties);
+    assert_eq!(state.is_stream_loaded("stream 1"), Some(true));
+    assert_eq!(state.is_stream_loaded("stream 2"), Some(false));
+    assert_eq!(state.is_stream_loaded("stream 3"), None);
+  }
+
+  #[test]
+  fn concurrent_reads_and_writes() {
+    let state = State::default();
+    let status: Message = SERVER_STATUS.parse().unwrap();
+    let changes: Vec<Message> = [
+      r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#,
+      r#"{"jsonrpc":"2.0","method":"Client.OnLatencyChanged","params":{"id":"00:21:6a:7d:74:fc","latency":10}}"#,
+      r#"{"jsonrpc":"2.0","method":"Stream.OnProperties","params":{"id":"stream 1","properties":{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false}}}"#,
+    ]
+    .iter()
+    .map(|message| message.parse().unwrap())
+    .collect();
+
+    std::thread::scope(|scope| {
+      for _ in 0..2 {
+        scope.spawn(|| {
+          for _ in 0..500 {
+            state.apply_message(&status);
+            changes.iter().for_each(|change| state.apply_message(change));
           }
-        }
+        });
+      }
+
+      for _ in 0..4 {
+        scope.spawn(|| {
+          for _ in 0..500 {
+            for group in state.groups_snapshot() {
+              group.clients.iter().for_each(|id| {
+                state.client(id);
+              });
+              state.stream(&group.stream_id);
+            }
+            state.counts();
+            state.loaded_streams();
+          }
+        });
       }
+    });
+
+    assert_eq!(state.counts().groups, 1);
+    assert_eq!(state.client("00:21:6a:7d:74:fc").unwrap().config.latency, 10);
+  }
+
+  #[test]
+  fn group_mute_aggregates() {
+    let state = State::default();
+    assert!(!state.all_groups_muted());
+    assert!(!state.any_group_muted());
+
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    assert!(!state.any_group_muted());
+
+    state.apply_message(
+      &r#"{"jsonrpc":"2.0","method":"Group.OnMute","params":{"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","mute":true}}"#
+        .parse()
+        .unwrap(),
+    );
+    assert!(state.all_groups_muted());
+    assert!(state.any_group_muted());
+  }
+
+  #[test]
+  fn group_volume_summary() {
+    let state = State::default();
+    assert_eq!(state.group_volume("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1"), None);
+
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut client = state.client("00:21:6a:7d:74:fc").unwrap();
+    client.id = "second".to_string();
+    client.config.volume = ClientVolume {
+      muted: true,
+      percent: 25,
+    };
+    state.clients.insert(client.id.clone(), client);
+    state
+      .groups
+      .get_mut("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1")
+      .unwrap()
+      .clients
+      .insert("second".to_string());
+
+    assert_eq!(
+      state.group_volume("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1"),
+      Some(GroupVolumeSummary {
+        average: 63,
+        max: 100,
+        min: 25,
+        all_muted: false,
+      })
+    );
+
+    let targets = state
+      .group_volume_targets("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1", 40)
+      .unwrap();
+    assert_eq!(
+      targets,
+      vec![
+        ("00:21:6a:7d:74:fc".to_string(), ClientVolume::new(64, false)),
+        ("second".to_string(), ClientVolume::new(16, true)),
+      ]
+    );
+  }
+
+  #[test]
+  fn distribute_volume_does_not_drift() {
+    assert_eq!(distribute_volume(&[0, 0], 30), vec![30, 30]);
+    assert_eq!(distribute_volume(&[50, 100], 90), vec![60, 100]);
+    assert_eq!(distribute_volume(&[10, 20, 30], 30), vec![15, 30, 45]);
+
+    let mut volumes = vec![40, 40, 41, 41];
+    let mut average = 40u8;
+    for step in 0..5000u32 {
+      let change = [1, -1, 2, 1, -2, -1, 3, -3][(step as usize * 7 + step as usize / 11) % 8];
+      average = (i32::from(average) + change).clamp(0, 100) as u8;
+      volumes = distribute_volume(&volumes, average);
+
+      let spread = volumes.iter().max().unwrap() - volumes.iter().min().unwrap();
+      assert!(spread <= 1, "clients drifted apart at step {}: {:?}", step, volumes);
     }
   }
+
+  #[test]
+  fn rebuild_server() {
+    let state = State::default();
+    assert_eq!(state.to_server(), None);
+
+    let message: crate::Message = SERVER_STATUS.parse().unwrap();
+    state.apply_message(&message);
+    let crate::Message::Result { result, .. } = message else {
+      panic!("expected a result");
+    };
+    let SnapcastResult::ServerGetStatus(status) = *result else {
+      panic!("expected a server status");
+    };
+
+    assert_eq!(state.to_server(), Some(status.server));
+  }
+
+  #[test]
+  fn stream_progress_from_properties() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    assert_eq!(state.stream_progress("stream 1"), None);
+
+    state.apply(StateUpdate::StreamProperties {
+      id: "stream 1".to_string(),
+      properties: serde_json::from_str(r#"{"canControl":true,"canGoNext":false,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":true,"playbackStatus":"paused","position":12.0}"#).unwrap(),
+    });
+    let progress = state.stream_progress("stream 1").unwrap();
+    assert_eq!(progress.elapsed(std::time::Instant::now()), Duration::from_secs(12));
+  }
+
+  #[test]
+  fn stream_progress_keeps_receive_time() {
+    use crate::{Clock, ManualClock};
+
+    let clock = ManualClock::new();
+    let state = State::with_clock(ClockHook::new(clock.clone()));
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let properties = r#"{"canControl":true,"canGoNext":false,"canGoPrevious":false,"canPause":true,"canPlay":true,"canSeek":true,"playbackStatus":"playing","position":12.0}"#;
+    state.apply(StateUpdate::StreamProperties {
+      id: "stream 1".to_string(),
+      properties: serde_json::from_str(properties).unwrap(),
+    });
+
+    let first = state.stream_progress("stream 1").unwrap();
+    clock.advance(Duration::from_millis(20));
+    let second = state.stream_progress("stream 1").unwrap();
+    assert_eq!(first, second);
+    assert_eq!(second.elapsed(clock.now()), Duration::from_millis(12_020));
+
+    // the same properties again do not restart the extrapolation
+    state.apply(StateUpdate::StreamProperties {
+      id: "stream 1".to_string(),
+      properties: serde_json::from_str(properties).unwrap(),
+    });
+    assert_eq!(state.stream_progress("stream 1").unwrap(), first);
+  }
+
+  #[test]
+  fn state_group_to_group() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut group = state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    group.clients.insert("missing".to_string());
+    let group = group.to_group(&state.clients);
+
+    assert_eq!(group.id, "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1");
+    assert_eq!(group.clients, vec![state.client("00:21:6a:7d:74:fc").unwrap()]);
+  }
+
+  #[test]
+  fn apply_updates() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+
+    state.apply(StateUpdate::GroupName {
+      id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+      name: "Kitchen".to_string(),
+    });
+    state.apply(StateUpdate::ClientRemoved("00:21:6a:7d:74:fc".to_string()));
+
+    assert_eq!(
+      state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap().name,
+      "Kitchen"
+    );
+    assert!(state.client("00:21:6a:7d:74:fc").is_none());
+    assert!(matches!(changes.try_recv(), Ok(StateChange::GroupChanged(group)) if group.name == "Kitchen"));
+    assert!(matches!(changes.try_recv(), Ok(StateChange::GroupNameChanged { new, .. }) if new == "Kitchen"));
+    assert_eq!(
+      changes.try_recv(),
+      Ok(StateChange::ClientRemoved("00:21:6a:7d:74:fc".to_string()))
+    );
+  }
+
+  #[test]
+  fn group_client_ids_ordered() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut bathroom = state.client("00:21:6a:7d:74:fc").unwrap();
+    bathroom.id = "bathroom".to_string();
+    bathroom.config.name = "Bathroom".to_string();
+    state.clients.insert(bathroom.id.clone(), bathroom);
+    let mut group = state.groups.get_mut("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    group.clients.extend(["missing".to_string(), "bathroom".to_string()]);
+    drop(group);
+
+    assert_eq!(
+      state.group_client_ids_ordered("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1"),
+      Some(vec![
+        "bathroom".to_string(),
+        "00:21:6a:7d:74:fc".to_string(),
+        "missing".to_string()
+      ])
+    );
+    assert_eq!(state.group_client_ids_ordered("missing"), None);
+  }
+
+  #[test]
+  fn groups_using_stream() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut group = state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    group.id = "kitchen".to_string();
+    state.groups.insert(group.id.clone(), group.clone());
+    group.id = "bathroom".to_string();
+    group.stream_id = "other".to_string();
+    state.groups.insert(group.id.clone(), group);
+
+    let ids: Vec<String> = state
+      .groups_using_stream("stream 1")
+      .into_iter()
+      .map(|group| group.id)
+      .collect();
+    assert_eq!(ids, ["4dcc4e3b-c699-a04b-7f0c-8260d23c43e1", "kitchen"]);
+    assert_eq!(state.groups_using_stream("other").len(), 1);
+    assert!(state.groups_using_stream("missing").is_empty());
+  }
+
+  #[test]
+  fn client_moves_between_groups() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut server = state.to_server().unwrap();
+    let mut kitchen = server.groups[0].clone();
+    kitchen.id = "kitchen".to_string();
+    kitchen.clients.clear();
+    server.groups.push(kitchen);
+    state.apply(StateUpdate::Server(server.clone()));
+
+    let mut changes = state.subscribe();
+    let client = server.groups[0].clients.pop().unwrap();
+    server.groups[1].clients.push(client);
+    state.apply(StateUpdate::Server(server));
+
+    let mut membership = vec![];
+    while let Ok(change) = changes.try_recv() {
+      if matches!(
+        change,
+        StateChange::ClientJoinedGroup { .. } | StateChange::ClientLeftGroup { .. }
+      ) {
+        membership.push(change);
+      }
+    }
+    assert_eq!(
+      membership,
+      vec![
+        StateChange::ClientLeftGroup {
+          client: "00:21:6a:7d:74:fc".to_string(),
+          group: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+        },
+        StateChange::ClientJoinedGroup {
+          client: "00:21:6a:7d:74:fc".to_string(),
+          group: "kitchen".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn serialize_state_group_sorted() {
+    let group = StateGroup {
+      id: "group".to_string(),
+      name: "Kitchen".to_string(),
+      stream_id: "stream 1".to_string(),
+      muted: false,
+      clients: HashSet::from(["c".to_string(), "a".to_string(), "b".to_string()]),
+    };
+
+    let json = serde_json::to_string(&group).unwrap();
+    assert_eq!(
+      json,
+      r#"{"id":"group","name":"Kitchen","stream_id":"stream 1","muted":false,"clients":["a","b","c"]}"#
+    );
+    assert_eq!(serde_json::from_str::<StateGroup>(&json).unwrap(), group);
+  }
+
+  #[test]
+  fn seeded_entries_are_unconfirmed() {
+    let cached = State::default();
+    cached.apply_message(&SERVER_STATUS.parse().unwrap());
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1";
+    let client = "00:21:6a:7d:74:fc";
+
+    let state = State::default();
+    state.seed(cached.to_server().unwrap());
+    assert!(!state.is_ready());
+    assert_eq!(state.group(group), cached.group(group));
+    assert!(state.is_unconfirmed(group));
+    assert!(state.is_unconfirmed(client));
+    assert!(state.is_unconfirmed("stream 1"));
+
+    state.apply(StateUpdate::ClientName {
+      id: client.to_string(),
+      name: "Kitchen".to_string(),
+    });
+    assert!(!state.is_unconfirmed(client));
+    assert!(state.is_unconfirmed(group));
+
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    assert!(state.is_ready());
+    assert!(!state.is_unconfirmed(group));
+    assert!(!state.is_unconfirmed("stream 1"));
+  }
+
+  #[test]
+  fn overview_joins_streams() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut group = state.group("4dcc4e3b-c699-a04b-7f0c-8260d23c43e1").unwrap();
+    group.id = "kitchen".to_string();
+    group.stream_id = "missing".to_string();
+    state.groups.insert(group.id.clone(), group);
+
+    let overview = state.overview();
+    assert_eq!(overview.len(), 2);
+    assert_eq!(overview[0].group.id, "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1");
+    assert_eq!(overview[0].stream, state.stream("stream 1"));
+    assert_eq!(overview[0].now_playing, state.stream("stream 1").unwrap().now_playing());
+    assert_eq!(overview[1].group.id, "kitchen");
+    assert_eq!(overview[1].stream, None);
+    assert_eq!(overview[1].now_playing, None);
+  }
+
+  #[test]
+  fn server_update_only_touches_changes() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+    let mut server = state.to_server().unwrap();
+    let mut laptop = server.groups[0].clients[0].clone();
+    laptop.id = "laptop".to_string();
+    server.groups[0].clients.push(laptop);
+    state.apply(StateUpdate::Server(server.clone()));
+
+    let mut changes = state.subscribe();
+    let writes = state.client_writes.load(Ordering::Relaxed);
+    state.apply(StateUpdate::Server(server.clone()));
+    assert!(changes.try_recv().is_err());
+    assert_eq!(state.client_writes.load(Ordering::Relaxed), writes);
+
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string();
+    let old_volume = server.groups[0].clients[1].config.volume.clone();
+    let new_volume = ClientVolume::new(40, true);
+    server.groups[0].clients[1].config.volume = new_volume.clone();
+    server.groups[0].name = "Living Room".to_string();
+    state.apply(StateUpdate::Server(server));
+    // only the laptop changed, the entry of the other client is compared but never written
+    assert_eq!(state.client_writes.load(Ordering::Relaxed), writes + 1);
+
+    let mut received = vec![];
+    while let Ok(change) = changes.try_recv() {
+      received.push(change);
+    }
+    assert_eq!(
+      received,
+      vec![
+        StateChange::ClientChanged(state.client("laptop").unwrap()),
+        StateChange::ClientVolumeChanged {
+          id: "laptop".to_string(),
+          old: old_volume,
+          new: new_volume,
+        },
+        StateChange::GroupChanged(state.group(&group).unwrap()),
+        StateChange::GroupNameChanged {
+          id: group.clone(),
+          old: String::new(),
+          new: "Living Room".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn last_seen_updates_are_silent() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+
+    let mut server = state.to_server().unwrap();
+    server.groups[0].clients[0].last_seen.sec += 30;
+    state.apply(StateUpdate::Server(server.clone()));
+    assert!(changes.try_recv().is_err());
+    assert_eq!(
+      state.client("00:21:6a:7d:74:fc").unwrap().last_seen,
+      server.groups[0].clients[0].last_seen
+    );
+
+    server.groups[0].clients[0].last_seen.sec += 30;
+    server.groups[0].clients[0].connected = false;
+    state.apply(StateUpdate::Server(server));
+    assert!(matches!(changes.try_recv(), Ok(StateChange::ClientChanged(client)) if !client.connected));
+  }
+
+  #[test]
+  fn same_value_updates_are_silent() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+
+    state.apply_message(&r#"{"jsonrpc":"2.0","method":"Client.OnVolumeChanged","params":{"id":"00:21:6a:7d:74:fc","volume":{"muted":false,"percent":100}}}"#.parse().unwrap());
+    state.apply(StateUpdate::GroupMuted {
+      id: "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string(),
+      muted: false,
+    });
+    assert!(changes.try_recv().is_err());
+
+    let properties: StreamProperties = serde_json::from_str(r#"{"canControl":false,"canGoNext":false,"canGoPrevious":false,"canPause":false,"canPlay":false,"canSeek":false,"metadata":{"title":"Radio"}}"#).unwrap();
+    let update = || StateUpdate::StreamProperties {
+      id: "stream 1".to_string(),
+      properties: Box::new(properties.clone()),
+    };
+    state.apply(update());
+    assert!(matches!(changes.try_recv(), Ok(StateChange::StreamChanged { .. })));
+    state.apply(update());
+    assert!(changes.try_recv().is_err());
+
+    state.apply(StateUpdate::Stream {
+      id: "stream 2".to_string(),
+      stream: None,
+    });
+    while changes.try_recv().is_ok() {}
+    state.apply(StateUpdate::StreamProperties {
+      id: "stream 2".to_string(),
+      properties: Box::new(properties),
+    });
+    assert!(changes.try_recv().is_err());
+  }
+
+  #[test]
+  fn partial_updates_carry_old_values() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+    let group = "4dcc4e3b-c699-a04b-7f0c-8260d23c43e1".to_string();
+    let client = "00:21:6a:7d:74:fc".to_string();
+
+    state.apply(StateUpdate::GroupName {
+      id: group.clone(),
+      name: "Kitchen".to_string(),
+    });
+    state.apply(StateUpdate::GroupMuted {
+      id: group.clone(),
+      muted: true,
+    });
+    state.apply(StateUpdate::GroupMuted {
+      id: group.clone(),
+      muted: true,
+    });
+    state.apply(StateUpdate::GroupStream {
+      id: group.clone(),
+      stream_id: "stream 2".to_string(),
+    });
+    state.apply(StateUpdate::ClientVolume {
+      id: client.clone(),
+      volume: ClientVolume::muted(40),
+    });
+    state.apply(StateUpdate::ClientLatency {
+      id: client.clone(),
+      latency: 20,
+    });
+    state.apply(StateUpdate::ClientName {
+      id: client.clone(),
+      name: "Kitchen".to_string(),
+    });
+
+    let mut fields = vec![];
+    while let Ok(change) = changes.try_recv() {
+      if !matches!(change, StateChange::GroupChanged(_) | StateChange::ClientChanged(_)) {
+        fields.push(change);
+      }
+    }
+    assert_eq!(
+      fields,
+      vec![
+        StateChange::GroupNameChanged {
+          id: group.clone(),
+          old: "".to_string(),
+          new: "Kitchen".to_string(),
+        },
+        StateChange::GroupMuteChanged {
+          id: group.clone(),
+          old: false,
+          new: true,
+        },
+        StateChange::GroupStreamChanged {
+          id: group,
+          old: "stream 1".to_string(),
+          new: "stream 2".to_string(),
+        },
+        StateChange::ClientVolumeChanged {
+          id: client.clone(),
+          old: ClientVolume::unmuted(100),
+          new: ClientVolume::muted(40),
+        },
+        StateChange::ClientLatencyChanged {
+          id: client.clone(),
+          old: 0,
+          new: 20,
+        },
+        StateChange::ClientNameChanged {
+          id: client,
+          old: "".to_string(),
+          new: "Kitchen".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn stale_update_ignored() {
+    let state = State::default();
+    state.apply_message(&SERVER_STATUS.parse().unwrap());
+    let mut changes = state.subscribe();
+
+    state.apply(StateUpdate::ClientVolume {
+      id: "gone".to_string(),
+      volume: ClientVolume::unmuted(50),
+    });
+
+    assert!(state.client("gone").is_none());
+    assert_eq!(
+      changes.try_recv(),
+      Ok(StateChange::StaleUpdateIgnored { id: "gone".to_string() })
+    );
+    assert!(changes.try_recv().is_err());
+  }
 }
@@ -0,0 +1,145 @@
+//! recording and replaying of the raw message stream
+//!
+//! a tape is a plain text file with one message per line, formatted as
+//! `<unix timestamp in ms> <direction> <json>` where direction is `>` for outbound requests
+//! and `<` for inbound messages.
+
+use std::{
+  fmt,
+  fs::File,
+  io::{BufRead, BufReader, Write},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+  protocol::{Request, RequestMethod, SentRequests},
+  ClientError, Message, State,
+};
+
+/// The direction of a message on the wire, see [SnapcastConnectionBuilder::on_wire](crate::SnapcastConnectionBuilder::on_wire)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  /// a message received from the server
+  Inbound,
+  /// a request sent to the server
+  Outbound,
+}
+
+impl Direction {
+  fn marker(self) -> char {
+    match self {
+      Direction::Inbound => '<',
+      Direction::Outbound => '>',
+    }
+  }
+}
+
+type WireCallback = dyn Fn(Direction, &[u8]) + Send + Sync;
+
+/// callback invoked with the raw bytes of every line sent or received
+#[derive(Clone)]
+pub(crate) struct WireTap(Arc<WireCallback>);
+
+impl WireTap {
+  pub(crate) fn new(callback: impl Fn(Direction, &[u8]) + Send + Sync + 'static) -> Self {
+    Self(Arc::new(callback))
+  }
+
+  pub(crate) fn tap(&self, direction: Direction, bytes: &[u8]) {
+    (self.0)(direction, bytes);
+  }
+}
+
+impl fmt::Debug for WireTap {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("WireTap")
+  }
+}
+
+/// a tape file that raw messages are appended to
+#[derive(Debug, Clone)]
+pub(crate) struct Tape {
+  file: Arc<Mutex<File>>,
+}
+
+impl Tape {
+  pub(crate) fn create(path: PathBuf) -> Result<Self, ClientError> {
+    let file = File::create(&path)?;
+    tracing::info!("recording snapcast messages to {}", path.display());
+
+    Ok(Self {
+      file: Arc::new(Mutex::new(file)),
+    })
+  }
+
+  pub(crate) fn record(&self, direction: Direction, line: &str) {
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis())
+      .unwrap_or_default();
+    let entry = format!("{} {} {}\n", timestamp, direction.marker(), line);
+
+    let mut file = self.file.lock().expect("mutex poisoned");
+    if let Err(err) = file.write_all(entry.as_bytes()) {
+      tracing::warn!("could not write to tape: {}", err);
+    }
+  }
+}
+
+pub(crate) fn replay(path: &Path) -> Result<State, ClientError> {
+  let state = State::default();
+  let purgatory = SentRequests::new();
+  let reader = BufReader::new(File::open(path)?);
+
+  for line in reader.lines() {
+    let line = line?;
+    let mut parts = line.splitn(3, ' ');
+    let (Some(_timestamp), Some(direction), Some(message)) = (parts.next(), parts.next(), parts.next()) else {
+      continue;
+    };
+
+    match direction {
+      ">" => {
+        let request: Request = serde_json::from_str(message)?;
+        purgatory.insert(request.id, RequestMethod::from(&request.method));
+      }
+      "<" => state.apply_message(&Message::try_from((message, &purgatory))?),
+      _ => tracing::warn!("skipping malformed tape line: {:?}", line),
+    }
+  }
+
+  Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn replay_tape() {
+    let path = std::env::temp_dir().join(format!("snapcast-tape-{}", uuid::Uuid::new_v4()));
+    let tape = Tape::create(path.clone()).unwrap();
+
+    tape.record(
+      Direction::Outbound,
+      r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","method":"Client.GetStatus","params":{"id":"00:21:6a:7d:74:fc"}}"#,
+    );
+    tape.record(
+      Direction::Inbound,
+      r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","result":{"client":{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":74}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488026416,"usec":135973},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}}}"#,
+    );
+    tape.record(
+      Direction::Inbound,
+      r#"{"jsonrpc":"2.0","method":"Client.OnVolumeChanged","params":{"id":"00:21:6a:7d:74:fc","volume":{"muted":true,"percent":36}}}"#,
+    );
+
+    let state = replay(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let client = state.clients.get("00:21:6a:7d:74:fc").unwrap();
+    assert_eq!(client.config.volume.percent, 36);
+    assert!(client.config.volume.muted);
+  }
+}
@@ -0,0 +1,460 @@
+use std::{
+  fmt,
+  future::Future,
+  io,
+  net::SocketAddr,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+  task::{Context, Poll},
+  time::{Duration, Instant},
+};
+
+use stubborn_io::{
+  strategies::ExpBackoffStrategy,
+  tokio::{StubbornIo, UnderlyingIo},
+  ReconnectOptions,
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::{TcpSocket, TcpStream},
+  sync::Notify,
+};
+
+use crate::{clock::ClockHook, metrics::MetricsHook};
+
+/// A change in the status of the underlying connection to the Snapcast server
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionStatus {
+  /// the connection was established
+  Connected,
+  /// the connection was lost
+  Disconnected,
+  /// an attempt to establish the connection failed
+  ConnectFailed,
+  /// the connection was established again after it was lost, sent right after [ConnectionStatus::Connected]
+  Reconnected {
+    /// how long the connection was down, measured from [ConnectionStatus::Disconnected]
+    downtime: Duration,
+  },
+  /// reconnecting was given up because the
+  /// [reconnect attempt callback](crate::SnapcastConnectionBuilder::on_reconnect_attempt) returned
+  /// [RetryDecision::Stop], the connection ends
+  ReconnectFailed,
+}
+
+/// whether to make another attempt at reconnecting, see
+/// [SnapcastConnectionBuilder::on_reconnect_attempt](crate::SnapcastConnectionBuilder::on_reconnect_attempt)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+  /// try again after the default exponential backoff
+  Continue,
+  /// give up and end the connection with [ConnectionStatus::ReconnectFailed]
+  Stop,
+  /// try again after the given delay
+  Backoff(Duration),
+}
+
+/// callback invoked with every [ConnectionStatus] change
+#[derive(Clone)]
+pub(crate) struct StatusCallback(Arc<dyn Fn(ConnectionStatus) + Send + Sync>);
+
+impl StatusCallback {
+  pub(crate) fn new(callback: impl Fn(ConnectionStatus) + Send + Sync + 'static) -> Self {
+    Self(Arc::new(callback))
+  }
+
+  pub(crate) fn emit(&self, status: ConnectionStatus) {
+    tracing::debug!("connection status changed: {:?}", status);
+    (self.0)(status);
+  }
+}
+
+impl Default for StatusCallback {
+  fn default() -> Self {
+    Self::new(|_| {})
+  }
+}
+
+impl fmt::Debug for StatusCallback {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("StatusCallback")
+  }
+}
+
+/// decides before every reconnect attempt whether to make it, always [RetryDecision::Continue] by default
+#[derive(Clone)]
+pub(crate) struct RetryPolicy(Arc<dyn Fn(usize) -> RetryDecision + Send + Sync>);
+
+impl RetryPolicy {
+  pub(crate) fn new(policy: impl Fn(usize) -> RetryDecision + Send + Sync + 'static) -> Self {
+    Self(Arc::new(policy))
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self::new(|_| RetryDecision::Continue)
+  }
+}
+
+impl fmt::Debug for RetryPolicy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("RetryPolicy")
+  }
+}
+
+/// the delays between the reconnect attempts after one disconnect, as asked from the [RetryPolicy]
+struct RetryAttempts {
+  policy: RetryPolicy,
+  on_status: StatusHook,
+  backoff: Box<dyn Iterator<Item = Duration> + Send + Sync>,
+  attempt: usize,
+}
+
+impl Iterator for RetryAttempts {
+  type Item = Duration;
+
+  fn next(&mut self) -> Option<Duration> {
+    self.attempt += 1;
+    match (self.policy.0)(self.attempt) {
+      RetryDecision::Continue => self.backoff.next(),
+      RetryDecision::Backoff(delay) => Some(delay),
+      RetryDecision::Stop => {
+        tracing::info!("giving up reconnecting before attempt {}", self.attempt);
+        self.on_status.emit(ConnectionStatus::ReconnectFailed);
+        None
+      }
+    }
+  }
+}
+
+/// tracks status transitions and forwards them to the user's [StatusCallback]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StatusHook {
+  callback: StatusCallback,
+  connected_since: Arc<Mutex<Option<Instant>>>,
+  disconnected_at: Arc<Mutex<Option<Instant>>>,
+  ready: Arc<AtomicBool>,
+  connections: Arc<AtomicUsize>,
+  reconnected: Arc<Notify>,
+  metrics: MetricsHook,
+  clock: ClockHook,
+}
+
+impl StatusHook {
+  pub(crate) fn new(callback: StatusCallback, ready: Arc<AtomicBool>, metrics: MetricsHook, clock: ClockHook) -> Self {
+    Self {
+      callback,
+      connected_since: Arc::default(),
+      disconnected_at: Arc::default(),
+      ready,
+      connections: Arc::default(),
+      reconnected: Arc::default(),
+      metrics,
+      clock,
+    }
+  }
+
+  pub(crate) fn emit(&self, status: ConnectionStatus) {
+    let mut downtime = None;
+    {
+      let mut connected_since = self.connected_since.lock().expect("mutex poisoned");
+      let mut disconnected_at = self.disconnected_at.lock().expect("mutex poisoned");
+      match status {
+        ConnectionStatus::Connected => {
+          let now = self.clock.now();
+          *connected_since = Some(now);
+          downtime = disconnected_at.take().map(|at| now.saturating_duration_since(at));
+          self.connections.fetch_add(1, Ordering::AcqRel);
+          self.reconnected.notify_one();
+        }
+        ConnectionStatus::Disconnected => {
+          *connected_since = None;
+          *disconnected_at = Some(self.clock.now());
+          self.ready.store(false, Ordering::Release);
+        }
+        ConnectionStatus::ConnectFailed | ConnectionStatus::Reconnected { .. } | ConnectionStatus::ReconnectFailed => {}
+      }
+    }
+
+    self.callback.emit(status);
+    if let Some(downtime) = downtime {
+      self.metrics.on_reconnect();
+      self.callback.emit(ConnectionStatus::Reconnected { downtime });
+    }
+  }
+
+  pub(crate) fn connected_since(&self) -> Option<Instant> {
+    *self.connected_since.lock().expect("mutex poisoned")
+  }
+
+  /// how many times the connection has been established, including the initial connect
+  pub(crate) fn connections(&self) -> usize {
+    self.connections.load(Ordering::Acquire)
+  }
+
+  /// notified once after every [ConnectionStatus::Connected]
+  pub(crate) fn reconnected(&self) -> Arc<Notify> {
+    self.reconnected.clone()
+  }
+}
+
+/// the io the framed codec is layered on
+pub(crate) trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+pub(crate) type BoxedIo = Box<dyn Io>;
+
+/// a socks5 proxy to tunnel the connection through, see [SnapcastConnectionBuilder::proxy](crate::SnapcastConnectionBuilder::proxy)
+#[cfg(feature = "socks")]
+#[derive(Clone)]
+pub struct ProxyConfig {
+  address: SocketAddr,
+  credentials: Option<(String, String)>,
+}
+
+#[cfg(feature = "socks")]
+impl ProxyConfig {
+  /// a socks5 proxy at `address` that does not require authentication
+  pub fn socks5(address: SocketAddr) -> Self {
+    Self {
+      address,
+      credentials: None,
+    }
+  }
+
+  /// authenticate with the proxy using `username` and `password`
+  pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+    self.credentials = Some((username.into(), password.into()));
+    self
+  }
+
+  /// open the tunnel to `target` over a stream already connected to the proxy
+  async fn tunnel(&self, stream: TcpStream, target: SocketAddr) -> io::Result<TcpStream> {
+    use tokio_socks::tcp::Socks5Stream;
+
+    let tunnel = match &self.credentials {
+      Some((username, password)) => {
+        Socks5Stream::connect_with_password_and_socket(stream, target, username, password).await
+      }
+      None => Socks5Stream::connect_with_socket(stream, target).await,
+    };
+
+    tunnel.map(Socks5Stream::into_inner).map_err(io::Error::other)
+  }
+}
+
+#[cfg(feature = "socks")]
+impl fmt::Debug for ProxyConfig {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ProxyConfig")
+      .field("address", &self.address)
+      .field("username", &self.credentials.as_ref().map(|(username, _)| username))
+      .finish_non_exhaustive()
+  }
+}
+
+/// where and how to open each tcp connection, including reconnects
+#[derive(Debug, Clone)]
+pub(crate) struct TcpTarget {
+  pub(crate) address: SocketAddr,
+  pub(crate) bind_local: Option<SocketAddr>,
+  pub(crate) connect_timeout: Option<Duration>,
+  #[cfg(feature = "socks")]
+  pub(crate) proxy: Option<ProxyConfig>,
+  /// the remote address of the most recently opened stream
+  pub(crate) peer_addr: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl TcpTarget {
+  pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+    *self.peer_addr.lock().expect("mutex poisoned")
+  }
+
+  async fn connect(&self) -> io::Result<TcpStream> {
+    let stream = self.connect_with_timeout().await?;
+    *self.peer_addr.lock().expect("mutex poisoned") = stream.peer_addr().ok();
+
+    Ok(stream)
+  }
+
+  async fn connect_with_timeout(&self) -> io::Result<TcpStream> {
+    match self.connect_timeout {
+      Some(duration) => tokio::time::timeout(duration, self.open())
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out connecting to snapcast server"))?,
+      None => self.open().await,
+    }
+  }
+
+  async fn open(&self) -> io::Result<TcpStream> {
+    #[cfg(feature = "socks")]
+    if let Some(proxy) = &self.proxy {
+      let stream = self.open_to(proxy.address).await?;
+      return proxy.tunnel(stream, self.address).await;
+    }
+
+    self.open_to(self.address).await
+  }
+
+  /// open a tcp connection to `address`, from the local address to bind if any
+  async fn open_to(&self, address: SocketAddr) -> io::Result<TcpStream> {
+    let Some(local) = self.bind_local else {
+      return TcpStream::connect(address).await;
+    };
+
+    let socket = match address {
+      SocketAddr::V4(_) => TcpSocket::new_v4()?,
+      SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(local)?;
+    socket.connect(address).await
+  }
+}
+
+/// a [TcpStream] opened from a [TcpTarget], so [StubbornIo] reconnects with the same options
+pub(crate) struct TargetedTcpStream(TcpStream);
+
+impl UnderlyingIo<TcpTarget> for TargetedTcpStream {
+  fn establish(target: TcpTarget) -> Pin<Box<dyn Future<Output = io::Result<Self>> + Send>> {
+    Box::pin(async move { target.connect().await.map(Self) })
+  }
+}
+
+impl AsyncRead for TargetedTcpStream {
+  fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.0).poll_read(cx, buf)
+  }
+}
+
+impl AsyncWrite for TargetedTcpStream {
+  fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.0).poll_write(cx, buf)
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.0).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.0).poll_shutdown(cx)
+  }
+}
+
+/// options for establishing the underlying connection
+#[derive(Debug, Clone)]
+pub(crate) struct TransportOptions {
+  pub(crate) target: TcpTarget,
+  pub(crate) reconnect: bool,
+  pub(crate) retry: RetryPolicy,
+  pub(crate) on_status: StatusHook,
+}
+
+impl TransportOptions {
+  /// use a stream that was connected by the caller, which is never reconnected
+  pub(crate) fn adopt(&self, stream: TcpStream) -> BoxedIo {
+    tracing::info!("taking over connection to snapcast server at {}", self.target.address);
+
+    *self.target.peer_addr.lock().expect("mutex poisoned") = stream.peer_addr().ok();
+    self.on_status.emit(ConnectionStatus::Connected);
+    Box::new(stream)
+  }
+
+  pub(crate) async fn connect(&self) -> io::Result<BoxedIo> {
+    self.establish(true).await
+  }
+
+  /// open a new connection without emitting [ConnectionStatus::Connected] once it is established, for replacing a
+  /// connection that is still in use. later reconnects of the new connection are reported as usual.
+  pub(crate) async fn connect_quietly(&self) -> io::Result<BoxedIo> {
+    self.establish(false).await
+  }
+
+  async fn establish(&self, announce: bool) -> io::Result<BoxedIo> {
+    tracing::info!("connecting to snapcast server at {}", self.target.address);
+
+    if self.reconnect {
+      let (on_connect, on_disconnect, on_connect_fail) =
+        (self.on_status.clone(), self.on_status.clone(), self.on_status.clone());
+      let quiet = Arc::new(AtomicBool::new(!announce));
+      let (policy, on_give_up) = (self.retry.clone(), self.on_status.clone());
+      let options = ReconnectOptions::new()
+        .with_retries_generator(move || RetryAttempts {
+          policy: policy.clone(),
+          on_status: on_give_up.clone(),
+          backoff: Box::new(ExpBackoffStrategy::default().into_iter()),
+          attempt: 0,
+        })
+        .with_on_connect_callback(move || {
+          if !quiet.swap(false, Ordering::AcqRel) {
+            on_connect.emit(ConnectionStatus::Connected);
+          }
+        })
+        .with_on_disconnect_callback(move || on_disconnect.emit(ConnectionStatus::Disconnected))
+        .with_on_connect_fail_callback(move || on_connect_fail.emit(ConnectionStatus::ConnectFailed));
+
+      let stream: StubbornIo<TargetedTcpStream, TcpTarget> =
+        StubbornIo::connect_with_options(self.target.clone(), options).await?;
+      Ok(Box::new(stream))
+    } else {
+      match self.target.connect().await {
+        Ok(stream) => {
+          if announce {
+            self.on_status.emit(ConnectionStatus::Connected);
+          }
+          Ok(Box::new(stream))
+        }
+        Err(err) => {
+          self.on_status.emit(ConnectionStatus::ConnectFailed);
+          Err(err)
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reconnected_after_disconnect() {
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let recorded = statuses.clone();
+    let clock = crate::ManualClock::new();
+    let hook = StatusHook::new(
+      StatusCallback::new(move |status| recorded.lock().unwrap().push(status)),
+      Arc::default(),
+      MetricsHook::default(),
+      ClockHook::new(clock.clone()),
+    );
+
+    hook.emit(ConnectionStatus::Connected);
+    hook.emit(ConnectionStatus::Disconnected);
+    hook.emit(ConnectionStatus::ConnectFailed);
+    clock.advance(Duration::from_secs(3));
+    hook.emit(ConnectionStatus::Connected);
+
+    let statuses = statuses.lock().unwrap();
+    assert_eq!(
+      statuses[..4],
+      [
+        ConnectionStatus::Connected,
+        ConnectionStatus::Disconnected,
+        ConnectionStatus::ConnectFailed,
+        ConnectionStatus::Connected
+      ]
+    );
+    assert_eq!(
+      statuses[4],
+      ConnectionStatus::Reconnected {
+        downtime: Duration::from_secs(3)
+      }
+    );
+    assert_eq!(statuses.len(), 5);
+    assert_eq!(hook.connections(), 2);
+  }
+}
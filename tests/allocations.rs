@@ -0,0 +1,76 @@
+//! counts allocations with a global allocator, in its own binary so the unit tests keep the system allocator
+
+use snapcast_control::{Message, SnapcastResult, State};
+
+const SERVER_STATUS: &str = r#"{"id":"00000000-0000-0000-0000-000000000000","jsonrpc":"2.0","result":{"server":{"groups":[{"clients":[{"config":{"instance":1,"latency":0,"name":"","volume":{"muted":false,"percent":100}},"connected":true,"host":{"arch":"x86_64","ip":"127.0.0.1","mac":"00:21:6a:7d:74:fc","name":"T400","os":"Linux Mint 17.3 Rosa"},"id":"00:21:6a:7d:74:fc","lastSeen":{"sec":1488025905,"usec":45238},"snapclient":{"name":"Snapclient","protocolVersion":2,"version":"0.10.0"}}],"id":"4dcc4e3b-c699-a04b-7f0c-8260d23c43e1","muted":false,"name":"","stream_id":"stream 1"}],"server":{"host":{"arch":"x86_64","ip":"","mac":"","name":"T400","os":"Linux Mint 17.3 Rosa"},"snapserver":{"controlProtocolVersion":1,"name":"Snapserver","protocolVersion":1,"version":"0.10.0"}},"streams":[{"id":"stream 1","status":"idle","uri":{"fragment":"","host":"","path":"/tmp/snapfifo","query":{"name":"stream 1"},"raw":"pipe:///tmp/snapfifo?name=stream 1","scheme":"pipe"}}]}}}"#;
+
+/// counts the allocations of the current thread while [count_allocations] runs
+struct CountingAllocator;
+
+thread_local! {
+  static ALLOCATIONS: std::cell::Cell<Option<usize>> = const { std::cell::Cell::new(None) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|n| n + 1)));
+    unsafe { std::alloc::System.alloc(layout) }
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+    unsafe { std::alloc::System.dealloc(ptr, layout) }
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+  ALLOCATIONS.with(|count| count.set(Some(0)));
+  f();
+  ALLOCATIONS.with(|count| count.take()).unwrap()
+}
+
+#[test]
+fn results_are_applied_without_cloning() {
+  let state = State::default();
+  state.apply_message(&SERVER_STATUS.parse().unwrap());
+
+  // four groups of four clients, each group playing its own stream
+  let mut server = state.to_server().unwrap();
+  let (group, stream) = (server.groups.remove(0), server.streams.remove(0));
+  for g in 0..4 {
+    let mut group = group.clone();
+    group.id = format!("group {}", g);
+    group.stream_id = format!("stream {}", g);
+    let client = group.clients.remove(0);
+    for c in 0..4 {
+      let mut client = client.clone();
+      client.id = format!("client {}.{}", g, c);
+      group.clients.push(client);
+    }
+    server.groups.push(group);
+
+    let mut stream = stream.clone();
+    stream.id = format!("stream {}", g);
+    server.streams.push(stream);
+  }
+  let result = Message::Result {
+    id: uuid::Uuid::nil(),
+    jsonrpc: snapcast_control::JsonRpcVersion,
+    result: Box::new(SnapcastResult::ServerGetStatus(
+      snapcast_control::server::GetStatusResult { server },
+    )),
+    method: None,
+  };
+  state.apply_message(&result);
+
+  let cloning = count_allocations(|| drop(result.clone()));
+  let applying = count_allocations(|| state.apply_message(&result));
+  assert!(
+    applying < cloning / 10,
+    "applying an unchanged status allocated {} times, cloning it {} times",
+    applying,
+    cloning
+  );
+}